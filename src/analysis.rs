@@ -0,0 +1,326 @@
+// Static analysis of a ROM image
+//
+// Follows control flow from the entry point (recursive descent) to separate code
+// from data, and records every reference between addresses on the way.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use chip8_core::decode::{decode, Instruction};
+use chip8_core::quirks::Quirks;
+use chip8_core::{is_hires_rom, HIRES_START_ADDRESS, START_ADDRESS};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RefKind {
+    Call,
+    Jump,
+    // JP V0, addr: only the base address is known statically
+    ComputedJump,
+    // LD I, addr
+    Index,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Reference {
+    pub from: u16,
+    pub kind: RefKind,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EdgeKind {
+    Fallthrough,
+    Jump,
+    ComputedJump,
+    Call,
+    // Where a skip lands when its condition holds
+    Skip,
+}
+
+// A straight-line run of instructions with a single entry at `start`
+pub struct Block {
+    pub start: u16,
+    // First address after the block's last instruction, which is 0x10000 for
+    // one that runs to the top of XO-CHIP memory
+    pub end: usize,
+    pub edges: Vec<(u16, EdgeKind)>,
+}
+
+pub struct Analysis {
+    pub rom: Vec<u8>,
+    pub entry: u16,
+    pub hires: bool,
+    // Start addresses of everything reached as an instruction
+    pub code: BTreeSet<u16>,
+    // Incoming references, by target address
+    pub refs: BTreeMap<u16, Vec<Reference>>,
+}
+
+impl Analysis {
+    pub fn new(rom: &[u8]) -> Analysis {
+        let hires = is_hires_rom(rom);
+        let entry = if hires { HIRES_START_ADDRESS } else { START_ADDRESS };
+        let mut analysis = Analysis {
+            rom: rom.to_vec(),
+            entry,
+            hires,
+            code: BTreeSet::new(),
+            refs: BTreeMap::new(),
+        };
+        analysis.trace();
+        analysis
+    }
+
+    // First address past the ROM image, past 0xFFFF for one filling XO-CHIP
+    // memory
+    pub fn end(&self) -> usize {
+        START_ADDRESS as usize + self.rom.len()
+    }
+
+    pub fn byte(&self, addr: u16) -> Option<u8> {
+        self.rom.get(addr.checked_sub(START_ADDRESS)? as usize).copied()
+    }
+
+    pub fn opcode(&self, addr: u16) -> Option<u16> {
+        Some(((self.byte(addr)? as u16) << 8) | self.byte(addr.checked_add(1)?)? as u16)
+    }
+
+    pub fn instruction(&self, addr: u16) -> Option<Instruction> {
+        Some(decode(self.opcode(addr)?, self.hires))
+    }
+
+    // Control flow and data references leaving the instruction at `addr`
+    pub fn outgoing(&self, addr: u16) -> Vec<(u16, RefKind)> {
+        match self.instruction(addr) {
+            Some(Instruction::Jp(target)) => vec![(target, RefKind::Jump)],
+            Some(Instruction::Call(target)) => vec![(target, RefKind::Call)],
+            Some(Instruction::JpV0(target)) => vec![(target, RefKind::ComputedJump)],
+            Some(Instruction::LdI(target)) => vec![(target, RefKind::Index)],
+            Some(Instruction::LdILong) => addr.checked_add(2).and_then(|addr| self.opcode(addr)).map(|target| (target, RefKind::Index)).into_iter().collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    // Address of the instruction after the one at `addr`. Like the PC, it
+    // wraps at the top of memory.
+    fn next(&self, addr: u16) -> u16 {
+        addr.wrapping_add(self.instruction(addr).map_or(2, |i| i.size()))
+    }
+
+    // Addresses execution may continue at after `addr`
+    fn successors(&self, addr: u16, instruction: Instruction) -> Vec<u16> {
+        let next = addr.wrapping_add(instruction.size());
+        match instruction {
+            Instruction::Jp(target) | Instruction::JpV0(target) => vec![target],
+            Instruction::Call(target) => vec![target, next],
+            Instruction::Ret | Instruction::Exit => Vec::new(),
+            // XO-CHIP skips step over a whole F000
+            _ if instruction.is_skip() => vec![next, self.next(next)],
+            _ => vec![next],
+        }
+    }
+
+    fn trace(&mut self) {
+        let mut pending = vec![self.entry];
+
+        while let Some(addr) = pending.pop() {
+            if self.code.contains(&addr) {
+                continue;
+            }
+            let instruction = match self.instruction(addr) {
+                // Falling into something undecodable means we've run into data
+                Some(Instruction::Unknown(_)) | None => continue,
+                Some(instruction) => instruction,
+            };
+            self.code.insert(addr);
+
+            for (target, kind) in self.outgoing(addr) {
+                self.refs.entry(target).or_default().push(Reference { from: addr, kind });
+            }
+            pending.extend(self.successors(addr, instruction));
+        }
+
+        for refs in self.refs.values_mut() {
+            refs.sort_by_key(|r| r.from);
+        }
+    }
+
+    // Splits the reached code into basic blocks
+    pub fn blocks(&self) -> Vec<Block> {
+        // Every branch target and every instruction after a branch starts a block
+        let mut leaders: BTreeSet<u16> = BTreeSet::new();
+        leaders.insert(self.entry);
+        for &addr in &self.code {
+            let instruction = self.instruction(addr).unwrap();
+            let branches = !matches!(self.successors(addr, instruction).as_slice(), [next] if *next == addr.wrapping_add(instruction.size()));
+            if branches {
+                leaders.extend(self.successors(addr, instruction));
+            }
+        }
+
+        let mut blocks = Vec::new();
+        for &start in leaders.iter().filter(|a| self.code.contains(a)) {
+            let mut addr = start;
+            loop {
+                let instruction = self.instruction(addr).unwrap();
+                let next = addr.wrapping_add(instruction.size());
+                let edges = match instruction {
+                    Instruction::Jp(target) => vec![(target, EdgeKind::Jump)],
+                    Instruction::JpV0(target) => vec![(target, EdgeKind::ComputedJump)],
+                    Instruction::Call(target) => vec![(target, EdgeKind::Call), (next, EdgeKind::Fallthrough)],
+                    Instruction::Ret | Instruction::Exit => Vec::new(),
+                    _ if instruction.is_skip() => vec![(next, EdgeKind::Fallthrough), (self.next(next), EdgeKind::Skip)],
+                    _ if leaders.contains(&next) || !self.code.contains(&next) => vec![(next, EdgeKind::Fallthrough)],
+                    _ => {
+                        addr = next;
+                        continue;
+                    }
+                };
+                // Drop edges into addresses that were never decoded (past the end of the ROM)
+                let edges = edges.into_iter().filter(|(target, _)| self.code.contains(target)).collect();
+                blocks.push(Block { start, end: addr as usize + instruction.size() as usize, edges });
+                break;
+            }
+        }
+        blocks
+    }
+
+    // Whether any reachable instruction needs SCHIP
+    pub fn uses_schip(&self) -> bool {
+        self.code.iter().any(|&addr| self.instruction(addr).is_some_and(|i| i.is_schip()))
+    }
+
+    // Whether any reachable instruction needs XO-CHIP
+    pub fn uses_xochip(&self) -> bool {
+        self.code.iter().any(|&addr| self.instruction(addr).is_some_and(|i| i.is_xochip()))
+    }
+
+    // SCHIP, XO-CHIP and the quirks to run the ROM with, where they aren't given
+    pub fn mode(&self, schip: Option<bool>, xochip: Option<bool>, quirks: Option<Quirks>) -> (bool, bool, Quirks) {
+        let xochip = xochip.unwrap_or_else(|| self.uses_xochip());
+        let schip = xochip || schip.unwrap_or_else(|| self.uses_schip());
+        let quirks = quirks.unwrap_or_else(|| Quirks::for_mode(schip, xochip));
+        (schip, xochip, quirks)
+    }
+
+    pub fn incoming(&self, addr: u16) -> &[Reference] {
+        self.refs.get(&addr).map(|r| r.as_slice()).unwrap_or(&[])
+    }
+
+    // Label for a referenced address, named after how it's used
+    pub fn label(&self, addr: u16) -> Option<String> {
+        let refs = self.incoming(addr);
+        let has = |kind| refs.iter().any(|r| r.kind == kind);
+        if has(RefKind::Call) {
+            Some(format!("sub_{:03X}", addr))
+        } else if has(RefKind::Jump) || has(RefKind::ComputedJump) {
+            Some(format!("L_{:03X}", addr))
+        } else if has(RefKind::Index) {
+            Some(format!("data_{:03X}", addr))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //   200  LD V0, 0x01
+    //   202  SE V0, 0x01
+    //   204  JP 0x20A
+    //   206  CALL 0x210
+    //   208  JP 0x208
+    //   20A  RET
+    //   20C  data
+    //   210  RET
+    const ROM: &[u8] = &[
+        0x60, 0x01, 0x30, 0x01, 0x12, 0x0A, 0x22, 0x10, 0x12, 0x08, 0x00, 0xEE,
+        0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0xEE,
+    ];
+
+    fn successors(analysis: &Analysis, addr: u16) -> Vec<u16> {
+        analysis.successors(addr, analysis.instruction(addr).unwrap())
+    }
+
+    #[test]
+    fn separates_code_from_data() {
+        let analysis = Analysis::new(ROM);
+        let code: Vec<u16> = analysis.code.iter().copied().collect();
+        assert_eq!(code, [0x200, 0x202, 0x204, 0x206, 0x208, 0x20A, 0x210]);
+        assert_eq!(analysis.end(), 0x212);
+    }
+
+    #[test]
+    fn follows_branches() {
+        let analysis = Analysis::new(ROM);
+        assert_eq!(successors(&analysis, 0x200), [0x202]);
+        assert_eq!(successors(&analysis, 0x202), [0x204, 0x206]);
+        assert_eq!(successors(&analysis, 0x204), [0x20A]);
+        assert_eq!(successors(&analysis, 0x206), [0x210, 0x208]);
+        assert_eq!(successors(&analysis, 0x208), [0x208]);
+        assert!(successors(&analysis, 0x20A).is_empty());
+    }
+
+    #[test]
+    fn skips_step_over_long_loads() {
+        // SE V0, 0x00 then F000 0300 and CLS
+        let analysis = Analysis::new(&[0x30, 0x00, 0xF0, 0x00, 0x03, 0x00, 0x00, 0xE0]);
+        assert_eq!(successors(&analysis, 0x200), [0x202, 0x206]);
+        assert_eq!(analysis.outgoing(0x202), [(0x300, RefKind::Index)]);
+    }
+
+    #[test]
+    fn records_references() {
+        let analysis = Analysis::new(ROM);
+        assert_eq!(analysis.incoming(0x210), [Reference { from: 0x206, kind: RefKind::Call }]);
+        assert_eq!(analysis.incoming(0x208), [Reference { from: 0x208, kind: RefKind::Jump }]);
+        assert_eq!(analysis.label(0x210).as_deref(), Some("sub_210"));
+        assert_eq!(analysis.label(0x20A).as_deref(), Some("L_20A"));
+        assert_eq!(analysis.label(0x200), None);
+    }
+
+    #[test]
+    fn splits_basic_blocks() {
+        let blocks = Analysis::new(ROM).blocks()
+            .into_iter()
+            .map(|block| (block.start, block.end, block.edges))
+            .collect::<Vec<_>>();
+        assert_eq!(blocks, [
+            (0x200, 0x204, vec![(0x204, EdgeKind::Fallthrough), (0x206, EdgeKind::Skip)]),
+            (0x204, 0x206, vec![(0x20A, EdgeKind::Jump)]),
+            (0x206, 0x208, vec![(0x210, EdgeKind::Call), (0x208, EdgeKind::Fallthrough)]),
+            (0x208, 0x20A, vec![(0x208, EdgeKind::Jump)]),
+            (0x20A, 0x20C, vec![]),
+            (0x210, 0x212, vec![]),
+        ]);
+    }
+
+    #[test]
+    fn runs_to_the_top_of_memory() {
+        // SYS 0x000 all the way up to an F000 in the last four bytes
+        let mut rom = vec![0; chip8_core::MAX_ROM_SIZE];
+        let len = rom.len();
+        rom[len - 4..].copy_from_slice(&[0xF0, 0x00, 0xAB, 0xCD]);
+        let analysis = Analysis::new(&rom);
+        assert_eq!(analysis.end(), 0x10000);
+        assert!(analysis.code.contains(&0xFFFC));
+        assert_eq!(analysis.opcode(0xFFFF), None);
+        assert_eq!(analysis.incoming(0xABCD), [Reference { from: 0xFFFC, kind: RefKind::Index }]);
+        assert_eq!(analysis.blocks().last().map(|block| block.end), Some(0x10000));
+    }
+
+    #[test]
+    fn detects_the_mode() {
+        assert_eq!(Analysis::new(ROM).mode(None, None, None), (false, false, Quirks::default()));
+        // HIGH, then F002
+        let analysis = Analysis::new(&[0x00, 0xFF, 0xF0, 0x02]);
+        assert!(analysis.uses_schip() && analysis.uses_xochip());
+        assert_eq!(analysis.mode(None, None, None), (true, true, Quirks::preset("xochip").unwrap()));
+        assert_eq!(analysis.mode(Some(false), Some(false), None), (false, false, Quirks::default()));
+
+        // SCHIP without XO-CHIP gets the SCHIP quirks, unless others are given
+        let analysis = Analysis::new(&[0x00, 0xFF, 0x12, 0x02]);
+        assert_eq!(analysis.mode(None, None, None), (true, false, Quirks::preset("schip").unwrap()));
+        assert_eq!(analysis.mode(Some(true), None, Some(Quirks::default())), (true, false, Quirks::default()));
+    }
+}
@@ -0,0 +1,106 @@
+// Buzzer output
+//
+// A square wave played through SDL while the sound timer runs. It goes to the
+// system's default output unless `--audio-device NAME` (or `audio_device` in
+// the config file) picks one of the devices `--list-audio-devices` prints.
+// `--tone HZ` and `--volume PERCENT` change the sound, `--mute` leaves audio
+// off altogether.
+
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::{AudioSubsystem, Sdl};
+
+const SAMPLE_RATE: i32 = 44100;
+pub const DEFAULT_TONE_HZ: u32 = 440;
+pub const DEFAULT_VOLUME: u32 = 25;
+
+struct SquareWave {
+    // Fraction of a period per sample
+    step: f32,
+    phase: f32,
+    // Amplitude from 0 to 1
+    volume: f32,
+    // An XO-CHIP audio pattern playing instead of the tone, with the fraction
+    // of it to step through per sample
+    pattern: Option<([u8; 16], f32)>,
+    sample_rate: f32,
+}
+
+impl AudioCallback for SquareWave {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        for sample in out.iter_mut() {
+            let (high, step) = match &self.pattern {
+                Some((bits, step)) => {
+                    let bit = (self.phase * 128.0) as usize % 128;
+                    (bits[bit / 8] & (0x80 >> (bit % 8)) != 0, *step)
+                }
+                None => (self.phase < 0.5, self.step),
+            };
+            *sample = if high { self.volume } else { -self.volume };
+            self.phase = (self.phase + step) % 1.0;
+        }
+    }
+}
+
+// SDL reports no audio driver at all with an empty message
+pub fn subsystem(sdl: &Sdl) -> Result<AudioSubsystem, String> {
+    sdl.audio().map_err(|e| if e.is_empty() { "no audio driver available".to_string() } else { e })
+}
+
+// Names of the playback devices, as `--audio-device` takes them
+pub fn device_names(audio: &AudioSubsystem) -> Result<Vec<String>, String> {
+    let count = audio.num_audio_playback_devices().ok_or("can't list audio devices")?;
+    (0..count).map(|i| audio.audio_playback_device_name(i)).collect()
+}
+
+pub struct Buzzer {
+    device: AudioDevice<SquareWave>,
+    on: bool,
+    pattern: Option<([u8; 16], f32)>,
+}
+
+impl Buzzer {
+    // Opens `device` by name, or the default output, for a `tone_hz` tone at
+    // `volume` percent
+    pub fn open(audio: &AudioSubsystem, device: Option<&str>, tone_hz: u32, volume: u32) -> Result<Buzzer, String> {
+        if let Some(name) = device {
+            if !device_names(audio)?.iter().any(|n| n == name) {
+                return Err(format!("no audio device `{}`, see --list-audio-devices", name));
+            }
+        }
+        let desired = AudioSpecDesired { freq: Some(SAMPLE_RATE), channels: Some(1), samples: None };
+        let device = audio.open_playback(device, &desired, |spec| SquareWave {
+            step: tone_hz as f32 / spec.freq as f32,
+            phase: 0.0,
+            volume: volume.min(100) as f32 / 100.0,
+            pattern: None,
+            sample_rate: spec.freq as f32,
+        })?;
+        Ok(Buzzer { device, on: false, pattern: None })
+    }
+
+    // Plays `pattern` (Chip8::audio_pattern) rather than the tone, or the tone
+    // again with None
+    pub fn set_pattern(&mut self, pattern: Option<(&[u8; 16], f32)>) {
+        let pattern = pattern.map(|(bits, rate)| (*bits, rate));
+        if pattern == self.pattern {
+            return;
+        }
+        self.pattern = pattern;
+        let mut wave = self.device.lock();
+        wave.pattern = pattern.map(|(bits, rate)| (bits, rate / 128.0 / wave.sample_rate));
+    }
+
+    pub fn set(&mut self, on: bool) {
+        if on == self.on {
+            return;
+        }
+        self.on = on;
+        if on {
+            self.device.resume();
+        } else {
+            self.device.pause();
+        }
+    }
+}
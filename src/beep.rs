@@ -0,0 +1,32 @@
+// Handling of very short beeps
+//
+// Interpreters disagree on what a tiny sound timer value does. The COSMAC VIP
+// doesn't sound at all for LD ST with a value of 1, others beep for a single
+// frame, and some round short beeps up to something audible. Both are applied
+// when the sound timer is written.
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Beep {
+    // Sound timer values below this make no sound
+    pub min_audible: u8,
+    // Audible values shorter than this are lengthened to it
+    pub min_frames: u8,
+}
+
+impl Default for Beep {
+    // Every non-zero value beeps for exactly its length
+    fn default() -> Beep {
+        Beep { min_audible: 1, min_frames: 0 }
+    }
+}
+
+impl Beep {
+    // Sound timer value to use for a write of `value`
+    pub fn adjust(&self, value: u8) -> u8 {
+        if value < self.min_audible {
+            0
+        } else {
+            value.max(self.min_frames)
+        }
+    }
+}
@@ -0,0 +1,128 @@
+// Spectator broadcast over WebSocket
+//
+// `--broadcast ADDR` accepts any number of WebSocket viewers while the game is
+// played locally. Every change to the display, and to the buzzer, is sent to all
+// of them as a binary message; viewers joining late get the current frame first.
+//
+//   'F' width:u8 height:u8 rle...   display, 1 bit per pixel, rows packed MSB first
+//                                   then run-length coded as (count, byte) pairs
+//   'A' on:u8                       buzzer switched on (1) or off (0)
+//
+// Sockets are written from a thread of their own so a slow viewer can't hold up
+// emulation.
+
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+
+use tungstenite::{Message, WebSocket};
+
+enum Event {
+    Viewer(Box<WebSocket<TcpStream>>),
+    Frame(Vec<u8>),
+    Sound(bool),
+}
+
+pub struct Broadcaster {
+    events: Sender<Event>,
+    last_frame: Vec<u8>,
+    sounding: bool,
+}
+
+// 1 bit per pixel, then (count, byte) runs
+fn encode_frame(width: u32, height: u32, video: &[u32]) -> Vec<u8> {
+    let mut packed = Vec::with_capacity(video.len() / 8);
+    for row in video.chunks(width as usize).take(height as usize) {
+        for pixels in row.chunks(8) {
+            packed.push(pixels.iter().enumerate().fold(0, |byte, (bit, &p)| byte | (((p != 0) as u8) << (7 - bit))));
+        }
+    }
+
+    let mut out = vec![b'F', width as u8, height as u8];
+    let mut bytes = packed.iter().peekable();
+    while let Some(&byte) = bytes.next() {
+        let mut count = 1u8;
+        while count < u8::MAX && bytes.next_if(|&&b| b == byte).is_some() {
+            count += 1;
+        }
+        out.extend_from_slice(&[count, byte]);
+    }
+    out
+}
+
+fn sound_message(on: bool) -> Vec<u8> {
+    vec![b'A', on as u8]
+}
+
+impl Broadcaster {
+    pub fn start(addr: &str) -> io::Result<Broadcaster> {
+        let listener = TcpListener::bind(addr)?;
+        let (tx, rx) = mpsc::channel();
+
+        let viewers = tx.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                stream.set_nodelay(true).ok();
+                // A failed handshake only loses that one viewer
+                if let Ok(socket) = tungstenite::accept(stream) {
+                    if viewers.send(Event::Viewer(Box::new(socket))).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+
+        thread::spawn(move || {
+            let mut sockets: Vec<WebSocket<TcpStream>> = Vec::new();
+            let mut frame: Option<Vec<u8>> = None;
+            let mut sounding = false;
+
+            for event in rx {
+                let message = match event {
+                    Event::Viewer(mut socket) => {
+                        let mut catch_up: Vec<Vec<u8>> = frame.iter().cloned().collect();
+                        catch_up.push(sound_message(sounding));
+                        if catch_up.into_iter().all(|m| socket.send(Message::binary(m)).is_ok()) {
+                            sockets.push(*socket);
+                            eprintln!("Viewer connected ({} watching)", sockets.len());
+                        }
+                        continue;
+                    }
+                    Event::Frame(message) => {
+                        frame = Some(message.clone());
+                        message
+                    }
+                    Event::Sound(on) => {
+                        sounding = on;
+                        sound_message(on)
+                    }
+                };
+
+                let before = sockets.len();
+                sockets.retain_mut(|socket| socket.send(Message::binary(message.clone())).is_ok());
+                if sockets.len() < before {
+                    eprintln!("Viewer disconnected ({} watching)", sockets.len());
+                }
+            }
+        });
+
+        Ok(Broadcaster { events: tx, last_frame: Vec::new(), sounding: false })
+    }
+
+    // Sends the display if it changed since the last call
+    pub fn frame(&mut self, width: u32, height: u32, video: &[u32]) {
+        let message = encode_frame(width, height, video);
+        if message != self.last_frame {
+            self.last_frame = message.clone();
+            self.events.send(Event::Frame(message)).ok();
+        }
+    }
+
+    pub fn sound(&mut self, on: bool) {
+        if on != self.sounding {
+            self.sounding = on;
+            self.events.send(Event::Sound(on)).ok();
+        }
+    }
+}
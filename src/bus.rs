@@ -0,0 +1,35 @@
+// Memory-mapped peripherals
+//
+// Loads and stores made by instructions (sprite data, BCD, register dumps and
+// loads) go through the bus, where a peripheral can answer reads and take writes
+// for addresses it maps. Instruction fetch and the interpreter's own setup of
+// memory bypass it. Clones of a machine share its peripherals.
+
+use std::sync::Arc;
+
+use crate::Chip8;
+
+pub trait Peripheral: Send + Sync {
+    // The value at `addr`, or None to leave it to RAM
+    fn read(&self, addr: u16) -> Option<u8>;
+    // Whether the write was taken; RAM is only written when it wasn't
+    fn write(&self, addr: u16, value: u8) -> bool;
+}
+
+pub type SharedPeripheral = Arc<dyn Peripheral>;
+
+impl Chip8 {
+    pub fn read(&self, addr: u16) -> u8 {
+        let addr = addr & self.address_mask();
+        self.peripheral.as_ref()
+            .and_then(|p| p.read(addr))
+            .unwrap_or(self.memory[addr as usize])
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        let addr = addr & self.address_mask();
+        if !self.peripheral.as_ref().is_some_and(|p| p.write(addr, value)) {
+            self.memory[addr as usize] = value;
+        }
+    }
+}
@@ -0,0 +1,287 @@
+// Regression fixtures
+//
+// `--capture FILE` records a live session as a fixture: the ROM, where the run
+// started from (an RNG seed, or a save state when it started from one), the
+// keypad and the bytes frozen in the debugger on every cycle they changed, and
+// a hash of the final machine state.
+// `verify` replays fixtures headlessly and fails if the state comes out any
+// different, so a bug that was fixed stays fixed.
+//
+//   rom_data = "<hex>"
+//   seed = 1234               # or state = "<hex>"
+//   cycles = 5120             # instructions
+//   ips = 700                 # with --ips, otherwise a timer tick every cycle
+//   inputs = [[0, 0], [310, 32], [318, 0]]    # cycle, keypad bitmask (bit n = key n)
+//   freezes = [[200, [[752, 3]]], [900, []]]  # cycle, every frozen [address, value]
+//   hash = "0x3f1c0e56d8a2b7e4"
+//   schip = false             # whether the SCHIP instructions were on
+//   cdp1802 = false           # whether 0NNN machine code ran, with --cdp1802
+//   no_hires = false          # with --no-hires
+//   min_audible = 1           # --min-audible and --min-beep, see chip8_core::beep
+//   min_beep = 0
+//   quirks = ["wrap"]         # the quirks that were on, see quirks.rs
+
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::session::{from_hex, to_hex};
+use crate::cheats::Freezes;
+use chip8_core::beep::Beep;
+use chip8_core::timing::Timing;
+use chip8_core::quirks::Quirks;
+use chip8_core::Chip8;
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Fixture {
+    rom_data: String,
+    seed: Option<u64>,
+    state: Option<String>,
+    cycles: u64,
+    #[serde(default)]
+    ips: Option<u32>,
+    inputs: Vec<(u64, u16)>,
+    #[serde(default)]
+    freezes: Vec<(u64, Vec<(u16, u8)>)>,
+    hash: String,
+    #[serde(default)]
+    schip: bool,
+    #[serde(default)]
+    xochip: bool,
+    #[serde(default)]
+    cdp1802: bool,
+    #[serde(default)]
+    no_hires: bool,
+    #[serde(default = "default_min_audible")]
+    min_audible: u8,
+    #[serde(default)]
+    min_beep: u8,
+    #[serde(default)]
+    quirks: Vec<String>,
+}
+
+fn default_min_audible() -> u8 {
+    Beep::default().min_audible
+}
+
+pub fn keypad_mask(keypad: &[u8; 16]) -> u16 {
+    keypad.iter().enumerate().fold(0, |mask, (key, &down)| mask | (((down != 0) as u16) << key))
+}
+
+pub fn set_keypad(keypad: &mut [u8; 16], mask: u16) {
+    for (key, down) in keypad.iter_mut().enumerate() {
+        *down = ((mask >> key) & 1) as u8;
+    }
+}
+
+pub struct Capture {
+    seed: Option<u64>,
+    state: Option<Vec<u8>>,
+    cycles: u64,
+    ips: Option<u32>,
+    inputs: Vec<(u64, u16)>,
+    freezes: Vec<(u64, Vec<(u16, u8)>)>,
+}
+
+impl Capture {
+    // A run starting from a freshly loaded ROM with the RNG seeded with `seed`,
+    // at `ips` instructions a second (see chip8_core::timing) or one per timer tick
+    pub fn from_seed(seed: u64, ips: Option<u32>) -> Capture {
+        Capture { seed: Some(seed), state: None, cycles: 0, ips, inputs: Vec::new(), freezes: Vec::new() }
+    }
+
+    // A run starting from an arbitrary machine state
+    pub fn from_state(chip8: &Chip8, ips: Option<u32>) -> Capture {
+        Capture { seed: None, state: Some(chip8.save_state()), cycles: 0, ips, inputs: Vec::new(), freezes: Vec::new() }
+    }
+
+    // Call right before every instruction, with the bytes frozen for it
+    pub fn record(&mut self, keypad: &[u8; 16], frozen: &[(u16, u8)]) {
+        let mask = keypad_mask(keypad);
+        if self.inputs.last().is_none_or(|&(_, last)| last != mask) {
+            self.inputs.push((self.cycles, mask));
+        }
+        let last = self.freezes.last().map_or(&[][..], |(_, last)| last.as_slice());
+        if last != frozen {
+            self.freezes.push((self.cycles, frozen.to_vec()));
+        }
+        self.cycles += 1;
+    }
+
+    pub fn finish(self, rom: &[u8], chip8: &Chip8) -> Fixture {
+        Fixture {
+            rom_data: to_hex(rom),
+            seed: self.seed,
+            state: self.state.as_deref().map(to_hex),
+            cycles: self.cycles,
+            ips: self.ips,
+            inputs: self.inputs,
+            freezes: self.freezes,
+            hash: format!("{:#018x}", chip8.state_hash()),
+            schip: chip8.is_schip(),
+            xochip: chip8.is_xochip(),
+            cdp1802: chip8.is_cdp1802(),
+            no_hires: !chip8.detects_hires(),
+            min_audible: chip8.beep().min_audible,
+            min_beep: chip8.beep().min_frames,
+            quirks: chip8.quirks().enabled().iter().map(|name| name.to_string()).collect(),
+        }
+    }
+}
+
+impl Fixture {
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = toml::to_string(self).map_err(|e| e.to_string())?;
+        fs::write(path, text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn load(path: &Path) -> Result<Fixture, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    // Runs the recorded inputs, returning the hash of the final state
+    fn replay(&self) -> Result<u64, String> {
+        let mut chip8 = Chip8::new();
+        chip8.set_schip(self.schip);
+        chip8.set_xochip(self.xochip);
+        chip8.set_cdp1802(self.cdp1802);
+        chip8.set_hires_detection(!self.no_hires);
+        chip8.set_beep(Beep { min_audible: self.min_audible, min_frames: self.min_beep });
+        chip8.set_quirks(Quirks::parse(&self.quirks.join(","))?);
+        match (&self.state, self.seed) {
+            (Some(state), _) => chip8.load_state(&from_hex(state)?)?,
+            (None, Some(seed)) => {
+                chip8.seed(seed);
+                chip8.load_rom(&from_hex(&self.rom_data)?).map_err(|e| e.to_string())?;
+            }
+            (None, None) => return Err("fixture has neither `seed` nor `state`".to_string()),
+        }
+
+        let mut inputs = self.inputs.iter().peekable();
+        let mut freezes = self.freezes.iter().peekable();
+        let mut frozen = Freezes::default();
+        let mut set_inputs = |chip8: &mut Chip8, cycle: u64| {
+            if let Some((_, entries)) = freezes.next_if(|&&(at, _)| at == cycle) {
+                frozen = Freezes::default();
+                for &(addr, value) in entries {
+                    frozen.freeze(addr, value);
+                }
+            }
+            frozen.apply(&mut chip8.memory);
+            if let Some(&(_, mask)) = inputs.next_if(|&&(at, _)| at == cycle) {
+                set_keypad(chip8.keypad_mut(), mask);
+            }
+        };
+        match self.ips {
+            None => {
+                for cycle in 0..self.cycles {
+                    set_inputs(&mut chip8, cycle);
+                    chip8.cycle().map_err(|e| format!("cycle {}: {}", cycle, e))?;
+                }
+            }
+            // Frames as the main loop runs them with --ips
+            Some(ips) => {
+                let mut timing = Timing::from_ips(ips);
+                let mut cycle = 0;
+                while cycle < self.cycles {
+                    timing.start_frame();
+                    while timing.due() && cycle < self.cycles {
+                        set_inputs(&mut chip8, cycle);
+                        timing.tick(&mut chip8).map_err(|e| format!("cycle {}: {}", cycle, e))?;
+                        cycle += 1;
+                    }
+                    if !timing.due() {
+                        chip8.tick_timers();
+                    }
+                }
+            }
+        }
+        Ok(chip8.state_hash())
+    }
+
+    fn expected_hash(&self) -> Result<u64, String> {
+        u64::from_str_radix(self.hash.trim_start_matches("0x"), 16).map_err(|_| format!("bad hash `{}`", self.hash))
+    }
+}
+
+// `verify <FIXTURE>...`
+pub fn run_verify(program: &str, args: &[String]) -> i32 {
+    if args.is_empty() {
+        eprintln!("Usage: {} verify <FIXTURE>...\n", program);
+        return 1;
+    }
+
+    let mut failures = 0;
+    for file in args {
+        let result = Fixture::load(Path::new(file))
+            .and_then(|fixture| Ok((fixture.replay()?, fixture.expected_hash()?)));
+        match result {
+            Ok((got, expected)) if got == expected => println!("PASS  {:016x}  {}", got, file),
+            Ok((got, expected)) => {
+                failures += 1;
+                println!("FAIL  {:016x}  {} (expected {:016x})", got, file, expected);
+            }
+            Err(e) => {
+                failures += 1;
+                println!("FAIL  {:>16}  {} ({})", "error", file, e);
+            }
+        }
+    }
+
+    println!("\n{} fixtures, {} failed", args.len(), failures);
+    if failures > 0 { 1 } else { 0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Adds the byte at 0x300 to V1, forever
+    const ROM: [u8; 8] = [0xa3, 0x00, 0xf0, 0x65, 0x81, 0x04, 0x12, 0x02];
+
+    // Runs ROM as the main loop does without --ips, freezing 0x300 to 5 from cycle 10
+    fn capture() -> Fixture {
+        let mut chip8 = Chip8::new();
+        chip8.seed(7);
+        chip8.load_rom(&ROM).unwrap();
+        let mut freezes = Freezes::default();
+        let mut capture = Capture::from_seed(7, None);
+        for cycle in 0..100 {
+            if cycle == 10 {
+                freezes.freeze(0x300, 5);
+            }
+            freezes.apply(&mut chip8.memory);
+            chip8.keypad_mut()[3] = (cycle >= 50) as u8;
+            capture.record(chip8.keypad(), freezes.entries());
+            chip8.cycle().unwrap();
+        }
+        capture.finish(&ROM, &chip8)
+    }
+
+    #[test]
+    fn replays_inputs_and_freezes() {
+        let fixture = capture();
+        assert_eq!(fixture.inputs, [(0, 0), (50, 1 << 3)]);
+        assert_eq!(fixture.freezes, [(10, vec![(0x300, 5)])]);
+        assert_eq!(fixture.replay().unwrap(), fixture.expected_hash().unwrap());
+
+        let text = toml::to_string(&fixture).unwrap();
+        let loaded: Fixture = toml::from_str(&text).unwrap();
+        assert_eq!(loaded.replay().unwrap(), fixture.expected_hash().unwrap());
+
+        let unfrozen = Fixture { freezes: Vec::new(), ..fixture };
+        assert_ne!(unfrozen.replay().unwrap(), unfrozen.expected_hash().unwrap());
+    }
+
+    #[test]
+    fn replays_the_machine_settings() {
+        let fixture = capture();
+        let hash = fixture.expected_hash().unwrap();
+        assert_ne!(Fixture { schip: true, ..capture() }.replay().unwrap(), hash);
+        assert_eq!(fixture.min_audible, Beep::default().min_audible);
+        assert!(!fixture.cdp1802 && !fixture.no_hires);
+    }
+}
@@ -0,0 +1,340 @@
+// CDP1802 core for 0NNN machine code routines
+//
+// On the COSMAC VIP, 0NNN runs the 1802 code at NNN until it hands control back
+// to the interpreter with SEP R4. Hybrid ROMs use this for things the
+// interpreter can't do, mostly by poking the interpreter's own work area, so
+// that area is laid out as the 4K VIP has it while a routine runs:
+//
+//   0EA0-0ECF  1802 stack, R2 starts at 0ECF
+//   0EF0-0EFF  V0-VF
+//   0F00-0FFF  the 64x32 display, 8 bytes a row, leftmost pixel in the top bit
+//
+// and the registers are set up the way the interpreter leaves them:
+//
+//   R2 stack   R3 PC (NNN)   R4 return to the interpreter   R5 CHIP-8 PC
+//   R6 &VX     R7 &VY        R8.1 delay timer   R8.0 sound timer   RA I
+//
+// V registers, I, the timers and the display are read back afterwards. OUT 2
+// selects a key and EF3 reads it, like the VIP keypad; other I/O reads as 0.
+// Timers don't run during a routine, so one is cut short after
+// MAX_INSTRUCTIONS rather than waiting on them forever.
+
+use crate::Chip8;
+
+const STACK_TOP: u16 = 0x0ECF;
+const REGISTERS: u16 = 0x0EF0;
+const DISPLAY: u16 = 0x0F00;
+
+const MAX_INSTRUCTIONS: u32 = 100_000;
+
+#[derive(Default)]
+struct Cdp1802 {
+    r: [u16; 16],
+    p: u8,
+    x: u8,
+    d: u8,
+    df: bool,
+    t: u8,
+    ie: bool,
+    q: bool,
+    // Key selected by OUT 2
+    key_latch: u8,
+}
+
+impl Cdp1802 {
+    fn pc(&self) -> u16 {
+        self.r[self.p as usize]
+    }
+
+    fn set_pc(&mut self, value: u16) {
+        self.r[self.p as usize] = value;
+    }
+
+    // Byte at R(P), advancing it
+    fn immediate(&mut self, chip8: &Chip8) -> u8 {
+        let value = chip8.read(self.pc());
+        self.set_pc(self.pc().wrapping_add(1));
+        value
+    }
+
+    fn rx(&self) -> u16 {
+        self.r[self.x as usize]
+    }
+
+    fn flag(&self, n: u8, chip8: &Chip8) -> bool {
+        n == 3 && chip8.keypad[self.key_latch as usize & 0xF] != 0
+    }
+
+    fn add(&mut self, a: u8, b: u8, carry: bool) {
+        let sum = a as u16 + b as u16 + carry as u16;
+        self.d = sum as u8;
+        self.df = sum > 0xFF;
+    }
+
+    // a - b, with DF set when there was no borrow
+    fn subtract(&mut self, a: u8, b: u8, borrow: bool) {
+        let difference = a as i16 - b as i16 - borrow as i16;
+        self.d = difference as u8;
+        self.df = difference >= 0;
+    }
+
+    // Executes one instruction
+    fn step(&mut self, chip8: &mut Chip8) {
+        let opcode = self.immediate(chip8);
+        let n = (opcode & 0x0F) as usize;
+
+        match opcode >> 4 {
+            // 00 - IDL: waits for an interrupt, which never comes here
+            0x0 if n == 0 => {}
+            // 0N - LDN RN
+            0x0 => self.d = chip8.read(self.r[n]),
+            // 1N - INC RN
+            0x1 => self.r[n] = self.r[n].wrapping_add(1),
+            // 2N - DEC RN
+            0x2 => self.r[n] = self.r[n].wrapping_sub(1),
+            // 3N - short branches, to an address in the current page
+            0x3 => {
+                let taken = match n {
+                    0x0 => true,
+                    0x1 => self.q,
+                    0x2 => self.d == 0,
+                    0x3 => self.df,
+                    0x4..=0x7 => self.flag(n as u8 - 0x3, chip8),
+                    // 38 - SKP
+                    0x8 => false,
+                    0x9 => !self.q,
+                    0xA => self.d != 0,
+                    0xB => !self.df,
+                    _ => !self.flag(n as u8 - 0xB, chip8),
+                };
+                if taken {
+                    let target = chip8.read(self.pc());
+                    self.set_pc((self.pc() & 0xFF00) | target as u16);
+                } else {
+                    self.set_pc(self.pc().wrapping_add(1));
+                }
+            }
+            // 4N - LDA RN
+            0x4 => {
+                self.d = chip8.read(self.r[n]);
+                self.r[n] = self.r[n].wrapping_add(1);
+            }
+            // 5N - STR RN
+            0x5 => chip8.write(self.r[n], self.d),
+            // 60 - IRX
+            0x6 if n == 0 => self.r[self.x as usize] = self.rx().wrapping_add(1),
+            // 61-67 - OUT N
+            0x6 if n < 8 => {
+                let value = chip8.read(self.rx());
+                if n == 2 {
+                    self.key_latch = value & 0xF;
+                }
+                self.r[self.x as usize] = self.rx().wrapping_add(1);
+            }
+            // 68 isn't an 1802 instruction
+            0x6 if n == 8 => {}
+            // 69-6F - INP N
+            0x6 => {
+                chip8.write(self.rx(), 0);
+                self.d = 0;
+            }
+            0x7 => match n {
+                // 70 - RET, 71 - DIS
+                0x0 | 0x1 => {
+                    let value = chip8.read(self.rx());
+                    self.r[self.x as usize] = self.rx().wrapping_add(1);
+                    self.x = value >> 4;
+                    self.p = value & 0xF;
+                    self.ie = n == 0;
+                }
+                // 72 - LDXA
+                0x2 => {
+                    self.d = chip8.read(self.rx());
+                    self.r[self.x as usize] = self.rx().wrapping_add(1);
+                }
+                // 73 - STXD
+                0x3 => {
+                    chip8.write(self.rx(), self.d);
+                    self.r[self.x as usize] = self.rx().wrapping_sub(1);
+                }
+                // 74 - ADC
+                0x4 => self.add(chip8.read(self.rx()), self.d, self.df),
+                // 75 - SDB
+                0x5 => self.subtract(chip8.read(self.rx()), self.d, !self.df),
+                // 76 - SHRC
+                0x6 => {
+                    let carry = self.df;
+                    self.df = self.d & 1 != 0;
+                    self.d = (self.d >> 1) | ((carry as u8) << 7);
+                }
+                // 77 - SMB
+                0x7 => self.subtract(self.d, chip8.read(self.rx()), !self.df),
+                // 78 - SAV
+                0x8 => chip8.write(self.rx(), self.t),
+                // 79 - MARK
+                0x9 => {
+                    self.t = (self.x << 4) | self.p;
+                    chip8.write(self.r[2], self.t);
+                    self.x = self.p;
+                    self.r[2] = self.r[2].wrapping_sub(1);
+                }
+                // 7A - REQ, 7B - SEQ
+                0xA => self.q = false,
+                0xB => self.q = true,
+                // 7C - ADCI
+                0xC => {
+                    let value = self.immediate(chip8);
+                    self.add(value, self.d, self.df);
+                }
+                // 7D - SDBI
+                0xD => {
+                    let value = self.immediate(chip8);
+                    self.subtract(value, self.d, !self.df);
+                }
+                // 7E - SHLC
+                0xE => {
+                    let carry = self.df;
+                    self.df = self.d & 0x80 != 0;
+                    self.d = (self.d << 1) | carry as u8;
+                }
+                // 7F - SMBI
+                _ => {
+                    let value = self.immediate(chip8);
+                    self.subtract(self.d, value, !self.df);
+                }
+            },
+            // 8N - GLO RN
+            0x8 => self.d = self.r[n] as u8,
+            // 9N - GHI RN
+            0x9 => self.d = (self.r[n] >> 8) as u8,
+            // AN - PLO RN
+            0xA => self.r[n] = (self.r[n] & 0xFF00) | self.d as u16,
+            // BN - PHI RN
+            0xB => self.r[n] = (self.r[n] & 0x00FF) | ((self.d as u16) << 8),
+            // CN - long branches and skips
+            0xC => {
+                let condition = match n & 0x3 {
+                    0x0 => true,
+                    0x1 => self.q,
+                    0x2 => self.d == 0,
+                    _ => self.df,
+                };
+                match n {
+                    // C4 - NOP
+                    0x4 => {}
+                    // C0-C3 - LBR, LBQ, LBZ, LBDF, C8-CB - LSKP, LBNQ, LBNZ, LBNF
+                    0x0..=0x3 | 0x9..=0xB => {
+                        if condition == (n < 0x8) {
+                            let target = ((chip8.read(self.pc()) as u16) << 8) | chip8.read(self.pc().wrapping_add(1)) as u16;
+                            self.set_pc(target);
+                        } else {
+                            self.set_pc(self.pc().wrapping_add(2));
+                        }
+                    }
+                    // C5-C8 - LSNQ, LSNZ, LSNF, LSKP, CC-CF - LSIE, LSQ, LSZ, LSDF
+                    _ => {
+                        let skip = match n {
+                            0x5..=0x7 => !condition,
+                            0x8 => true,
+                            0xC => self.ie,
+                            _ => condition,
+                        };
+                        if skip {
+                            self.set_pc(self.pc().wrapping_add(2));
+                        }
+                    }
+                }
+            }
+            // DN - SEP RN
+            0xD => self.p = n as u8,
+            // EN - SEX RN
+            0xE => self.x = n as u8,
+            // F6 - SHR
+            0xF if n == 0x6 => {
+                self.df = self.d & 1 != 0;
+                self.d >>= 1;
+            }
+            // FE - SHL
+            0xF if n == 0xE => {
+                self.df = self.d & 0x80 != 0;
+                self.d <<= 1;
+            }
+            // FN - ALU operations on M(R(X)), or the immediate byte for F8-FF
+            _ => {
+                let value = if n < 8 { chip8.read(self.rx()) } else { self.immediate(chip8) };
+                match n & 0x7 {
+                    // F0 - LDX, F8 - LDI
+                    0x0 => self.d = value,
+                    // F1 - OR, F9 - ORI
+                    0x1 => self.d |= value,
+                    // F2 - AND, FA - ANI
+                    0x2 => self.d &= value,
+                    // F3 - XOR, FB - XRI
+                    0x3 => self.d ^= value,
+                    // F4 - ADD, FC - ADI
+                    0x4 => self.add(value, self.d, false),
+                    // F5 - SD, FD - SDI
+                    0x5 => self.subtract(value, self.d, false),
+                    // F7 - SM, FF - SMI
+                    _ => self.subtract(self.d, value, false),
+                }
+            }
+        }
+    }
+}
+
+// The VIP's view of the CHIP-8 machine, for the duration of a routine
+impl Chip8 {
+    fn export_vip_state(&mut self) {
+        let registers = self.registers;
+        for (i, value) in registers.into_iter().enumerate() {
+            self.write(REGISTERS + i as u16, value);
+        }
+        if !self.hires {
+            let display: Vec<u8> = self.video[..64 * 32].chunks(8)
+                .map(|pixels| pixels.iter().enumerate().fold(0, |byte, (bit, &p)| byte | (((p != 0) as u8) << (7 - bit))))
+                .collect();
+            for (i, byte) in display.into_iter().enumerate() {
+                self.write(DISPLAY + i as u16, byte);
+            }
+        }
+    }
+
+    fn import_vip_state(&mut self) {
+        for i in 0..16 {
+            self.registers[i] = self.read(REGISTERS + i as u16);
+        }
+        if !self.hires {
+            for i in 0..64 * 32 {
+                let byte = self.read(DISPLAY + (i / 8) as u16);
+                self.video[i] = (byte & (0x80 >> (i % 8)) != 0) as u32;
+            }
+        }
+    }
+
+    // 0nnn - SYS addr: Run the 1802 machine code routine at nnn
+    pub fn call_native(&mut self, addr: u16) {
+        let mut cpu = Cdp1802 { p: 3, x: 2, ..Default::default() };
+        cpu.r[2] = STACK_TOP;
+        cpu.r[3] = addr;
+        cpu.r[5] = self.pc;
+        cpu.r[6] = REGISTERS + ((self.opcode >> 8) & 0xF);
+        cpu.r[7] = REGISTERS + ((self.opcode >> 4) & 0xF);
+        cpu.r[8] = ((self.delay_timer as u16) << 8) | self.sound_timer as u16;
+        cpu.r[0xA] = self.index;
+
+        self.export_vip_state();
+        for _ in 0..MAX_INSTRUCTIONS {
+            cpu.step(self);
+            if cpu.p == 4 {
+                break;
+            }
+        }
+        self.import_vip_state();
+
+        self.pc = cpu.r[5] & 0xFFF;
+        self.index = cpu.r[0xA] & 0xFFF;
+        self.delay_timer = (cpu.r[8] >> 8) as u8;
+        self.sound_timer = cpu.r[8] as u8;
+    }
+}
@@ -0,0 +1,126 @@
+// Cheat search and frozen memory
+//
+// A search starts from every byte of RAM and is narrowed down by comparing the
+// current memory against the snapshot taken at the previous step, the usual way
+// to find where a game keeps its lives or score. Whatever's left can be frozen so
+// the game can't change it.
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Condition {
+    Equals(u8),
+    Increased,
+    Decreased,
+    Changed,
+    Unchanged,
+}
+
+impl Condition {
+    fn holds(self, before: u8, now: u8) -> bool {
+        match self {
+            Condition::Equals(value) => now == value,
+            Condition::Increased => now > before,
+            Condition::Decreased => now < before,
+            Condition::Changed => now != before,
+            Condition::Unchanged => now == before,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct CheatSearch {
+    // Addresses still matching every step so far, None before a search starts
+    candidates: Option<Vec<u16>>,
+    snapshot: Vec<u8>,
+}
+
+impl CheatSearch {
+    pub fn start(&mut self, memory: &[u8]) {
+        self.candidates = Some((0..memory.len()).map(|addr| addr as u16).collect());
+        self.snapshot = memory.to_vec();
+    }
+
+    // Keeps the candidates meeting `condition`, returning how many are left
+    pub fn filter(&mut self, condition: Condition, memory: &[u8]) -> Option<usize> {
+        let candidates = self.candidates.as_mut()?;
+        candidates.retain(|&addr| condition.holds(self.snapshot[addr as usize], memory[addr as usize]));
+        self.snapshot.copy_from_slice(memory);
+        Some(candidates.len())
+    }
+
+    pub fn candidates(&self) -> &[u16] {
+        self.candidates.as_deref().unwrap_or(&[])
+    }
+}
+
+// Bytes held at a fixed value
+#[derive(Default)]
+pub struct Freezes {
+    entries: Vec<(u16, u8)>,
+}
+
+impl Freezes {
+    pub fn freeze(&mut self, addr: u16, value: u8) {
+        self.thaw(addr);
+        self.entries.push((addr, value));
+    }
+
+    pub fn thaw(&mut self, addr: u16) {
+        self.entries.retain(|&(a, _)| a != addr);
+    }
+
+    pub fn entries(&self) -> &[(u16, u8)] {
+        &self.entries
+    }
+
+    // Writes the frozen values back over whatever the game stored
+    pub fn apply(&self, memory: &mut [u8]) {
+        for &(addr, value) in &self.entries {
+            memory[addr as usize] = value;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn narrows_down_to_the_changing_byte() {
+        let mut memory = vec![0u8; 0x10000];
+        memory[0x2f0] = 3;
+        let mut search = CheatSearch::default();
+        assert_eq!(search.filter(Condition::Changed, &memory), None);
+
+        search.start(&memory);
+        assert_eq!(search.candidates().len(), 0x10000);
+        assert_eq!(search.filter(Condition::Equals(3), &memory), Some(1));
+        assert_eq!(search.candidates(), [0x2f0]);
+
+        search.start(&memory);
+        memory[0x2f0] = 2;
+        memory[0xfff0] = 1;
+        assert_eq!(search.filter(Condition::Decreased, &memory), Some(1));
+        memory[0x2f0] = 5;
+        assert_eq!(search.filter(Condition::Increased, &memory), Some(1));
+        assert_eq!(search.filter(Condition::Unchanged, &memory), Some(1));
+        assert_eq!(search.candidates(), [0x2f0]);
+    }
+
+    #[test]
+    fn holds_frozen_bytes() {
+        let mut memory = [0u8; 16];
+        let mut freezes = Freezes::default();
+        freezes.freeze(2, 7);
+        freezes.freeze(5, 1);
+        freezes.freeze(2, 9);
+        assert_eq!(freezes.entries(), [(5, 1), (2, 9)]);
+
+        freezes.apply(&mut memory);
+        assert_eq!((memory[2], memory[5]), (9, 1));
+
+        freezes.thaw(5);
+        memory[5] = 0;
+        freezes.apply(&mut memory);
+        assert_eq!((memory[2], memory[5]), (9, 0));
+    }
+}
@@ -0,0 +1,157 @@
+// Text-command input
+//
+// `--commands -` reads newline-delimited commands from stdin, `--commands ADDR`
+// from any number of TCP connections, so chat bots or scripts can play. Commands
+// run one after another, each starting once the previous one is done:
+//
+//   press 5 [for 3 frames]    hold key 5 for a number of frames (default 6)
+//   hold A                    hold a key until it's released
+//   release A | release all
+//   wait 30 [frames]          do nothing for a number of frames
+//
+// A frame is one tick of the 60Hz timers. Keys are hex digits.
+
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader};
+use std::net::TcpListener;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+const DEFAULT_PRESS_FRAMES: u64 = 6;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Command {
+    Press { key: u8, frames: u64 },
+    Hold(u8),
+    Release(u8),
+    ReleaseAll,
+    Wait(u64),
+}
+
+fn parse_key(word: &str) -> Result<u8, String> {
+    u8::from_str_radix(word, 16).ok().filter(|&k| k < 16).ok_or_else(|| format!("`{}` is not a key (0-F)", word))
+}
+
+// `N`, `N frames` or `for N frames`
+fn parse_frames(words: &[&str]) -> Result<Option<u64>, String> {
+    let words = words.strip_prefix(&["for"]).unwrap_or(words);
+    let count = match words {
+        [] => return Ok(None),
+        [count] | [count, "frame" | "frames"] => count,
+        _ => return Err("expected `N frames`".to_string()),
+    };
+    count.parse().map(Some).map_err(|_| format!("`{}` is not a number", count))
+}
+
+fn parse(line: &str) -> Result<Command, String> {
+    let lower = line.to_ascii_lowercase();
+    let words: Vec<&str> = lower.split_whitespace().collect();
+    match words.as_slice() {
+        ["press", key, rest @ ..] => Ok(Command::Press {
+            key: parse_key(key)?,
+            frames: parse_frames(rest)?.unwrap_or(DEFAULT_PRESS_FRAMES),
+        }),
+        ["hold", key] => Ok(Command::Hold(parse_key(key)?)),
+        ["release", "all"] => Ok(Command::ReleaseAll),
+        ["release", key] => Ok(Command::Release(parse_key(key)?)),
+        ["wait", rest @ ..] => parse_frames(rest)?.map(Command::Wait).ok_or_else(|| "wait needs a number of frames".to_string()),
+        _ => Err(format!("unknown command `{}`", line)),
+    }
+}
+
+fn read_lines(reader: impl BufRead, lines: Sender<String>) {
+    for line in reader.lines() {
+        match line {
+            Ok(line) => {
+                if lines.send(line).is_err() {
+                    break;
+                }
+            }
+            Err(_) => break,
+        }
+    }
+}
+
+pub struct CommandInput {
+    lines: Receiver<String>,
+    queue: VecDeque<Command>,
+    held: [bool; 16],
+    // Key and frames left of the press in progress
+    pressing: Option<(u8, u64)>,
+    waiting: u64,
+}
+
+impl CommandInput {
+    fn new(lines: Receiver<String>) -> CommandInput {
+        CommandInput { lines, queue: VecDeque::new(), held: [false; 16], pressing: None, waiting: 0 }
+    }
+
+    pub fn stdin() -> CommandInput {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || read_lines(io::stdin().lock(), tx));
+        CommandInput::new(rx)
+    }
+
+    pub fn listen(addr: &str) -> io::Result<CommandInput> {
+        let listener = TcpListener::bind(addr)?;
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let lines = tx.clone();
+                thread::spawn(move || read_lines(BufReader::new(stream), lines));
+            }
+        });
+        Ok(CommandInput::new(rx))
+    }
+
+    // Queues commands received since the last call
+    pub fn poll(&mut self) {
+        while let Ok(line) = self.lines.try_recv() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            match parse(line) {
+                Ok(command) => self.queue.push_back(command),
+                Err(e) => eprintln!("Ignoring command: {}", e),
+            }
+        }
+        self.start_next();
+    }
+
+    // Runs queued commands until one takes time
+    fn start_next(&mut self) {
+        while self.pressing.is_none() && self.waiting == 0 {
+            match self.queue.pop_front() {
+                Some(Command::Press { key, frames }) if frames > 0 => self.pressing = Some((key, frames)),
+                Some(Command::Press { .. }) => {}
+                Some(Command::Hold(key)) => self.held[key as usize] = true,
+                Some(Command::Release(key)) => self.held[key as usize] = false,
+                Some(Command::ReleaseAll) => self.held = [false; 16],
+                Some(Command::Wait(frames)) => self.waiting = frames,
+                None => break,
+            }
+        }
+    }
+
+    // Advances the schedule by one frame
+    pub fn tick(&mut self) {
+        if let Some((key, frames)) = self.pressing {
+            self.pressing = (frames > 1).then_some((key, frames - 1));
+        }
+        self.waiting = self.waiting.saturating_sub(1);
+        self.start_next();
+    }
+
+    // Presses the keys the commands are holding down, on top of the local input
+    pub fn apply(&self, keys: &mut [u8; 16]) {
+        for (key, &held) in self.held.iter().enumerate() {
+            if held {
+                keys[key] = 1;
+            }
+        }
+        if let Some((key, _)) = self.pressing {
+            keys[key as usize] = 1;
+        }
+    }
+}
@@ -0,0 +1,132 @@
+// Optional TOML config file
+//
+// Looked up at $XDG_CONFIG_HOME/chipeight/config.toml (falling back to
+// ~/.config/chipeight/config.toml) unless --config points elsewhere. A missing
+// file just means defaults; command line flags override whatever it sets.
+//
+//   frame_skip = 8
+//   audio_device = "USB Audio"
+//   turbo_rate = 10
+//   watch = ["lives=[2F0]", "V0+V1"]
+//   keypad = "qwerty"
+//   scale = 12
+//   palette = "amber"
+//   foreground = "#FFB000"
+//   background = "#1A1000"
+//   quirks = "chip8,-vf_reset"
+//
+//   [keys]
+//   A = "4"
+//   Q = "7"
+//
+//   [hotkeys]
+//   pause = "Space"
+//   fast_forward = "Left Shift"
+//
+//   [turbo]
+//   Space = "5"
+//
+//   [controller]
+//   start = "5"
+//
+//   [games.brix.controller]
+//   a = "none"
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    // Action name to SDL key name, see hotkeys::ACTIONS
+    pub hotkeys: HashMap<String, String>,
+    // Draw only every Nth frame while fast-forwarding, like --frame-skip
+    pub frame_skip: Option<u32>,
+    // Buzzer output by name, like --audio-device
+    pub audio_device: Option<String>,
+    // Host key name to keypad key, see turbo.rs
+    pub turbo: HashMap<String, String>,
+    pub turbo_rate: Option<u32>,
+    // Expressions for the watch overlay, added to any --watch
+    pub watch: Vec<String>,
+    // Keypad layout, like --keypad
+    pub keypad: Option<String>,
+    // Host key name to keypad key, on top of the layout, like --key
+    pub keys: HashMap<String, String>,
+    // Window scale when none is given on the command line or in a session
+    pub scale: Option<u32>,
+    // Colours by palette name, like --palette
+    pub palette: Option<String>,
+    // Lit and unlit pixels as #RRGGBB, over the palette, see palette.rs
+    pub foreground: Option<String>,
+    pub background: Option<String>,
+    // Quirks for ROMs without any on the command line or in their session
+    pub quirks: Option<String>,
+    // Controller button name to keypad key, see gamepad.rs
+    pub controller: HashMap<String, String>,
+    // Settings for one game, by ROM name
+    pub games: HashMap<String, Game>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Game {
+    // On top of the [controller] table
+    pub controller: HashMap<String, String>,
+}
+
+fn config_dir() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(base.join("chipeight"))
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    Some(config_dir()?.join("config.toml"))
+}
+
+// The display fullscreen last opened on, remembered next to the config file
+pub fn last_monitor() -> Option<i32> {
+    let text = fs::read_to_string(config_dir()?.join("monitor")).ok()?;
+    text.trim().parse().ok()
+}
+
+pub fn remember_monitor(monitor: i32) -> io::Result<()> {
+    let dir = config_dir().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no config directory"))?;
+    fs::create_dir_all(&dir)?;
+    fs::write(dir.join("monitor"), format!("{}\n", monitor))
+}
+
+// Where the save-state slots of a ROM are kept, e.g. `states/pong-1a2b3c4d`.
+// The hash of the image tells apart different ROMs going by the same name.
+pub fn states_dir(rom_name: &str, rom: &[u8]) -> Option<PathBuf> {
+    let hash = rom.iter().fold(0x811c9dc5u32, |hash, &byte| (hash ^ byte as u32).wrapping_mul(0x01000193));
+    Some(config_dir()?.join("states").join(format!("{}-{:08x}", rom_name, hash)))
+}
+
+impl Config {
+    // An explicitly requested file has to exist, the default one doesn't
+    pub fn load(path: Option<&str>) -> Result<Config, String> {
+        let (path, required) = match path {
+            Some(path) => (PathBuf::from(path), true),
+            None => match default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Config::default()),
+            },
+        };
+
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !required => return Ok(Config::default()),
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+
+        toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
@@ -0,0 +1,49 @@
+// Controls discovery
+//
+// Most ROMs come without a word about their controls. The machine notes every
+// key a SKP or SKNP tests and whether it has waited on LD Vx, K, and whenever
+// that turns up something new a hint like
+//
+//   Uses 4 5 6 (Q W E)
+//
+// is shown, the keypad keys followed by the host keys they're on. The controls
+// hotkey (F1) shows it again.
+
+use crate::keymap::Keymap;
+use chip8_core::Chip8;
+
+#[derive(Default)]
+pub struct ControlsHint {
+    // What the last hint covered
+    shown_keys: u16,
+    shown_any_key: bool,
+}
+
+impl ControlsHint {
+    // The hint, if the machine has used keys it hasn't mentioned yet
+    pub fn update(&mut self, chip8: &Chip8, keymap: &Keymap) -> Option<String> {
+        let new_keys = chip8.polled_keys() & !self.shown_keys != 0;
+        let new_wait = chip8.waited_for_key() && !self.shown_any_key;
+        if !new_keys && !new_wait {
+            return None;
+        }
+        self.shown_keys |= chip8.polled_keys();
+        self.shown_any_key |= chip8.waited_for_key();
+        Some(self.describe(keymap))
+    }
+
+    pub fn describe(&self, keymap: &Keymap) -> String {
+        let keys: Vec<u8> = (0..16).filter(|&k| self.shown_keys & (1 << k) != 0).collect();
+        if keys.is_empty() {
+            let hint = if self.shown_any_key { "Waits for any key" } else { "No keys used yet" };
+            return hint.to_string();
+        }
+
+        let keypad: Vec<String> = keys.iter().map(|k| format!("{:X}", k)).collect();
+        let host: Vec<String> = keys.iter()
+            .map(|&k| keymap.keys_for(k).first().map_or("-".to_string(), |keycode| keycode.name()))
+            .collect();
+        let hint = format!("Uses {} ({})", keypad.join(" "), host.join(" "));
+        if self.shown_any_key { format!("{}, waits for any key", hint) } else { hint }
+    }
+}
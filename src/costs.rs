@@ -0,0 +1,39 @@
+// Cycle-cost tables
+//
+// `--timing FILE` runs frames by a table of machine cycles instead of a number
+// of instructions, see chip8_core::timing. The window, --headless, suite and
+// render all take one, in TOML:
+//
+//   cycles_per_frame = 3668    # 1.76MHz COSMAC VIP: 8 clocks per machine cycle
+//   default = 68               # classes missing from [costs]
+//
+//   [costs]
+//   00E0 = 3078
+//   DXYN = 4000
+
+use std::collections::HashMap;
+use std::fs;
+
+use serde::Deserialize;
+
+use chip8_core::timing::Timing;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CostTable {
+    cycles_per_frame: u64,
+    #[serde(default = "default_cost")]
+    default: u64,
+    #[serde(default)]
+    costs: HashMap<String, u64>,
+}
+
+fn default_cost() -> u64 {
+    1
+}
+
+pub fn load(path: &str) -> Result<Timing, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let table: CostTable = toml::from_str(&text).map_err(|e| format!("{}: {}", path, e))?;
+    Timing::new(table.cycles_per_frame, table.default, &table.costs).map_err(|e| format!("{}: {}", path, e))
+}
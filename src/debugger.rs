@@ -0,0 +1,351 @@
+// Minimal stdin-driven debugger
+//
+// Commands are read on a background thread so the window keeps responding while
+// the emulator is halted. Every command is checked before an instruction executes.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use crate::cheats::{CheatSearch, Condition, Freezes};
+use chip8_core::decode::decode;
+use chip8_core::Chip8;
+
+// Search results listed by `sl`
+const MAX_LISTED_CANDIDATES: usize = 32;
+// Instructions listed before and after the address by `l` and on halting
+const LISTED_BEFORE: u16 = 2;
+const LISTED_AFTER: u16 = 5;
+
+const HELP: &str = "\
+Commands:
+  c                continue
+  s                execute a single instruction
+  p                pause
+  b ADDR           set a breakpoint
+  d ADDR           delete a breakpoint
+  w ADDR           watch a memory byte, halting when it changes
+  u ADDR           remove a watch
+  r                show registers
+  m ADDR [LEN]     dump memory
+  l [ADDR]         disassemble around ADDR, or the PC
+  sn               start a cheat search over all of RAM
+  se VAL           keep bytes now equal to VAL
+  si, sd           keep bytes that increased, decreased since the last search step
+  sc, su           keep bytes that changed, stayed the same
+  sl               list the remaining candidates
+  sf [VAL]         freeze every remaining candidate, at VAL or its current value
+  f ADDR [VAL]     freeze a byte, at VAL or its current value
+  t ADDR           thaw a frozen byte
+  fl               list frozen bytes
+  h                this help
+ADDR and VAL are hex (2A4, 0x2A4, $2A4), ADDR can also be a label from the symbol file";
+
+pub struct Debugger {
+    paused: bool,
+    // Run exactly one instruction, then pause again
+    stepping: bool,
+    // Set when resuming from a breakpoint so it doesn't fire again immediately
+    resume_at: Option<u16>,
+    breakpoints: Vec<u16>,
+    // Address and last seen value of each watched byte
+    watches: Vec<(u16, u8)>,
+    symbols: Symbols,
+    search: CheatSearch,
+    freezes: Freezes,
+    commands: Receiver<String>,
+}
+
+// Labels loaded from a symbol file, one `ADDR LABEL` pair per line
+#[derive(Default)]
+pub struct Symbols {
+    labels: HashMap<String, u16>,
+}
+
+impl Symbols {
+    pub fn load(path: &str) -> Result<Symbols, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let mut labels = HashMap::new();
+
+        for (number, line) in text.lines().enumerate() {
+            // `;` and `#` start comments
+            let line = line.split([';', '#']).next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            match (words.next().and_then(parse_hex), words.next(), words.next()) {
+                (Some(addr), Some(label), None) => {
+                    labels.insert(label.to_string(), addr);
+                }
+                _ => return Err(format!("{}:{}: expected `ADDR LABEL`", path, number + 1)),
+            }
+        }
+
+        Ok(Symbols { labels })
+    }
+
+    // Resolves a label or a hex address
+    pub fn resolve(&self, text: &str) -> Option<u16> {
+        self.labels.get(text).copied().or_else(|| parse_hex(text))
+    }
+
+    // Label for an address, if it has one
+    fn name(&self, addr: u16) -> Option<&str> {
+        self.labels.iter().find(|&(_, &a)| a == addr).map(|(label, _)| label.as_str())
+    }
+
+    fn describe(&self, addr: u16) -> String {
+        match self.name(addr) {
+            Some(label) => format!("{:04X} ({})", addr, label),
+            None => format!("{:04X}", addr),
+        }
+    }
+}
+
+impl Debugger {
+    pub fn new(paused: bool, symbols: Symbols) -> Debugger {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines() {
+                match line {
+                    Ok(line) => {
+                        if tx.send(line).is_err() {
+                            break;
+                        }
+                    }
+                    Err(_) => break,
+                }
+            }
+        });
+
+        Debugger {
+            paused,
+            stepping: false,
+            resume_at: None,
+            breakpoints: Vec::new(),
+            watches: Vec::new(),
+            symbols,
+            search: CheatSearch::default(),
+            freezes: Freezes::default(),
+            commands: rx,
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        if !self.breakpoints.contains(&addr) {
+            self.breakpoints.push(addr);
+        }
+    }
+
+    // Handles pending commands; call once per main loop iteration
+    pub fn poll(&mut self, chip8: &Chip8) {
+        while let Ok(line) = self.commands.try_recv() {
+            self.execute(line.trim(), chip8);
+            prompt();
+        }
+    }
+
+    // Whether the instruction at `chip8.pc` may execute now
+    pub fn should_run(&mut self, chip8: &Chip8) -> bool {
+        if self.stepping {
+            self.stepping = false;
+            return true;
+        }
+        if self.paused {
+            return false;
+        }
+        if self.resume_at.take() == Some(chip8.pc) {
+            return true;
+        }
+        if self.breakpoints.contains(&chip8.pc) {
+            self.halt(chip8, &format!("Breakpoint at {}", self.symbols.describe(chip8.pc)));
+            return false;
+        }
+        true
+    }
+
+    // Holds frozen bytes at their values; call before every instruction
+    pub fn apply_freezes(&self, chip8: &mut Chip8) {
+        self.freezes.apply(&mut chip8.memory);
+    }
+
+    // The frozen bytes and their values, for fixtures to replay
+    pub fn frozen(&self) -> &[(u16, u8)] {
+        self.freezes.entries()
+    }
+
+    // Checks watches once an instruction has executed
+    pub fn after_cycle(&mut self, chip8: &Chip8) {
+        let mut changed = Vec::new();
+        for (addr, last) in self.watches.iter_mut() {
+            let value = chip8.memory[*addr as usize];
+            if value != *last {
+                changed.push(format!("Watch {:04X}: {:02X} -> {:02X}", addr, last, value));
+                *last = value;
+            }
+        }
+        if !changed.is_empty() {
+            self.halt(chip8, &changed.join("\n"));
+        } else if self.paused {
+            // Finished a single step
+            print_state(chip8);
+            prompt();
+        }
+    }
+
+    pub fn announce(&self, chip8: &Chip8) {
+        if self.paused {
+            println!("Paused at {:04X}, type h for help", chip8.pc);
+            print_state(chip8);
+            self.print_disassembly(chip8, chip8.pc);
+            prompt();
+        }
+    }
+
+    fn halt(&mut self, chip8: &Chip8, reason: &str) {
+        self.paused = true;
+        println!("\n{}", reason);
+        print_state(chip8);
+        self.print_disassembly(chip8, chip8.pc);
+        prompt();
+    }
+
+    // A few instructions either side of `addr`, the one at the PC marked, as
+    // far as the top of memory
+    fn print_disassembly(&self, chip8: &Chip8, addr: u16) {
+        let mask = chip8.address_mask();
+        let addr = addr & mask;
+        let start = addr.saturating_sub(2 * LISTED_BEFORE);
+        let end = addr.saturating_add(2 * LISTED_AFTER).min(mask);
+        for at in (start..=end).step_by(2) {
+            let opcode = ((chip8.memory[at as usize] as u16) << 8) | chip8.memory[(at.wrapping_add(1) & mask) as usize] as u16;
+            let marker = if at == chip8.pc { ">" } else { " " };
+            println!("{} {:<16} {:04X}  {}", marker, self.symbols.describe(at), opcode, decode(opcode, chip8.is_hires()));
+        }
+    }
+
+    fn execute(&mut self, line: &str, chip8: &Chip8) {
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(command) => command,
+            None => return,
+        };
+        let first = words.next();
+        let addr = first.and_then(|w| self.symbols.resolve(w)).filter(|&a| (a as usize) < chip8.memory.len());
+        let value = first.and_then(parse_byte);
+        let second = words.next();
+        let len = second.and_then(parse_hex);
+
+        match (command, addr) {
+            ("c", _) => {
+                self.paused = false;
+                self.resume_at = Some(chip8.pc);
+            }
+            ("s", _) => {
+                self.paused = true;
+                self.stepping = true;
+            }
+            ("p", _) => {
+                self.paused = true;
+                print_state(chip8);
+            }
+            ("b", Some(addr)) => self.add_breakpoint(addr),
+            ("d", Some(addr)) => self.breakpoints.retain(|&b| b != addr),
+            ("w", Some(addr)) => {
+                self.watches.retain(|&(a, _)| a != addr);
+                self.watches.push((addr, chip8.memory[addr as usize]));
+            }
+            ("u", Some(addr)) => self.watches.retain(|&(a, _)| a != addr),
+            ("r", _) => print_state(chip8),
+            ("m", Some(addr)) => dump_memory(chip8, addr, len.unwrap_or(16)),
+            ("l", _) => self.print_disassembly(chip8, addr.unwrap_or(chip8.pc)),
+            ("sn", _) => {
+                self.search.start(&chip8.memory);
+                println!("{} candidates", self.search.candidates().len());
+            }
+            ("se" | "si" | "sd" | "sc" | "su", _) => {
+                let condition = match (command, value) {
+                    ("se", Some(value)) => Condition::Equals(value),
+                    ("se", None) => return println!("se needs a value"),
+                    ("si", _) => Condition::Increased,
+                    ("sd", _) => Condition::Decreased,
+                    ("sc", _) => Condition::Changed,
+                    _ => Condition::Unchanged,
+                };
+                match self.search.filter(condition, &chip8.memory) {
+                    Some(left) => println!("{} candidates", left),
+                    None => println!("No search running, start one with sn"),
+                }
+            }
+            ("sl", _) => {
+                let candidates = self.search.candidates();
+                for &addr in candidates.iter().take(MAX_LISTED_CANDIDATES) {
+                    println!("{}: {:02X}", self.symbols.describe(addr), chip8.memory[addr as usize]);
+                }
+                if candidates.len() > MAX_LISTED_CANDIDATES {
+                    println!("... {} more", candidates.len() - MAX_LISTED_CANDIDATES);
+                }
+            }
+            ("sf", _) => {
+                for &addr in self.search.candidates() {
+                    self.freezes.freeze(addr, value.unwrap_or(chip8.memory[addr as usize]));
+                }
+                println!("{} bytes frozen", self.freezes.entries().len());
+            }
+            ("f", Some(addr)) => {
+                let value = second.and_then(parse_byte).unwrap_or(chip8.memory[addr as usize]);
+                self.freezes.freeze(addr, value);
+            }
+            ("t", Some(addr)) => self.freezes.thaw(addr),
+            ("fl", _) => {
+                for &(addr, value) in self.freezes.entries() {
+                    println!("{}: {:02X}", self.symbols.describe(addr), value);
+                }
+            }
+            ("h", _) => println!("{}", HELP),
+            _ => println!("Unknown command, type h for help"),
+        }
+    }
+}
+
+// Accepts `2A4`, `0x2A4` and `$2A4`
+fn parse_hex(text: &str) -> Option<u16> {
+    let hex = text.trim_start_matches("0x").trim_start_matches('$');
+    u16::from_str_radix(hex, 16).ok()
+}
+
+fn parse_byte(text: &str) -> Option<u8> {
+    parse_hex(text).and_then(|n| u8::try_from(n).ok())
+}
+
+fn prompt() {
+    print!("(chip8) ");
+    io::stdout().flush().ok();
+}
+
+fn print_state(chip8: &Chip8) {
+    let mask = chip8.address_mask();
+    let pc = chip8.pc & mask;
+    let opcode = ((chip8.memory[pc as usize] as u16) << 8) | chip8.memory[(pc.wrapping_add(1) & mask) as usize] as u16;
+    println!(
+        "PC={:04X} [{:04X}]  I={:04X}  SP={:X}  DT={:02X}  ST={:02X}",
+        chip8.pc, opcode, chip8.index, chip8.sp, chip8.delay_timer, chip8.sound_timer
+    );
+    let registers: Vec<String> = chip8.registers.iter()
+        .enumerate()
+        .map(|(i, v)| format!("V{:X}={:02X}", i, v))
+        .collect();
+    println!("{}", registers.join(" "));
+}
+
+fn dump_memory(chip8: &Chip8, addr: u16, len: u16) {
+    let start = (addr & chip8.address_mask()) as usize;
+    let end = (start + len as usize).min(chip8.address_mask() as usize + 1);
+    for (row, chunk) in chip8.memory[start..end].chunks(16).enumerate() {
+        let bytes: Vec<String> = chunk.iter().map(|b| format!("{:02X}", b)).collect();
+        println!("{:04X}: {}", start + row * 16, bytes.join(" "));
+    }
+}
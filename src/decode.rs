@@ -0,0 +1,378 @@
+// Opcode decoding shared by the interpreter, the disassembler and the
+// analysis and timing tools
+
+use std::fmt;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Instruction {
+    // 00E0, and 0230 in hi-res mode
+    Cls,
+    Ret,
+    // 0nnn machine code routine, only run with --cdp1802
+    Sys(u16),
+    // SCHIP display control: 00CN, 00FB, 00FC, 00FD, 00FE, 00FF
+    Scd(u8),
+    Scr,
+    Scl,
+    Exit,
+    Low,
+    High,
+    // XO-CHIP's 00DN scroll up
+    Scu(u8),
+    Jp(u16),
+    Call(u16),
+    SeImm { x: u8, byte: u8 },
+    SneImm { x: u8, byte: u8 },
+    SeReg { x: u8, y: u8 },
+    // 5XY2 and 5XY3, XO-CHIP's register range save and load
+    Save { x: u8, y: u8 },
+    Load { x: u8, y: u8 },
+    LdImm { x: u8, byte: u8 },
+    AddImm { x: u8, byte: u8 },
+    LdReg { x: u8, y: u8 },
+    Or { x: u8, y: u8 },
+    And { x: u8, y: u8 },
+    Xor { x: u8, y: u8 },
+    AddReg { x: u8, y: u8 },
+    Sub { x: u8, y: u8 },
+    Shr { x: u8, y: u8 },
+    Subn { x: u8, y: u8 },
+    Shl { x: u8, y: u8 },
+    SneReg { x: u8, y: u8 },
+    LdI(u16),
+    JpV0(u16),
+    Rnd { x: u8, byte: u8 },
+    Drw { x: u8, y: u8, n: u8 },
+    Skp(u8),
+    Sknp(u8),
+    LdVxDt(u8),
+    LdVxK(u8),
+    LdDtVx(u8),
+    LdStVx(u8),
+    AddI(u8),
+    LdF(u8),
+    // FX30, SCHIP's large digits
+    LdHf(u8),
+    LdB(u8),
+    LdIVx(u8),
+    LdVxI(u8),
+    // FX75 and FX85, SCHIP's RPL user flags
+    LdRVx(u8),
+    LdVxR(u8),
+    // XO-CHIP: F000 (followed by the address), FN01, F002, FX3A
+    LdILong,
+    Plane(u8),
+    Audio,
+    Pitch(u8),
+    Unknown(u16),
+}
+
+pub fn decode(opcode: u16, hires: bool) -> Instruction {
+    use Instruction::*;
+
+    let x = ((opcode & 0x0F00) >> 8) as u8;
+    let y = ((opcode & 0x00F0) >> 4) as u8;
+    let n = (opcode & 0x000F) as u8;
+    let byte = (opcode & 0x00FF) as u8;
+    let addr = opcode & 0x0FFF;
+
+    match (opcode & 0xF000) >> 12 {
+        0x0 => match opcode {
+            0x00E0 => Cls,
+            0x00EE => Ret,
+            0x0230 if hires => Cls,
+            0x00C0..=0x00CF => Scd(n),
+            0x00FB => Scr,
+            0x00FC => Scl,
+            0x00FD => Exit,
+            0x00FE => Low,
+            0x00FF => High,
+            0x00D0..=0x00DF => Scu(n),
+            _ => Sys(addr),
+        },
+        0x1 => Jp(addr),
+        0x2 => Call(addr),
+        0x3 => SeImm { x, byte },
+        0x4 => SneImm { x, byte },
+        0x5 if n == 0 => SeReg { x, y },
+        0x5 if n == 2 => Save { x, y },
+        0x5 if n == 3 => Load { x, y },
+        0x6 => LdImm { x, byte },
+        0x7 => AddImm { x, byte },
+        0x8 => match n {
+            0x0 => LdReg { x, y },
+            0x1 => Or { x, y },
+            0x2 => And { x, y },
+            0x3 => Xor { x, y },
+            0x4 => AddReg { x, y },
+            0x5 => Sub { x, y },
+            0x6 => Shr { x, y },
+            0x7 => Subn { x, y },
+            0xE => Shl { x, y },
+            _ => Unknown(opcode),
+        },
+        0x9 if n == 0 => SneReg { x, y },
+        0xA => LdI(addr),
+        0xB => JpV0(addr),
+        0xC => Rnd { x, byte },
+        0xD => Drw { x, y, n },
+        0xE => match byte {
+            0x9E => Skp(x),
+            0xA1 => Sknp(x),
+            _ => Unknown(opcode),
+        },
+        0xF => match byte {
+            0x00 if x == 0 => LdILong,
+            0x01 => Plane(x),
+            0x02 if x == 0 => Audio,
+            0x07 => LdVxDt(x),
+            0x0A => LdVxK(x),
+            0x15 => LdDtVx(x),
+            0x18 => LdStVx(x),
+            0x1E => AddI(x),
+            0x29 => LdF(x),
+            0x30 => LdHf(x),
+            0x33 => LdB(x),
+            0x3A => Pitch(x),
+            0x55 => LdIVx(x),
+            0x65 => LdVxI(x),
+            0x75 => LdRVx(x),
+            0x85 => LdVxR(x),
+            _ => Unknown(opcode),
+        },
+        _ => Unknown(opcode),
+    }
+}
+
+// Every opcode class, as named by Instruction::class
+pub const CLASSES: &[&str] = &[
+    "00E0", "00EE", "0NNN", "1NNN", "2NNN", "3XKK", "4XKK", "5XY0", "6XKK", "7XKK",
+    "8XY0", "8XY1", "8XY2", "8XY3", "8XY4", "8XY5", "8XY6", "8XY7", "8XYE", "9XY0",
+    "ANNN", "BNNN", "CXKK", "DXYN", "EX9E", "EXA1", "FX07", "FX0A", "FX15", "FX18",
+    "FX1E", "FX29", "FX33", "FX55", "FX65",
+    "00CN", "00FB", "00FC", "00FD", "00FE", "00FF", "FX30", "FX75", "FX85",
+    "00DN", "5XY2", "5XY3", "F000", "FN01", "F002", "FX3A",
+];
+
+impl Instruction {
+    // Opcode pattern the instruction was decoded from, `None` for unknown opcodes
+    pub fn class(&self) -> Option<&'static str> {
+        use Instruction::*;
+
+        Some(match self {
+            Cls => "00E0",
+            Ret => "00EE",
+            Sys(_) => "0NNN",
+            Scd(_) => "00CN",
+            Scr => "00FB",
+            Scl => "00FC",
+            Exit => "00FD",
+            Low => "00FE",
+            High => "00FF",
+            Scu(_) => "00DN",
+            Jp(_) => "1NNN",
+            Call(_) => "2NNN",
+            SeImm { .. } => "3XKK",
+            SneImm { .. } => "4XKK",
+            SeReg { .. } => "5XY0",
+            Save { .. } => "5XY2",
+            Load { .. } => "5XY3",
+            LdImm { .. } => "6XKK",
+            AddImm { .. } => "7XKK",
+            LdReg { .. } => "8XY0",
+            Or { .. } => "8XY1",
+            And { .. } => "8XY2",
+            Xor { .. } => "8XY3",
+            AddReg { .. } => "8XY4",
+            Sub { .. } => "8XY5",
+            Shr { .. } => "8XY6",
+            Subn { .. } => "8XY7",
+            Shl { .. } => "8XYE",
+            SneReg { .. } => "9XY0",
+            LdI(_) => "ANNN",
+            JpV0(_) => "BNNN",
+            Rnd { .. } => "CXKK",
+            Drw { .. } => "DXYN",
+            Skp(_) => "EX9E",
+            Sknp(_) => "EXA1",
+            LdVxDt(_) => "FX07",
+            LdVxK(_) => "FX0A",
+            LdDtVx(_) => "FX15",
+            LdStVx(_) => "FX18",
+            AddI(_) => "FX1E",
+            LdF(_) => "FX29",
+            LdHf(_) => "FX30",
+            LdB(_) => "FX33",
+            LdIVx(_) => "FX55",
+            LdVxI(_) => "FX65",
+            LdRVx(_) => "FX75",
+            LdVxR(_) => "FX85",
+            LdILong => "F000",
+            Plane(_) => "FN01",
+            Audio => "F002",
+            Pitch(_) => "FX3A",
+            Unknown(_) => return None,
+        })
+    }
+
+    // Conditional skips continue at either the next or the one after
+    pub fn is_skip(&self) -> bool {
+        use Instruction::*;
+        matches!(self, SeImm { .. } | SneImm { .. } | SeReg { .. } | SneReg { .. } | Skp(_) | Sknp(_))
+    }
+
+    // Only SCHIP (and later) interpreters run it; DRW with n=0 is its 16x16 sprite
+    pub fn is_schip(&self) -> bool {
+        use Instruction::*;
+        matches!(self, Scd(_) | Scr | Scl | Exit | Low | High | LdHf(_) | LdRVx(_) | LdVxR(_) | Drw { n: 0, .. })
+    }
+
+    // Only XO-CHIP interpreters run it
+    pub fn is_xochip(&self) -> bool {
+        use Instruction::*;
+        matches!(self, Scu(_) | Save { .. } | Load { .. } | LdILong | Plane(_) | Audio | Pitch(_))
+    }
+
+    // Bytes the instruction takes up, 4 for F000 and its address
+    pub fn size(&self) -> u16 {
+        if *self == Instruction::LdILong { 4 } else { 2 }
+    }
+}
+
+// Mnemonics follow Cowgod's reference, as in the comments on the op_ functions
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Instruction::*;
+
+        match *self {
+            Cls => write!(f, "CLS"),
+            Ret => write!(f, "RET"),
+            Sys(addr) => write!(f, "SYS 0x{:03X}", addr),
+            Scd(n) => write!(f, "SCD {}", n),
+            Scr => write!(f, "SCR"),
+            Scl => write!(f, "SCL"),
+            Exit => write!(f, "EXIT"),
+            Low => write!(f, "LOW"),
+            High => write!(f, "HIGH"),
+            Scu(n) => write!(f, "SCU {}", n),
+            Jp(addr) => write!(f, "JP 0x{:03X}", addr),
+            Call(addr) => write!(f, "CALL 0x{:03X}", addr),
+            SeImm { x, byte } => write!(f, "SE V{:X}, 0x{:02X}", x, byte),
+            SneImm { x, byte } => write!(f, "SNE V{:X}, 0x{:02X}", x, byte),
+            SeReg { x, y } => write!(f, "SE V{:X}, V{:X}", x, y),
+            Save { x, y } => write!(f, "SAVE V{:X} - V{:X}", x, y),
+            Load { x, y } => write!(f, "LOAD V{:X} - V{:X}", x, y),
+            LdImm { x, byte } => write!(f, "LD V{:X}, 0x{:02X}", x, byte),
+            AddImm { x, byte } => write!(f, "ADD V{:X}, 0x{:02X}", x, byte),
+            LdReg { x, y } => write!(f, "LD V{:X}, V{:X}", x, y),
+            Or { x, y } => write!(f, "OR V{:X}, V{:X}", x, y),
+            And { x, y } => write!(f, "AND V{:X}, V{:X}", x, y),
+            Xor { x, y } => write!(f, "XOR V{:X}, V{:X}", x, y),
+            AddReg { x, y } => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Sub { x, y } => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Shr { x, y } => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Subn { x, y } => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Shl { x, y } => write!(f, "SHL V{:X}, V{:X}", x, y),
+            SneReg { x, y } => write!(f, "SNE V{:X}, V{:X}", x, y),
+            LdI(addr) => write!(f, "LD I, 0x{:03X}", addr),
+            JpV0(addr) => write!(f, "JP V0, 0x{:03X}", addr),
+            Rnd { x, byte } => write!(f, "RND V{:X}, 0x{:02X}", x, byte),
+            Drw { x, y, n } => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Skp(x) => write!(f, "SKP V{:X}", x),
+            Sknp(x) => write!(f, "SKNP V{:X}", x),
+            LdVxDt(x) => write!(f, "LD V{:X}, DT", x),
+            LdVxK(x) => write!(f, "LD V{:X}, K", x),
+            LdDtVx(x) => write!(f, "LD DT, V{:X}", x),
+            LdStVx(x) => write!(f, "LD ST, V{:X}", x),
+            AddI(x) => write!(f, "ADD I, V{:X}", x),
+            LdF(x) => write!(f, "LD F, V{:X}", x),
+            LdHf(x) => write!(f, "LD HF, V{:X}", x),
+            LdB(x) => write!(f, "LD B, V{:X}", x),
+            LdIVx(x) => write!(f, "LD [I], V{:X}", x),
+            LdVxI(x) => write!(f, "LD V{:X}, [I]", x),
+            LdRVx(x) => write!(f, "LD R, V{:X}", x),
+            LdVxR(x) => write!(f, "LD V{:X}, R", x),
+            LdILong => write!(f, "LD I, LONG"),
+            Plane(n) => write!(f, "PLANE {}", n),
+            Audio => write!(f, "AUDIO"),
+            Pitch(x) => write!(f, "PITCH V{:X}", x),
+            Unknown(opcode) => write!(f, "DW 0x{:04X}", opcode),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Instruction::*;
+
+    // An opcode of every class
+    const SAMPLES: &[(u16, &str)] = &[
+        (0x00E0, "00E0"), (0x00EE, "00EE"), (0x0123, "0NNN"), (0x1234, "1NNN"), (0x2345, "2NNN"),
+        (0x3A12, "3XKK"), (0x4B34, "4XKK"), (0x5120, "5XY0"), (0x6C56, "6XKK"), (0x7D78, "7XKK"),
+        (0x8120, "8XY0"), (0x8121, "8XY1"), (0x8122, "8XY2"), (0x8123, "8XY3"), (0x8124, "8XY4"),
+        (0x8125, "8XY5"), (0x8126, "8XY6"), (0x8127, "8XY7"), (0x812E, "8XYE"), (0x9120, "9XY0"),
+        (0xA456, "ANNN"), (0xB567, "BNNN"), (0xC1FF, "CXKK"), (0xD125, "DXYN"), (0xE19E, "EX9E"),
+        (0xE1A1, "EXA1"), (0xF107, "FX07"), (0xF10A, "FX0A"), (0xF115, "FX15"), (0xF118, "FX18"),
+        (0xF11E, "FX1E"), (0xF129, "FX29"), (0xF133, "FX33"), (0xF155, "FX55"), (0xF165, "FX65"),
+        (0x00C4, "00CN"), (0x00FB, "00FB"), (0x00FC, "00FC"), (0x00FD, "00FD"), (0x00FE, "00FE"),
+        (0x00FF, "00FF"), (0xF130, "FX30"), (0xF175, "FX75"), (0xF185, "FX85"), (0x00D3, "00DN"),
+        (0x5122, "5XY2"), (0x5123, "5XY3"), (0xF000, "F000"), (0xF201, "FN01"), (0xF002, "F002"),
+        (0xF13A, "FX3A"),
+    ];
+
+    #[test]
+    fn decodes_every_class() {
+        for &(opcode, class) in SAMPLES {
+            assert_eq!(decode(opcode, false).class(), Some(class), "{:04X}", opcode);
+        }
+        for class in CLASSES {
+            assert!(SAMPLES.iter().any(|&(_, c)| c == *class), "no sample of {}", class);
+        }
+    }
+
+    #[test]
+    fn decodes_operands() {
+        assert_eq!(decode(0x1234, false), Jp(0x234));
+        assert_eq!(decode(0x3A12, false), SeImm { x: 0xA, byte: 0x12 });
+        assert_eq!(decode(0x8AB4, false), AddReg { x: 0xA, y: 0xB });
+        assert_eq!(decode(0xD12F, false), Drw { x: 1, y: 2, n: 0xF });
+        assert_eq!(decode(0xFE65, false), LdVxI(0xE));
+        assert_eq!(decode(0xF301, false), Plane(3));
+    }
+
+    #[test]
+    fn rejects_unknown_opcodes() {
+        for opcode in [0x5121, 0x8128, 0x9121, 0xE100, 0xF1FF, 0xF100, 0xF102] {
+            assert_eq!(decode(opcode, false), Unknown(opcode), "{:04X}", opcode);
+            assert_eq!(decode(opcode, false).class(), None);
+        }
+    }
+
+    #[test]
+    fn clears_with_0230_only_in_hires() {
+        assert_eq!(decode(0x0230, true), Cls);
+        assert_eq!(decode(0x0230, false), Sys(0x230));
+    }
+
+    #[test]
+    fn formats_mnemonics() {
+        assert_eq!(decode(0x00EE, false).to_string(), "RET");
+        assert_eq!(decode(0x2345, false).to_string(), "CALL 0x345");
+        assert_eq!(decode(0x7A01, false).to_string(), "ADD VA, 0x01");
+        assert_eq!(decode(0xD125, false).to_string(), "DRW V1, V2, 5");
+        assert_eq!(decode(0x5123, false).to_string(), "LOAD V1 - V2");
+        assert_eq!(decode(0xFFFF, false).to_string(), "DW 0xFFFF");
+    }
+
+    #[test]
+    fn classifies_instructions() {
+        assert!(decode(0xE19E, false).is_skip());
+        assert!(!decode(0x1234, false).is_skip());
+        assert!(decode(0xD120, false).is_schip());
+        assert!(!decode(0xD125, false).is_schip());
+        assert!(decode(0xF002, false).is_xochip());
+        assert_eq!(decode(0xF000, false).size(), 4);
+        assert_eq!(decode(0x6000, false).size(), 2);
+    }
+}
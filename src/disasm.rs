@@ -0,0 +1,165 @@
+// `disasm` subcommand: annotated listing with cross-references
+
+use std::fs;
+
+use crate::analysis::{Analysis, EdgeKind, RefKind};
+use crate::rom;
+
+const DATA_BYTES_PER_LINE: usize = 8;
+
+fn verb(kind: RefKind) -> &'static str {
+    match kind {
+        RefKind::Call => "called from",
+        RefKind::Jump => "jumped to from",
+        RefKind::ComputedJump => "computed jump from",
+        RefKind::Index => "loaded into I at",
+    }
+}
+
+// `; called from 0x224, 0x310; jumped to from 0x2A0`
+fn incoming_comment(analysis: &Analysis, addr: u16) -> String {
+    let mut parts = Vec::new();
+    for kind in [RefKind::Call, RefKind::Jump, RefKind::ComputedJump, RefKind::Index] {
+        let sources: Vec<String> = analysis.incoming(addr).iter()
+            .filter(|r| r.kind == kind)
+            .map(|r| format!("0x{:03X}", r.from))
+            .collect();
+        if !sources.is_empty() {
+            parts.push(format!("{} {}", verb(kind), sources.join(", ")));
+        }
+    }
+    parts.join("; ")
+}
+
+fn outgoing_comment(analysis: &Analysis, addr: u16) -> Option<String> {
+    let targets: Vec<String> = analysis.outgoing(addr).iter()
+        .map(|&(target, _)| analysis.label(target).unwrap_or_else(|| format!("0x{:03X}", target)))
+        .collect();
+    if targets.is_empty() {
+        None
+    } else {
+        Some(format!("-> {}", targets.join(", ")))
+    }
+}
+
+pub fn listing(analysis: &Analysis) -> String {
+    let mut out = String::new();
+    let end = analysis.end();
+    let mut next = chip8_core::START_ADDRESS as usize;
+
+    while next < end {
+        let addr = next as u16;
+        if let Some(label) = analysis.label(addr) {
+            out += &format!("\n{:<31}; {}\n", format!("{}:", label), incoming_comment(analysis, addr));
+        }
+
+        if analysis.code.contains(&addr) {
+            let opcode = analysis.opcode(addr).unwrap();
+            let instruction = analysis.instruction(addr).unwrap();
+            let text = format!("0x{:03X}  {:04X}  {}", addr, opcode, instruction);
+            match outgoing_comment(analysis, addr) {
+                Some(comment) => out += &format!("{:<31}; {}\n", text, comment),
+                None => out += &format!("{}\n", text),
+            }
+            next += instruction.size() as usize;
+            continue;
+        }
+
+        // Raw bytes up to the next instruction or label
+        let start = addr;
+        let mut bytes = Vec::new();
+        while next < end && bytes.len() < DATA_BYTES_PER_LINE {
+            let addr = next as u16;
+            if addr != start && (analysis.code.contains(&addr) || analysis.label(addr).is_some()) {
+                break;
+            }
+            bytes.push(format!("{:02X}", analysis.byte(addr).unwrap()));
+            next += 1;
+        }
+        out += &format!("{:<31}; data\n", format!("0x{:03X}  {}", start, bytes.join(" ")));
+    }
+
+    out
+}
+
+// Control-flow graph in Graphviz DOT, one node per basic block
+pub fn cfg_dot(analysis: &Analysis) -> String {
+    let mut out = String::from("digraph cfg {\n    node [shape=box, fontname=\"monospace\"];\n");
+
+    for block in analysis.blocks() {
+        let mut label = analysis.label(block.start).map(|l| format!("{}:\\l", l)).unwrap_or_default();
+        let mut addr = block.start as usize;
+        while addr < block.end {
+            let instruction = analysis.instruction(addr as u16).unwrap();
+            label += &format!("0x{:03X}  {}\\l", addr, instruction);
+            addr += instruction.size() as usize;
+        }
+        out += &format!("    b{:03X} [label=\"{}\"];\n", block.start, label);
+
+        for (target, kind) in block.edges {
+            let style = match kind {
+                EdgeKind::Fallthrough => "",
+                EdgeKind::Jump => " [label=\"jp\"]",
+                EdgeKind::ComputedJump => " [label=\"jp v0\", style=dotted]",
+                EdgeKind::Call => " [label=\"call\", style=dashed]",
+                EdgeKind::Skip => " [label=\"skip\"]",
+            };
+            out += &format!("    b{:03X} -> b{:03X}{};\n", block.start, target, style);
+        }
+    }
+
+    out += "}\n";
+    out
+}
+
+// `disasm <ROM> [--cfg OUT.dot]`
+pub fn run(program: &str, args: &[String]) -> i32 {
+    let mut rom_path = None;
+    let mut cfg_path = None;
+
+    let mut iter = args.iter();
+    let parsed = (|| {
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--cfg" => cfg_path = Some(iter.next()?),
+                _ if rom_path.is_none() => rom_path = Some(arg),
+                _ => return None,
+            }
+        }
+        Some(())
+    })();
+
+    let rom_path = match (parsed, rom_path) {
+        (Some(()), Some(path)) => path,
+        _ => {
+            eprintln!("Usage: {} disasm <ROM> [--cfg OUT.dot]\n", program);
+            return 1;
+        }
+    };
+
+    let rom = match rom::read(rom_path) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("Error reading {}: {}", rom_path, e);
+            return 1;
+        }
+    };
+
+    if let Err(e) = chip8_core::check_rom_size(&rom) {
+        eprintln!("Error loading {}: {}", rom_path, e);
+        return 1;
+    }
+    let analysis = Analysis::new(&rom);
+
+    match cfg_path {
+        Some(path) => match fs::write(path, cfg_dot(&analysis)) {
+            Ok(()) => println!("Wrote {}", path),
+            Err(e) => {
+                eprintln!("Error writing {}: {}", path, e);
+                return 1;
+            }
+        },
+        None => print!("{}", listing(&analysis)),
+    }
+    0
+}
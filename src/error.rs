@@ -0,0 +1,55 @@
+// Errors of the interpreter core
+//
+// Rather than panicking or carrying on with garbage, loading a ROM that
+// doesn't fit and running an instruction that can't be executed report what
+// went wrong, and where. The machine is left as it was before the failed
+// instruction, PC included, so a debugger can show it.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum Chip8Error {
+    // Reading the ROM, for frontends that load it through the core's error type
+    Io(io::Error),
+    // `size` bytes of ROM with room for `max` from 0x200
+    RomTooLarge { size: usize, max: usize },
+    // CALL with all 16 stack levels in use
+    StackOverflow { pc: u16 },
+    // RET with nothing to return to
+    StackUnderflow { pc: u16 },
+    // An opcode no instruction of the current mode decodes to
+    InvalidOpcode { pc: u16, opcode: u16 },
+    // The PC has run off the end of the memory the mode can address, 4KB or
+    // XO-CHIP's 64KB
+    MemoryOutOfBounds { pc: u16 },
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Chip8Error::Io(e) => write!(f, "{}", e),
+            Chip8Error::RomTooLarge { size, max } => write!(f, "ROM is {} bytes, only {} fit in memory", size, max),
+            Chip8Error::StackOverflow { pc } => write!(f, "stack overflow at 0x{:03X}, more than 16 nested calls", pc),
+            Chip8Error::StackUnderflow { pc } => write!(f, "stack underflow at 0x{:03X}, a return with no call", pc),
+            Chip8Error::InvalidOpcode { pc, opcode } => write!(f, "invalid opcode {:04X} at 0x{:03X}", opcode, pc),
+            Chip8Error::MemoryOutOfBounds { pc } => write!(f, "ran off the end of memory at 0x{:03X}", pc),
+        }
+    }
+}
+
+impl Error for Chip8Error {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Chip8Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Chip8Error {
+    fn from(e: io::Error) -> Chip8Error {
+        Chip8Error::Io(e)
+    }
+}
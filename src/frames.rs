@@ -0,0 +1,68 @@
+// Frame-by-frame emulation without a window
+//
+// `Chip8::frames` turns a sequence of per-frame keypad states into owned
+// framebuffers, one per 60Hz frame, for tools that render videos, thumbnails or
+// analyses of a ROM. A ROM that fails ends the frames with its error. The
+// chipeight binary's `render` command writes them out as images.
+
+use crate::error::Chip8Error;
+use crate::timing::Timing;
+use crate::Chip8;
+
+// The visible display after a frame
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Frame {
+    pub width: u32,
+    pub height: u32,
+    // Row-major, one u32 per pixel as in Chip8::active_video
+    pub pixels: Vec<u32>,
+}
+
+impl Frame {
+    pub fn pixel(&self, x: u32, y: u32) -> bool {
+        self.pixels[(y * self.width + x) as usize] != 0
+    }
+}
+
+pub struct Frames<'a, I> {
+    chip8: &'a mut Chip8,
+    inputs: I,
+    timing: Timing,
+    failed: bool,
+}
+
+impl<I: Iterator<Item = [u8; 16]>> Iterator for Frames<'_, I> {
+    type Item = Result<Frame, Chip8Error>;
+
+    fn next(&mut self) -> Option<Result<Frame, Chip8Error>> {
+        if self.failed {
+            return None;
+        }
+        *self.chip8.keypad_mut() = self.inputs.next()?;
+        if let Err(e) = self.timing.run_frame(self.chip8) {
+            self.failed = true;
+            return Some(Err(e));
+        }
+        self.chip8.tick_timers();
+
+        Some(Ok(Frame {
+            width: self.chip8.video_width(),
+            height: self.chip8.video_height(),
+            pixels: self.chip8.active_video().to_vec(),
+        }))
+    }
+}
+
+impl<I> Frames<'_, I> {
+    // Decides the instructions per frame with a cycle-cost table instead
+    pub fn with_timing(self, timing: Timing) -> Self {
+        Frames { timing, ..self }
+    }
+}
+
+impl Chip8 {
+    // Runs one frame per keypad state in `inputs`, yielding the display after each
+    pub fn frames<I: IntoIterator<Item = [u8; 16]>>(&mut self, inputs: I) -> Frames<'_, I::IntoIter> {
+        Frames { chip8: self, inputs: inputs.into_iter(), timing: Timing::default(), failed: false }
+    }
+}
@@ -0,0 +1,411 @@
+// Random program generator, fuzzer and differential tester
+//
+// Programs are random but structurally valid: every CALL targets a subroutine that
+// returns, subroutines only call "later" subroutines so the call graph is acyclic
+// and the stack can't overflow, and jumps only go forward inside their own block.
+// The main block ends in a self-jump, so a program always settles into a halt loop.
+//
+// `fuzz` runs each program for the panics it turns up, and differentially: the
+// same program has to end in the same machine state when it's run
+//
+//   - straight through, or saved and restored half way (Chip8::save_state)
+//   - in CHIP-8 mode, or in SCHIP mode with the CHIP-8 quirks, unless it has a
+//     Dxy0, which draws a 16x16 sprite in SCHIP mode and nothing in CHIP-8 mode
+//
+// since none of that may change what a CHIP-8 instruction does.
+
+use std::fs;
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use chip8_core::quirks::Quirks;
+use chip8_core::{Chip8, START_ADDRESS};
+
+// I is only ever pointed into this region, so stores can't overwrite code
+const DATA_START: u16 = 0xE00;
+const DATA_END: u16 = 0x1000;
+const MAX_SUBROUTINES: usize = 8;
+
+const DEFAULT_LENGTH: usize = 64;
+const DEFAULT_ITERATIONS: u64 = 1000;
+const DEFAULT_CYCLES: u64 = 5000;
+
+// A single instruction slot, resolved to an opcode once the layout is known
+#[derive(Clone, Copy)]
+enum Item {
+    Raw(u16),
+    // 1nnn to another item of the same block
+    Jump(usize),
+    // 2nnn to the start of a subroutine
+    Call(usize),
+    // Bnnn landing on an item of the same block, with V0 already set to `v0`
+    JumpV0 { v0: u8, target: usize },
+}
+
+struct Generator {
+    rng: StdRng,
+}
+
+impl Generator {
+    fn new(seed: u64) -> Generator {
+        Generator { rng: StdRng::seed_from_u64(seed) }
+    }
+
+    fn reg(&mut self) -> u16 {
+        self.rng.gen_range(0..16)
+    }
+
+    fn byte(&mut self) -> u16 {
+        self.rng.gen::<u8>() as u16
+    }
+
+    fn data_address(&mut self) -> u16 {
+        // Leave room for I + 255 (Fx1E) + 16 bytes (Fx55)
+        self.rng.gen_range(DATA_START..DATA_END - 0x110)
+    }
+
+    // An instruction that never changes control flow
+    fn plain(&mut self) -> u16 {
+        let (x, y) = (self.reg(), self.reg());
+        match self.rng.gen_range(0..14) {
+            0 => 0x00E0,
+            1 => 0x6000 | (x << 8) | self.byte(),
+            2 => 0x7000 | (x << 8) | self.byte(),
+            3 => {
+                let op = [0x0, 0x1, 0x2, 0x3, 0x4, 0x5, 0x6, 0x7, 0xE][self.rng.gen_range(0..9)];
+                0x8000 | (x << 8) | (y << 4) | op
+            }
+            4 => 0xA000 | self.data_address(),
+            5 => 0xC000 | (x << 8) | self.byte(),
+            6 => 0xD000 | (x << 8) | (y << 4) | self.rng.gen_range(0..16),
+            7 => 0xF007 | (x << 8),
+            8 => 0xF015 | (x << 8),
+            9 => 0xF018 | (x << 8),
+            10 => 0xF029 | (x << 8),
+            11 => 0xF033 | (x << 8),
+            12 => 0xF055 | (x << 8),
+            _ => 0xF065 | (x << 8),
+        }
+    }
+
+    // A skip instruction; the item after it must be a single instruction
+    fn skip(&mut self) -> u16 {
+        let (x, y) = (self.reg(), self.reg());
+        match self.rng.gen_range(0..6) {
+            0 => 0x3000 | (x << 8) | self.byte(),
+            1 => 0x4000 | (x << 8) | self.byte(),
+            2 => 0x5000 | (x << 8) | (y << 4),
+            3 => 0x9000 | (x << 8) | (y << 4),
+            4 => 0xE09E | (x << 8),
+            _ => 0xE0A1 | (x << 8),
+        }
+    }
+
+    // Body of block `block` out of `blocks`, without its terminator
+    fn body(&mut self, block: usize, blocks: usize, len: usize) -> Vec<Item> {
+        // Jump targets are patched once the block length is final
+        const PENDING: usize = usize::MAX;
+
+        let mut items = Vec::with_capacity(len + 2);
+        // Second halves of two-instruction sequences, which jumps must not land on
+        let mut tails = Vec::new();
+        let mut after_skip = false;
+        while items.len() < len {
+            let callable = block + 1..blocks;
+            let roll = self.rng.gen_range(0..20);
+            if after_skip {
+                after_skip = false;
+                match roll {
+                    0..=1 if !callable.is_empty() => items.push(Item::Call(self.rng.gen_range(callable))),
+                    2 => items.push(Item::Jump(PENDING)),
+                    _ => items.push(Item::Raw(self.plain())),
+                }
+                continue;
+            }
+            match roll {
+                0..=3 => {
+                    items.push(Item::Raw(self.skip()));
+                    after_skip = true;
+                }
+                4 if !callable.is_empty() => items.push(Item::Call(self.rng.gen_range(callable))),
+                5 => items.push(Item::Jump(PENDING)),
+                6 => {
+                    let v0 = self.rng.gen_range(0..8) * 2;
+                    items.push(Item::Raw(0x6000 | v0 as u16));
+                    tails.push(items.len());
+                    items.push(Item::JumpV0 { v0, target: PENDING });
+                }
+                7 => {
+                    // Fx1E only right after I has been pointed back into the data region
+                    items.push(Item::Raw(0xA000 | self.data_address()));
+                    tails.push(items.len());
+                    items.push(Item::Raw(0xF01E | (self.reg() << 8)));
+                }
+                _ => items.push(Item::Raw(self.plain())),
+            }
+        }
+        if after_skip {
+            items.push(Item::Raw(self.plain()));
+        }
+
+        // Forward jumps land on any later instruction boundary, terminator included
+        let end = items.len();
+        for (idx, item) in items.iter_mut().enumerate() {
+            match item {
+                Item::Jump(target) | Item::JumpV0 { target, .. } if *target == PENDING => {
+                    let landings: Vec<usize> = (idx + 1..=end).filter(|t| !tails.contains(t)).collect();
+                    *target = landings[self.rng.gen_range(0..landings.len())];
+                }
+                _ => {}
+            }
+        }
+        items
+    }
+
+    // Generates a program of roughly `len` instructions
+    fn program(&mut self, len: usize) -> Vec<u8> {
+        let len = len.max(1);
+        let subroutines = self.rng.gen_range(0..=MAX_SUBROUTINES.min(len / 4));
+        let blocks = subroutines + 1;
+        let per_block = (len / blocks).max(1);
+
+        let mut layout: Vec<Vec<Item>> = Vec::with_capacity(blocks);
+        for block in 0..blocks {
+            let mut items = self.body(block, blocks, per_block);
+            let terminator = if block == 0 { Item::Jump(items.len()) } else { Item::Raw(0x00EE) };
+            items.push(terminator);
+            layout.push(items);
+        }
+
+        let mut starts = Vec::with_capacity(blocks);
+        let mut addr = START_ADDRESS;
+        for items in &layout {
+            starts.push(addr);
+            addr += 2 * items.len() as u16;
+        }
+        assert!(addr <= DATA_START, "generated program overlaps the data region");
+
+        let mut rom = Vec::with_capacity((addr - START_ADDRESS) as usize);
+        for (block, items) in layout.iter().enumerate() {
+            let at = |idx: usize| starts[block] + 2 * idx as u16;
+            for item in items {
+                let opcode = match *item {
+                    Item::Raw(op) => op,
+                    Item::Jump(target) => 0x1000 | at(target),
+                    Item::Call(sub) => 0x2000 | starts[sub],
+                    Item::JumpV0 { v0, target } => 0xB000 | (at(target) - v0 as u16),
+                };
+                rom.extend_from_slice(&opcode.to_be_bytes());
+            }
+        }
+        rom
+    }
+}
+
+// Largest program that still fits below the data region
+fn clamp_length(len: usize) -> usize {
+    len.min(((DATA_START - START_ADDRESS) / 2) as usize / 2)
+}
+
+pub fn generate(seed: u64, len: usize) -> Vec<u8> {
+    Generator::new(seed).program(clamp_length(len))
+}
+
+fn parse_number(text: &str) -> Option<u64> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+// Runs the instructions numbered `cycles`, stopping at the first error
+fn run(chip8: &mut Chip8, cycles: Range<u64>) -> Result<(), String> {
+    for cycle in cycles {
+        chip8.cycle().map_err(|e| format!("cycle {}: {}", cycle, e))?;
+    }
+    Ok(())
+}
+
+// A machine with `rom` loaded, in SCHIP mode or not, with the CHIP-8 quirks either
+// way and the RNG seeded with `seed`
+fn machine(rom: &[u8], seed: u64, schip: bool) -> Chip8 {
+    let mut chip8 = Chip8::new();
+    chip8.seed(seed);
+    chip8.set_schip(schip);
+    chip8.set_quirks(Quirks::for_mode(false, false));
+    chip8.load_rom(rom).expect("generated ROM doesn't fit");
+    chip8
+}
+
+// Describes how two runs that should have agreed came out differently
+fn compare(what: &str, (chip8, result): (&Chip8, Result<(), String>), (expected, ended): (&Chip8, &Result<(), String>)) -> Option<String> {
+    if result != *ended {
+        Some(format!("{}: {:?} instead of {:?}", what, result, ended))
+    } else if chip8.state_hash() != expected.state_hash() {
+        Some(format!("{}: state {:016x} instead of {:016x}", what, chip8.state_hash(), expected.state_hash()))
+    } else {
+        None
+    }
+}
+
+// How `rom` comes out differently run in ways that should agree, if it does
+fn differences(rom: &[u8], seed: u64, cycles: u64) -> Vec<String> {
+    let mut straight = machine(rom, seed, false);
+    let ended = run(&mut straight, 0..cycles);
+    let mut differences = Vec::new();
+
+    // The second half runs on a fresh machine, so it only has what the state kept
+    let mut first = machine(rom, seed, false);
+    let difference = match run(&mut first, 0..cycles / 2) {
+        Err(e) => compare("save/load", (&first, Err(e)), (&straight, &ended)),
+        Ok(()) => {
+            let mut restored = machine(rom, seed, false);
+            let result = restored.load_state(&first.save_state()).and_then(|()| run(&mut restored, cycles / 2..cycles));
+            compare("save/load", (&restored, result), (&straight, &ended))
+        }
+    };
+    differences.extend(difference);
+
+    let draws_16x16 = rom.chunks(2).any(|word| word[0] >> 4 == 0xD && word.get(1).is_some_and(|&low| low & 0xF == 0));
+    if !draws_16x16 {
+        // SCHIP mode keeps its big font below the program, where CHIP-8 has zeroes
+        let mut schip = machine(rom, seed, true);
+        let mut chip8 = machine(rom, seed, false);
+        chip8.memory[..START_ADDRESS as usize].copy_from_slice(&schip.memory[..START_ADDRESS as usize]);
+        let ended = run(&mut chip8, 0..cycles);
+        let result = run(&mut schip, 0..cycles);
+        differences.extend(compare("schip", (&schip, result), (&chip8, &ended)));
+    }
+    differences
+}
+
+// `gen <OUT> [--seed N] [--len N]`
+pub fn run_gen(program: &str, args: &[String]) -> i32 {
+    let mut out = None;
+    let mut seed = rand::thread_rng().gen();
+    let mut len = DEFAULT_LENGTH;
+
+    let mut iter = args.iter();
+    let parsed = (|| {
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--seed" => seed = parse_number(iter.next()?)?,
+                "--len" => len = parse_number(iter.next()?)? as usize,
+                _ if out.is_none() => out = Some(PathBuf::from(arg)),
+                _ => return None,
+            }
+        }
+        Some(())
+    })();
+
+    let out = match (parsed, out) {
+        (Some(()), Some(out)) => out,
+        _ => {
+            eprintln!("Usage: {} gen <OUT> [--seed N] [--len N]\n", program);
+            return 1;
+        }
+    };
+
+    match fs::write(&out, generate(seed, len)) {
+        Ok(()) => {
+            println!("Wrote {} (seed {})", out.display(), seed);
+            0
+        }
+        Err(e) => {
+            eprintln!("Error writing {}: {}", out.display(), e);
+            1
+        }
+    }
+}
+
+// `fuzz [--seed N] [--iterations N] [--len N] [--cycles N] [--out DIR]`
+pub fn run_fuzz(program: &str, args: &[String]) -> i32 {
+    let mut seed = rand::thread_rng().gen::<u32>() as u64;
+    let mut iterations = DEFAULT_ITERATIONS;
+    let mut len = DEFAULT_LENGTH;
+    let mut cycles = DEFAULT_CYCLES;
+    let mut out: Option<PathBuf> = None;
+
+    let mut iter = args.iter();
+    let parsed = (|| {
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--seed" => seed = parse_number(iter.next()?)?,
+                "--iterations" => iterations = parse_number(iter.next()?)?,
+                "--len" => len = parse_number(iter.next()?)? as usize,
+                "--cycles" => cycles = parse_number(iter.next()?)?,
+                "--out" => out = Some(PathBuf::from(iter.next()?)),
+                _ => return None,
+            }
+        }
+        Some(())
+    })();
+
+    if parsed.is_none() {
+        eprintln!("Usage: {} fuzz [--seed N] [--iterations N] [--len N] [--cycles N] [--out DIR]\n", program);
+        return 1;
+    }
+
+    // Panics are the findings here, report them ourselves instead of through the hook
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+
+    let mut failures = 0;
+    for case in seed..seed.saturating_add(iterations) {
+        let rom = generate(case, len);
+        // A ROM failing with an error is the core doing its job, only panics and
+        // runs that disagree count
+        let result = panic::catch_unwind(AssertUnwindSafe(|| differences(&rom, case, cycles)));
+
+        let found = match result {
+            Ok(differences) => differences,
+            Err(payload) => vec![payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_default()],
+        };
+        if !found.is_empty() {
+            failures += 1;
+            for message in found {
+                println!("FAIL  seed {}: {}", case, message);
+            }
+
+            if let Some(dir) = &out {
+                let path = dir.join(format!("fuzz-{}.ch8", case));
+                if let Err(e) = fs::write(&path, &rom) {
+                    eprintln!("Error writing {}: {}", path.display(), e);
+                }
+            }
+        }
+    }
+
+    panic::set_hook(default_hook);
+
+    println!("\n{} programs from seed {}, {} failed", iterations, seed, failures);
+    if failures > 0 { 1 } else { 0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_programs_agree() {
+        for seed in 0..50 {
+            assert_eq!(differences(&generate(seed, DEFAULT_LENGTH), seed, 2000), Vec::<String>::new(), "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn reports_runs_that_disagree() {
+        // HIGH is machine code to CHIP-8, then a halt loop
+        let found = differences(&[0x00, 0xff, 0x12, 0x02], 0, 10);
+        assert_eq!(found.len(), 1);
+        assert!(found[0].starts_with("schip: "), "{}", found[0]);
+    }
+}
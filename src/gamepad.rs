@@ -0,0 +1,162 @@
+// Game controller input
+//
+// The first controller SDL reports is bound to the keypad. Controllers can come
+// and go while running: when the bound one is unplugged the next available one
+// takes over, and a controller plugged in with none bound is picked up.
+//
+// Buttons go by SDL's names for them (a, b, x, y, back, guide, start,
+// leftstick, rightstick, leftshoulder, rightshoulder, dpup, dpdown, dpleft,
+// dpright) and can be bound in the config file, for every game in [controller]
+// and for one in [games.<ROM name>.controller], since games move with
+// different keys:
+//
+//   [controller]
+//   start = "5"
+//
+//   [games.brix.controller]
+//   dpleft = "4"
+//   dpright = "6"
+//   a = "none"
+
+use std::collections::{HashMap, HashSet};
+
+use sdl2::controller::{Button, GameController};
+use sdl2::event::Event;
+use sdl2::{GameControllerSubsystem, Sdl};
+
+// D-pad on the 2/4/6/8 directions most games use, face buttons around them
+const BUTTONS: &[(Button, u8)] = &[
+    (Button::DPadUp, 0x2),
+    (Button::DPadLeft, 0x4),
+    (Button::DPadRight, 0x6),
+    (Button::DPadDown, 0x8),
+    (Button::A, 0x5),
+    (Button::B, 0x0),
+    (Button::X, 0x7),
+    (Button::Y, 0x9),
+    (Button::Back, 0xE),
+    (Button::Start, 0xF),
+];
+
+// Controller button to keypad key bindings
+#[derive(Clone)]
+pub struct ButtonMap {
+    bindings: Vec<(Button, u8)>,
+}
+
+impl Default for ButtonMap {
+    fn default() -> ButtonMap {
+        ButtonMap { bindings: BUTTONS.to_vec() }
+    }
+}
+
+impl ButtonMap {
+    // `button = "K"` pairs as a [controller] table has them, on top of these bindings
+    pub fn apply_config(&mut self, bindings: &HashMap<String, String>) -> Result<(), String> {
+        for (name, key) in bindings {
+            self.bind(name, key)?;
+        }
+        Ok(())
+    }
+
+    // Binds the button called `name` to keypad key `key` (a hex digit), or
+    // leaves it doing nothing for `none`
+    pub fn bind(&mut self, name: &str, key: &str) -> Result<(), String> {
+        let button = Button::from_string(&name.to_ascii_lowercase()).ok_or_else(|| format!("unknown button `{}`", name))?;
+        self.bindings.retain(|&(b, _)| b != button);
+        if key.eq_ignore_ascii_case("none") {
+            return Ok(());
+        }
+        let key = u8::from_str_radix(key, 16).ok().filter(|&k| k < 16)
+            .ok_or_else(|| format!("`{}` is not a keypad key (0-F) or none", key))?;
+        self.bindings.push((button, key));
+        Ok(())
+    }
+
+    // Bindings that differ from the defaults, in the form apply_config reads
+    pub fn to_config(&self) -> HashMap<String, String> {
+        let mut changed: HashMap<String, String> = self.bindings.iter()
+            .filter(|binding| !BUTTONS.contains(binding))
+            .map(|&(button, key)| (button.string(), format!("{:X}", key)))
+            .collect();
+        for &(button, _) in BUTTONS {
+            if !self.bindings.iter().any(|&(b, _)| b == button) {
+                changed.insert(button.string(), "none".to_string());
+            }
+        }
+        changed
+    }
+
+    pub fn bindings(&self) -> &[(Button, u8)] {
+        &self.bindings
+    }
+}
+
+pub struct Gamepad {
+    subsystem: GameControllerSubsystem,
+    active: Option<GameController>,
+    held: HashSet<Button>,
+    buttons: ButtonMap,
+}
+
+impl Gamepad {
+    pub fn new(sdl_context: &Sdl, buttons: ButtonMap) -> Result<Gamepad, String> {
+        // Controllers present at startup arrive as ControllerDeviceAdded events too
+        Ok(Gamepad { subsystem: sdl_context.game_controller()?, active: None, held: HashSet::new(), buttons })
+    }
+
+    pub fn buttons(&self) -> &ButtonMap {
+        &self.buttons
+    }
+
+    // Rebinds the buttons, for a newly opened game
+    pub fn set_buttons(&mut self, buttons: ButtonMap) {
+        self.buttons = buttons;
+    }
+
+    // Opens the first available controller, if any
+    fn bind_first(&mut self) -> Option<String> {
+        let count = self.subsystem.num_joysticks().unwrap_or(0);
+        let controller = (0..count)
+            .filter(|&index| self.subsystem.is_game_controller(index))
+            .find_map(|index| self.subsystem.open(index).ok())?;
+        let notice = format!("Controller connected: {}", controller.name());
+        self.active = Some(controller);
+        Some(notice)
+    }
+
+    // Tracks hotplugging and button state, returning a notice when the bound controller changes
+    pub fn handle(&mut self, event: &Event) -> Option<String> {
+        let active_id = self.active.as_ref().map(|c| c.instance_id());
+
+        match *event {
+            Event::ControllerDeviceAdded { .. } if self.active.is_none() => self.bind_first(),
+            Event::ControllerDeviceRemoved { which, .. } if Some(which) == active_id => {
+                let name = self.active.take().map(|c| c.name()).unwrap_or_default();
+                self.held.clear();
+                match self.bind_first() {
+                    Some(notice) => Some(format!("Controller disconnected: {}. {}", name, notice)),
+                    None => Some(format!("Controller disconnected: {}", name)),
+                }
+            }
+            Event::ControllerButtonDown { which, button, .. } if Some(which) == active_id => {
+                self.held.insert(button);
+                None
+            }
+            Event::ControllerButtonUp { which, button, .. } if Some(which) == active_id => {
+                self.held.remove(&button);
+                None
+            }
+            _ => None,
+        }
+    }
+
+    // Presses the keypad keys of every held button
+    pub fn apply(&self, keys: &mut [u8; 16]) {
+        for &(button, key) in self.buttons.bindings() {
+            if self.held.contains(&button) {
+                keys[key as usize] = 1;
+            }
+        }
+    }
+}
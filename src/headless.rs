@@ -0,0 +1,298 @@
+// `--headless`: run a ROM without a window, for test ROMs in CI
+//
+// The ROM runs for up to `--cycles` instructions with the timers at 60Hz (in
+// frames set by `--ips` or the cycle-cost table of `--timing`, see costs.rs), or
+// until it settles: an instruction that leaves the PC where it was, which is how
+// test ROMs end (a jump to itself, SCHIP's EXIT, or waiting for a key nobody
+// will press). The hash of the display then goes to stdout, the same one `suite`
+// checks against, and `--png` writes the display out as well. The --trace
+// options log the instructions run, as in the window (see tracelog.rs).
+//
+// The buzzer is recorded as an audio timeline (see timeline.rs), with the
+// timers left to run down once the ROM settles so its last beep is heard out.
+// `--audio` writes it to a file, and `--expect-audio` checks it against one,
+// as suite does with the `.audio` files next to its ROMs.
+//
+// The exit status is 0, or 1 for bad arguments, unreadable files, a ROM that
+// fails (the display and hash are still written out, for a look at how far it
+// got) or a display or timeline that doesn't match `--expect` or
+// `--expect-audio`.
+
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::analysis::Analysis;
+use crate::costs;
+use crate::suite::hash_video;
+use crate::timeline::{self, AudioTimeline, Timeline};
+use chip8_core::timing::Timing;
+use crate::tracelog::TraceLog;
+use crate::rom;
+use chip8_core::beep::Beep;
+use chip8_core::error::Chip8Error;
+use chip8_core::palette;
+use chip8_core::quirks::Quirks;
+use chip8_core::trace::SharedTracer;
+use chip8_core::Chip8;
+
+const DEFAULT_CYCLES: u64 = 1_000_000;
+const DEFAULT_SCALE: u32 = 8;
+
+struct Options<'a> {
+    rom: &'a str,
+    cycles: u64,
+    png: Option<&'a str>,
+    scale: u32,
+    expect: Option<u64>,
+    audio: Option<&'a str>,
+    expect_audio: Option<&'a str>,
+    beep: Beep,
+    schip: Option<bool>,
+    xochip: Option<bool>,
+    quirks: Option<Quirks>,
+    hires_detection: bool,
+    ips: Option<u32>,
+    timing: Option<&'a str>,
+    seed: u64,
+    trace: Option<&'a str>,
+    trace_range: Option<&'a str>,
+    trace_last: Option<&'a str>,
+}
+
+fn usage(program: &str) -> i32 {
+    eprintln!("Usage: {} --headless <ROM> [--cycles N] [--png OUT] [--scale N] [--expect HASH]", program);
+    eprintln!("       [--audio OUT] [--expect-audio FILE] [--min-audible N] [--min-beep N]");
+    eprintln!("       [--schip | --chip8 | --xochip] [--quirks SPEC] [--no-hires] [--ips N | --timing FILE]");
+    eprintln!("       [--seed N] [--trace FILE] [--trace-range A-B] [--trace-last N]\n");
+    1
+}
+
+fn parse_args(args: &[String]) -> Option<Options<'_>> {
+    let mut rom = None;
+    let mut options = Options {
+        rom: "",
+        cycles: DEFAULT_CYCLES,
+        png: None,
+        scale: DEFAULT_SCALE,
+        expect: None,
+        audio: None,
+        expect_audio: None,
+        beep: Beep::default(),
+        schip: None,
+        xochip: None,
+        quirks: None,
+        hires_detection: true,
+        ips: None,
+        timing: None,
+        seed: 0,
+        trace: None,
+        trace_range: None,
+        trace_last: None,
+    };
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--cycles" => options.cycles = iter.next()?.parse().ok()?,
+            "--png" => options.png = Some(iter.next()?),
+            "--scale" => options.scale = iter.next()?.parse().ok().filter(|&s| s > 0)?,
+            "--expect" => options.expect = Some(u64::from_str_radix(iter.next()?.trim_start_matches("0x"), 16).ok()?),
+            "--audio" => options.audio = Some(iter.next()?),
+            "--expect-audio" => options.expect_audio = Some(iter.next()?),
+            "--min-audible" => options.beep.min_audible = iter.next()?.parse().ok()?,
+            "--min-beep" => options.beep.min_frames = iter.next()?.parse().ok()?,
+            "--schip" => (options.schip, options.xochip) = (Some(true), Some(false)),
+            "--chip8" => (options.schip, options.xochip) = (Some(false), Some(false)),
+            "--xochip" => options.xochip = Some(true),
+            "--quirks" => {
+                let spec = iter.next()?;
+                options.quirks = Some(Quirks::parse(spec).map_err(|e| eprintln!("Bad --quirks: {}", e)).ok()?);
+            }
+            "--no-hires" => options.hires_detection = false,
+            "--ips" if options.timing.is_none() => options.ips = Some(iter.next()?.parse().ok().filter(|&n| n > 0)?),
+            "--timing" if options.ips.is_none() => options.timing = Some(iter.next()?),
+            "--seed" => options.seed = iter.next()?.parse().ok()?,
+            "--trace" => options.trace = Some(iter.next()?),
+            "--trace-range" => options.trace_range = Some(iter.next()?),
+            "--trace-last" => options.trace_last = Some(iter.next()?),
+            _ if rom.is_none() && !arg.starts_with("--") => rom = Some(arg),
+            _ => return None,
+        }
+    }
+
+    options.rom = rom?;
+    Some(options)
+}
+
+// Why the run ended
+pub enum Stop {
+    Cycles,
+    Settled(u16),
+    Failed(Chip8Error),
+}
+
+// Runs `chip8` until it settles or `max` instructions have run, recording the
+// buzzer in `audio`, and returns why it stopped and how many instructions ran
+pub fn run_until_settled(chip8: &mut Chip8, timing: &mut Timing, max: u64, audio: &mut AudioTimeline) -> (Stop, u64) {
+    let mut cycles = 0;
+    loop {
+        timing.start_frame();
+        while timing.due() {
+            if cycles == max {
+                return (Stop::Cycles, cycles);
+            }
+            let pc = chip8.pc;
+            if let Err(e) = timing.tick(chip8) {
+                return (Stop::Failed(e), cycles);
+            }
+            cycles += 1;
+            if chip8.pc == pc {
+                // Nothing will happen from here on but the timers running down
+                while chip8.beeping() {
+                    audio.record(true);
+                    chip8.tick_timers();
+                }
+                audio.record(false);
+                return (Stop::Settled(pc), cycles);
+            }
+        }
+        audio.record(chip8.beeping());
+        chip8.tick_timers();
+    }
+}
+
+fn write_png(path: &str, chip8: &Chip8, scale: u32) -> Result<(), String> {
+    let (width, height) = (chip8.video_width(), chip8.video_height());
+    let colors = palette::colorize(chip8.active_video(), &palette::DEFAULT);
+
+    let mut image = Vec::with_capacity((width * height * scale * scale * 3) as usize);
+    for row in colors.chunks(width as usize) {
+        let line: Vec<u8> = row.iter()
+            .flat_map(|&argb| std::iter::repeat_n([(argb >> 16) as u8, (argb >> 8) as u8, argb as u8], scale as usize))
+            .flatten()
+            .collect();
+        for _ in 0..scale {
+            image.extend_from_slice(&line);
+        }
+    }
+
+    let file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width * scale, height * scale);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| format!("{}: {}", path, e))?;
+    writer.write_image_data(&image).map_err(|e| format!("{}: {}", path, e))
+}
+
+// `--headless <ROM> ...`, with `args` the arguments other than --headless
+pub fn run(program: &str, args: &[String]) -> i32 {
+    let Some(options) = parse_args(args) else {
+        return usage(program);
+    };
+
+    let rom = match rom::read(options.rom) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("Error reading {}: {}", options.rom, e);
+            return 1;
+        }
+    };
+
+    if let Err(e) = chip8_core::check_rom_size(&rom) {
+        eprintln!("Error loading {}: {}", options.rom, e);
+        return 1;
+    }
+
+    // The same mode and quirks the window would pick
+    let (schip, xochip, quirks) = Analysis::new(&rom).mode(options.schip, options.xochip, options.quirks);
+
+    let mut chip8 = Chip8::new();
+    chip8.set_beep(options.beep);
+    chip8.set_schip(schip);
+    chip8.set_xochip(xochip);
+    chip8.set_quirks(quirks);
+    chip8.set_hires_detection(options.hires_detection);
+    chip8.seed(options.seed);
+    let trace = match (options.trace, options.trace_range, options.trace_last) {
+        (None, None, None) => None,
+        (path, range, last) => match TraceLog::open(path, range, last) {
+            Ok(trace) => Some(Arc::new(trace)),
+            Err(e) => {
+                eprintln!("Bad --trace: {}", e);
+                return 1;
+            }
+        },
+    };
+    chip8.set_tracer(trace.clone().map(|trace| trace as SharedTracer));
+    if let Err(e) = chip8.load_rom(&rom) {
+        eprintln!("Error loading {}: {}", options.rom, e);
+        return 1;
+    }
+
+    let mut timing = match (options.timing, options.ips) {
+        (Some(path), _) => match costs::load(path) {
+            Ok(timing) => timing,
+            Err(e) => {
+                eprintln!("Error loading timing table: {}", e);
+                return 1;
+            }
+        },
+        (None, ips) => ips.map(Timing::from_ips).unwrap_or_default(),
+    };
+    let mut audio = AudioTimeline::default();
+    let mut failed = false;
+    match run_until_settled(&mut chip8, &mut timing, options.cycles, &mut audio) {
+        (Stop::Settled(pc), cycles) => eprintln!("Settled at 0x{:03X} after {} cycles", pc, cycles),
+        (Stop::Cycles, cycles) => eprintln!("Stopped after {} cycles", cycles),
+        (Stop::Failed(e), cycles) => {
+            if let Some(trace) = &trace {
+                trace.dump();
+            }
+            eprintln!("Error after {} cycles: {}", cycles, e);
+            failed = true;
+        }
+    }
+    if let Some(trace) = &trace {
+        trace.flush();
+    }
+
+    if let Some(path) = options.png {
+        if let Err(e) = write_png(path, &chip8, options.scale) {
+            eprintln!("Error writing PNG: {}", e);
+            return 1;
+        }
+    }
+
+    let tones = audio.tones();
+    if let Some(path) = options.audio {
+        if let Err(e) = fs::write(path, Timeline(&tones).to_string()) {
+            eprintln!("Error writing {}: {}", path, e);
+            return 1;
+        }
+    }
+    if let Some(path) = options.expect_audio {
+        match timeline::load(Path::new(path)) {
+            Ok(expected) => if let Some(detail) = timeline::diff(&expected, &tones) {
+                eprintln!("Audio doesn't match: {}", detail);
+                failed = true;
+            },
+            Err(e) => {
+                eprintln!("Error reading {}: {}", path, e);
+                return 1;
+            }
+        }
+    }
+
+    let hash = hash_video(&palette::colorize(chip8.active_video(), &palette::DEFAULT));
+    println!("{:016x}", hash);
+    match options.expect {
+        Some(expected) if expected != hash => {
+            eprintln!("Display doesn't match, expected {:016x}", expected);
+            1
+        }
+        _ if failed => 1,
+        _ => 0,
+    }
+}
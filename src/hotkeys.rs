@@ -0,0 +1,107 @@
+// Emulator-level actions and the host keys that trigger them
+
+use std::collections::HashMap;
+
+use sdl2::keyboard::Keycode;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    Quit,
+    Pause,
+    // Runs one frame and pauses again
+    FrameAdvance,
+    Reset,
+    // Brings back the machine as it was just before a reset, for a few seconds after one
+    UndoReset,
+    SaveState,
+    LoadState,
+    // Opens and closes the save slot browser
+    SlotBrowser,
+    // Writes a .c8session of the running game
+    SaveSession,
+    Screenshot,
+    ScaleUp,
+    ScaleDown,
+    // Marks a split of the speedrun timer
+    Split,
+    // Hides and shows the watch expressions
+    Watches,
+    // Shows which keys the game has been seen using
+    Controls,
+    // Held rather than pressed: runs without the cycle delay while down
+    FastForward,
+    // Held as well: steps back through recent states while down, see rewind.rs
+    Rewind,
+}
+
+// Config name, action and default key of every hotkey
+pub const ACTIONS: &[(&str, Action, Keycode)] = &[
+    ("quit", Action::Quit, Keycode::Escape),
+    ("pause", Action::Pause, Keycode::P),
+    ("frame_advance", Action::FrameAdvance, Keycode::N),
+    ("reset", Action::Reset, Keycode::F2),
+    ("undo_reset", Action::UndoReset, Keycode::F3),
+    ("save_state", Action::SaveState, Keycode::F5),
+    ("load_state", Action::LoadState, Keycode::F9),
+    ("slot_browser", Action::SlotBrowser, Keycode::F7),
+    ("save_session", Action::SaveSession, Keycode::F6),
+    ("screenshot", Action::Screenshot, Keycode::F12),
+    ("scale_up", Action::ScaleUp, Keycode::Equals),
+    ("scale_down", Action::ScaleDown, Keycode::Minus),
+    ("split", Action::Split, Keycode::F4),
+    ("watches", Action::Watches, Keycode::F8),
+    ("controls", Action::Controls, Keycode::F1),
+    ("fast_forward", Action::FastForward, Keycode::Tab),
+    ("rewind", Action::Rewind, Keycode::Backspace),
+];
+
+#[derive(Clone)]
+pub struct Hotkeys {
+    bindings: Vec<(Action, Keycode)>,
+}
+
+impl Hotkeys {
+    // Applies `name = "Key"` overrides from the config file on top of the defaults
+    pub fn from_config(overrides: &HashMap<String, String>) -> Result<Hotkeys, String> {
+        let mut hotkeys = Hotkeys::default();
+        for (name, key) in overrides {
+            let action = ACTIONS.iter()
+                .find(|(n, _, _)| n == name)
+                .map(|&(_, action, _)| action)
+                .ok_or_else(|| format!("unknown hotkey action `{}`", name))?;
+            let keycode = Keycode::from_name(key).ok_or_else(|| format!("unknown key `{}` for {}", key, name))?;
+            hotkeys.bind(action, keycode);
+        }
+        Ok(hotkeys)
+    }
+
+    // Bindings that differ from the defaults, in the form from_config reads
+    pub fn to_config(&self) -> HashMap<String, String> {
+        ACTIONS.iter()
+            .filter(|&&(_, action, default)| self.key(action) != default)
+            .map(|&(name, action, _)| (name.to_string(), self.key(action).name()))
+            .collect()
+    }
+
+    pub fn bind(&mut self, action: Action, keycode: Keycode) {
+        for binding in self.bindings.iter_mut().filter(|(a, _)| *a == action) {
+            binding.1 = keycode;
+        }
+    }
+
+    pub fn key(&self, action: Action) -> Keycode {
+        self.bindings.iter().find(|(a, _)| *a == action).map(|&(_, k)| k).unwrap()
+    }
+
+    pub fn action_for(&self, keycode: Keycode) -> Option<Action> {
+        self.bindings.iter().find(|&&(_, k)| k == keycode).map(|&(action, _)| action)
+    }
+}
+
+impl Default for Hotkeys {
+    fn default() -> Hotkeys {
+        Hotkeys {
+            bindings: ACTIONS.iter().map(|&(_, action, key)| (action, key)).collect(),
+        }
+    }
+}
@@ -0,0 +1,91 @@
+// Introspection flags
+//
+// Print the tables the emulator runs with, after the config file and command
+// line have been applied, so what's listed is what's in effect.
+
+use sdl2::AudioSubsystem;
+
+use crate::audio;
+use crate::gamepad::ButtonMap;
+use crate::hotkeys::{Hotkeys, ACTIONS};
+use crate::keymap::{Keymap, LAYOUTS};
+use crate::turbo::Turbo;
+use chip8_core::palette::{self, PALETTES, PLANES};
+use chip8_core::quirks::{Quirks, PRESETS, QUIRKS};
+
+// `--list-keys`
+pub fn list_keys(keymap: &Keymap, hotkeys: &Hotkeys, turbo: &Turbo, buttons: &ButtonMap) {
+    let layouts: Vec<&str> = LAYOUTS.iter().map(|&(name, _)| name).collect();
+    println!("Keypad layout: {} (available: {})", keymap.name, layouts.join(", "));
+    for key in 0..16 {
+        let names: Vec<String> = keymap.keys_for(key).iter().map(|k| k.name()).collect();
+        println!("  {:X}  {}", key, names.join(", "));
+    }
+
+    println!("\nHotkeys:");
+    for &(name, action, default) in ACTIONS {
+        let key = hotkeys.key(action);
+        if key == default {
+            println!("  {:<14} {}", name, key.name());
+        } else {
+            println!("  {:<14} {} (default {})", name, key.name(), default.name());
+        }
+    }
+
+    if !turbo.bindings().is_empty() {
+        println!("\nTurbo ({} a second):", turbo.rate);
+        for &(keycode, key) in turbo.bindings() {
+            println!("  {:<14} {:X}", keycode.name(), key);
+        }
+    }
+
+    println!("\nController:");
+    for &(button, key) in buttons.bindings() {
+        println!("  {:<14} {:X}", button.string(), key);
+    }
+}
+
+// `--list-quirks`
+pub fn list_quirks(quirks: &Quirks) {
+    match quirks.preset_name() {
+        Some(name) => println!("Quirks: the {} preset", name),
+        None => println!("Quirks:"),
+    }
+    for &(name, description) in QUIRKS.iter() {
+        let on = if quirks.get(name) == Some(true) { "on" } else { "off" };
+        println!("  {:<12} {:<4} {}", name, on, description);
+    }
+}
+
+// `--list-profiles`, marking the one in effect
+pub fn list_profiles(quirks: &Quirks) {
+    println!("Quirk presets:");
+    for &(name, description, preset) in PRESETS.iter() {
+        let current = if preset == *quirks { "*" } else { " " };
+        println!("{} {:<8} {}: {}", current, name, description, preset.enabled().join(", "));
+    }
+}
+
+// `--list-palettes`, marking the one in effect
+pub fn list_palettes(colors: &[u32; 4]) {
+    println!("Colours:");
+    for (name, &color) in PLANES.iter().zip(colors) {
+        println!("  {:<12} {}", name, palette::format_color(color));
+    }
+
+    println!("\nPalettes:");
+    for &(name, description, preset) in PALETTES.iter() {
+        let current = if preset == *colors { "*" } else { " " };
+        let preset: Vec<String> = preset.iter().map(|&color| palette::format_color(color)).collect();
+        println!("{} {:<8} {}: {}", current, name, description, preset.join(" "));
+    }
+}
+
+// `--list-audio-devices`
+pub fn list_audio_devices(audio: &AudioSubsystem) -> Result<(), String> {
+    println!("Audio devices:");
+    for name in audio::device_names(audio)? {
+        println!("  {}", name);
+    }
+    Ok(())
+}
@@ -0,0 +1,115 @@
+// Host keyboard to CHIP-8 keypad layouts
+
+use std::collections::HashMap;
+
+use sdl2::keyboard::Keycode;
+
+// Positional mapping of the 4x4 hex keypad onto the left of a QWERTY keyboard
+//   1 2 3 C      1 2 3 4
+//   4 5 6 D  ->  Q W E R
+//   7 8 9 E      A S D F
+//   A 0 B F      Z X C V
+const QWERTY: &[(Keycode, u8)] = &[
+    (Keycode::X, 0x0),
+    (Keycode::Num1, 0x1),
+    (Keycode::Num2, 0x2),
+    (Keycode::Num3, 0x3),
+    (Keycode::Q, 0x4),
+    (Keycode::W, 0x5),
+    (Keycode::E, 0x6),
+    (Keycode::A, 0x7),
+    (Keycode::S, 0x8),
+    (Keycode::D, 0x9),
+    (Keycode::Z, 0xA),
+    (Keycode::C, 0xB),
+    (Keycode::Num4, 0xC),
+    (Keycode::R, 0xD),
+    (Keycode::F, 0xE),
+    (Keycode::V, 0xF),
+];
+
+// The COSMAC VIP hex keypad by label: every key sends the digit printed on it,
+// from either the number row or the numeric keypad
+const COSMAC: &[(Keycode, u8)] = &[
+    (Keycode::Num0, 0x0),
+    (Keycode::Num1, 0x1),
+    (Keycode::Num2, 0x2),
+    (Keycode::Num3, 0x3),
+    (Keycode::Num4, 0x4),
+    (Keycode::Num5, 0x5),
+    (Keycode::Num6, 0x6),
+    (Keycode::Num7, 0x7),
+    (Keycode::Num8, 0x8),
+    (Keycode::Num9, 0x9),
+    (Keycode::Kp0, 0x0),
+    (Keycode::Kp1, 0x1),
+    (Keycode::Kp2, 0x2),
+    (Keycode::Kp3, 0x3),
+    (Keycode::Kp4, 0x4),
+    (Keycode::Kp5, 0x5),
+    (Keycode::Kp6, 0x6),
+    (Keycode::Kp7, 0x7),
+    (Keycode::Kp8, 0x8),
+    (Keycode::Kp9, 0x9),
+    (Keycode::A, 0xA),
+    (Keycode::B, 0xB),
+    (Keycode::C, 0xC),
+    (Keycode::D, 0xD),
+    (Keycode::E, 0xE),
+    (Keycode::F, 0xF),
+];
+
+pub const LAYOUTS: &[(&str, &[(Keycode, u8)])] = &[
+    ("qwerty", QWERTY),
+    ("cosmac", COSMAC),
+];
+
+#[derive(Clone)]
+pub struct Keymap {
+    pub name: &'static str,
+    bindings: Vec<(Keycode, u8)>,
+}
+
+impl Keymap {
+    pub fn layout(name: &str) -> Option<Keymap> {
+        LAYOUTS.iter()
+            .find(|(layout, _)| layout.eq_ignore_ascii_case(name))
+            .map(|&(name, bindings)| Keymap { name, bindings: bindings.to_vec() })
+    }
+
+    // Keypad key a host key is bound to
+    pub fn key_for(&self, keycode: Keycode) -> Option<u8> {
+        self.bindings.iter().find(|&&(k, _)| k == keycode).map(|&(_, key)| key)
+    }
+
+    // Binds the host key called `name` to keypad key `key` (a hex digit), in
+    // place of whatever the layout had it do
+    pub fn bind(&mut self, name: &str, key: &str) -> Result<(), String> {
+        let keycode = Keycode::from_name(name).ok_or_else(|| format!("unknown key `{}`", name))?;
+        let key = u8::from_str_radix(key, 16).ok().filter(|&k| k < 16)
+            .ok_or_else(|| format!("`{}` is not a keypad key (0-F)", key))?;
+        self.bindings.retain(|&(k, _)| k != keycode);
+        self.bindings.push((keycode, key));
+        Ok(())
+    }
+
+    // Bindings that differ from the layout, in the form bind takes them
+    pub fn to_config(&self) -> HashMap<String, String> {
+        let layout = Keymap::layout(self.name).unwrap_or_default();
+        self.bindings.iter()
+            .filter(|binding| !layout.bindings.contains(binding))
+            .map(|&(keycode, key)| (keycode.name(), format!("{:X}", key)))
+            .collect()
+    }
+
+    // Host keys bound to a keypad key
+    pub fn keys_for(&self, key: u8) -> Vec<Keycode> {
+        self.bindings.iter().filter(|&&(_, k)| k == key).map(|&(keycode, _)| keycode).collect()
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        Keymap::layout("qwerty").unwrap()
+    }
+}
@@ -0,0 +1,1136 @@
+// CHIP-8 interpreter core
+//
+// The machine and nothing else: no window, no audio, no files. A frontend
+// creates a Chip8, hands it a ROM image and drives it, one instruction per
+// `tick` and one timer update per `tick_timers` at 60Hz, then reads back the
+// framebuffer and whether the buzzer should sound:
+//
+//   let mut chip8 = Chip8::new();
+//   chip8.load_rom(&rom)?;
+//   loop {
+//       chip8.keypad_mut()[5] = key_down as u8;
+//       for _ in 0..10 {
+//           chip8.tick()?;
+//       }
+//       chip8.tick_timers();
+//       draw(chip8.active_video(), chip8.video_width(), chip8.video_height());
+//       buzz(chip8.beeping());
+//   }
+//
+// A ROM that doesn't fit, or an instruction that can't run, is a Chip8Error
+// (see error.rs) rather than a panic. A tracer (see trace.rs) can follow every
+// instruction run.
+//
+// timing.rs runs frames by instruction counts or cycle-cost tables instead of a
+// fixed 10 instructions, and frames.rs yields the display after each frame
+// with no frontend at all.
+//
+// renderer::run does the same for any frontend implementing its Renderer trait.
+// The chipeight binary is the SDL2 frontend and chipeight-tui (`--features
+// tui`) the terminal one; building this crate with `default-features = false`
+// leaves SDL2 and the frontend's dependencies out.
+
+pub mod beep;
+pub mod bus;
+mod cdp1802;
+pub mod decode;
+pub mod error;
+pub mod frames;
+pub mod palette;
+pub mod quirks;
+pub mod renderer;
+mod schip;
+mod state;
+pub mod timing;
+pub mod trace;
+mod xochip;
+
+use beep::Beep;
+use bus::SharedPeripheral;
+use decode::decode;
+use error::Chip8Error;
+use quirks::Quirks;
+use trace::{SharedTracer, TraceEntry};
+
+// Chip8’s memory from 0x000 to 0x1FF is reserved, so the ROM instructions must start at 0x200
+pub const START_ADDRESS: u16 = 0x200;
+const FONTSET_START_ADDRESS: u8 = 0x50;
+const FONTSET_SIZE: u32 = 80;
+pub const VIDEO_WIDTH: u32 = 64;
+pub const VIDEO_HEIGHT: u32 = 32;
+// Two-page display of the hi-res VIP interpreter
+pub const HIRES_VIDEO_HEIGHT: u32 = 64;
+// The extended SCHIP display, the largest of all modes
+pub const MAX_VIDEO_WIDTH: u32 = 128;
+pub const MAX_VIDEO_HEIGHT: u32 = 64;
+// Hi-res ROMs start with a jump into the patched interpreter, the program itself begins here
+pub const HIRES_START_ADDRESS: u16 = 0x2C0;
+// Where that jump goes, the interpreter's setup for the two-page display
+const HIRES_SETUP_ADDRESS: u16 = 0x260;
+
+const FONTSET: [u8; 80] = 
+[
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+	0x20, 0x60, 0x20, 0x20, 0x70, // 1
+	0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+	0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+	0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+	0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+	0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+	0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+	0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+	0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+	0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+	0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+	0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+	0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+	0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+	0xF0, 0x80, 0xF0, 0x80, 0x80  // F
+];
+
+
+// Struct for CHIP8 structure
+#[derive(Clone)]
+pub struct Chip8 {
+    // The CPU's registers and memory are open to frontends, for debuggers and the like
+    pub registers: [u8; 16],
+    // 64KB for XO-CHIP, the other machines only address the first 4KB
+    pub memory: [u8; 65536],
+    pub index: u16,
+    pub pc: u16,
+    pub stack: [u16; 16],
+    pub sp: u8,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    keypad: [u8; 16],
+    // Row-major with the current mode's width as the stride, each pixel the
+    // set of planes lit in it (bit 0 for plane 1, bit 1 for plane 2)
+    video: [u32; (MAX_VIDEO_WIDTH * MAX_VIDEO_HEIGHT) as usize],
+    opcode: u16,
+    hires: bool,
+    // SCHIP instructions are decoded, and the 128x64 mode is on, see schip.rs
+    schip: bool,
+    extended: bool,
+    // SCHIP's RPL user flags, 8 on the HP48 and 16 on XO-CHIP
+    rpl: [u8; 16],
+    // XO-CHIP instructions and 64KB addressing, see xochip.rs
+    xochip: bool,
+    // Planes drawing, clearing and scrolling act on, only plane 1 outside XO-CHIP
+    planes: u8,
+    // The XO-CHIP audio pattern, its pitch, and whether F002 has loaded one
+    pattern: [u8; 16],
+    pitch: u8,
+    pattern_loaded: bool,
+    // xorshift64* state behind RND, so runs can be replayed from a seed
+    rng: u64,
+    beep: Beep,
+    // Plugged-in hardware on the bus, see bus.rs
+    peripheral: Option<SharedPeripheral>,
+    // Told about every instruction, see trace.rs
+    tracer: Option<SharedTracer>,
+    // Run 0NNN machine code routines instead of ignoring them, see cdp1802.rs
+    cdp1802: bool,
+    // Which interpreter's take on the ambiguous instructions to follow, see quirks.rs
+    quirks: Quirks,
+    // Keys tested by SKP and SKNP so far, a bit each, and whether LD Vx, K ran
+    polled_keys: u16,
+    waited_for_key: bool,
+    // Start hi-res VIP ROMs in the 64x64 mode, see is_hires_rom
+    detect_hires: bool,
+}
+
+// The core has to stay Send so the suite runner can hand instances to worker threads
+const _: () = {
+    fn assert_send<T: Send>() {}
+    let _ = assert_send::<Chip8>;
+};
+
+// Constructor
+impl Chip8 {
+    pub fn new() -> Chip8 {
+        let mut chip8 = Chip8 {
+            registers: [0; 16],       // Default values for registers
+            memory: [0; 65536],       // Default values for memory
+            index: 0,                 // Default value for index
+            pc: START_ADDRESS,        // Initialize pc to 0x200
+            stack: [0; 16],           // Default values for stack
+            sp: 0,                    // Default value for stack pointer
+            delay_timer: 0,           // Default value for delay timer
+            sound_timer: 0,           // Default value for sound timer
+            keypad: [0; 16],          // Default values for keypad
+            video: [0; (MAX_VIDEO_WIDTH * MAX_VIDEO_HEIGHT) as usize], // Default values for video
+            opcode: 0,                // Default value for opcode
+            hires: false,             // Starts in the regular 64x32 mode
+            schip: false,             // Plain CHIP-8 unless asked for
+            extended: false,
+            rpl: [0; 16],
+            xochip: false,
+            planes: 1,
+            pattern: [0; 16],
+            pitch: 64,                // 4000Hz playback
+            pattern_loaded: false,
+            rng: 0,                   // Seeded below
+            beep: Beep::default(),    // Every non-zero sound timer value beeps
+            peripheral: None,         // Nothing but RAM on the bus
+            tracer: None,
+            cdp1802: false,           // 0NNN is ignored like on most interpreters
+            quirks: Quirks::default(),
+            polled_keys: 0,           // No keys looked at yet
+            waited_for_key: false,
+            detect_hires: true,
+        };
+        chip8.seed(rand::random());
+        chip8.load_fonts();
+        chip8
+    }
+}
+
+impl Default for Chip8 {
+    fn default() -> Chip8 {
+        Chip8::new()
+    }
+}
+
+// The most any mode has room for, XO-CHIP's 64KB from 0x200
+pub const MAX_ROM_SIZE: usize = 0x10000 - START_ADDRESS as usize;
+
+// For a ROM the mode is still to be worked out for; load_rom then holds it to
+// the room in the mode it's loaded in
+pub fn check_rom_size(rom: &[u8]) -> Result<(), Chip8Error> {
+    if rom.len() > MAX_ROM_SIZE {
+        return Err(Chip8Error::RomTooLarge { size: rom.len(), max: MAX_ROM_SIZE });
+    }
+    Ok(())
+}
+
+// Whether `rom` is made for the hi-res VIP interpreter: a leading 1260 jumps
+// over the interpreter's patched 1802 routines to its setup at 0x260, which
+// calls into them with 0NNN (0230 clears the screen, for one) before the program
+// at 0x2C0. A ROM that just happens to start with JP 0x260 makes no such call.
+pub fn is_hires_rom(rom: &[u8]) -> bool {
+    let setup = (HIRES_SETUP_ADDRESS - START_ADDRESS) as usize;
+    let program = (HIRES_START_ADDRESS - START_ADDRESS) as usize;
+    if !rom.starts_with(&[0x12, 0x60]) || rom.len() <= program {
+        return false;
+    }
+    rom[setup..program].chunks_exact(2)
+        .map(|op| u16::from_be_bytes([op[0], op[1]]))
+        .any(|op| op > START_ADDRESS && op < HIRES_SETUP_ADDRESS)
+}
+
+// Copies a ROM image into memory
+impl Chip8 {
+    // The program goes at 0x200; reading it from a file is up to the frontend.
+    // XO-CHIP has the rest of 64KB for it, so set the mode first.
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), Chip8Error> {
+        let addr = START_ADDRESS as usize;
+        let max = self.address_mask() as usize + 1 - addr;
+        if rom.len() > max {
+            return Err(Chip8Error::RomTooLarge { size: rom.len(), max });
+        }
+        self.memory[addr..addr + rom.len()].copy_from_slice(rom);
+
+        // The two-page display is a plain CHIP-8 interpreter's
+        if self.detect_hires && !self.schip && !self.xochip && is_hires_rom(rom) {
+            self.hires = true;
+            self.pc = HIRES_START_ADDRESS;
+        }
+        Ok(())
+    }
+}
+
+// Display geometry of the current mode
+impl Chip8 {
+    pub fn video_width(&self) -> u32 {
+        if self.extended { MAX_VIDEO_WIDTH } else { VIDEO_WIDTH }
+    }
+
+    pub fn video_height(&self) -> u32 {
+        if self.hires || self.extended { HIRES_VIDEO_HEIGHT } else { VIDEO_HEIGHT }
+    }
+
+    // The part of the framebuffer the current mode actually displays, row by
+    // row, each pixel the set of planes lit in it: 0 unlit, 1 for a lit pixel
+    // outside XO-CHIP
+    pub fn active_video(&self) -> &[u32] {
+        &self.video[..(self.video_width() * self.video_height()) as usize]
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+}
+
+// Input and output
+impl Chip8 {
+    // One byte per key 0-F, non-zero while held
+    pub fn keypad(&self) -> &[u8; 16] {
+        &self.keypad
+    }
+
+    pub fn keypad_mut(&mut self) -> &mut [u8; 16] {
+        &mut self.keypad
+    }
+
+    // Whether the buzzer should be sounding
+    pub fn beeping(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    // Keys tested by SKP and SKNP so far, a bit each, and whether LD Vx, K has run
+    pub fn polled_keys(&self) -> u16 {
+        self.polled_keys
+    }
+
+    pub fn waited_for_key(&self) -> bool {
+        self.waited_for_key
+    }
+}
+
+// Settings a frontend may change
+impl Chip8 {
+    pub fn set_beep(&mut self, beep: Beep) {
+        self.beep = beep;
+    }
+
+    pub fn beep(&self) -> Beep {
+        self.beep
+    }
+
+    pub fn set_peripheral(&mut self, peripheral: Option<SharedPeripheral>) {
+        self.peripheral = peripheral;
+    }
+
+    pub fn set_cdp1802(&mut self, enabled: bool) {
+        self.cdp1802 = enabled;
+    }
+
+    pub fn is_cdp1802(&self) -> bool {
+        self.cdp1802
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    // Whether load_rom may switch to the hi-res mode, on by default
+    pub fn set_hires_detection(&mut self, enabled: bool) {
+        self.detect_hires = enabled;
+    }
+
+    pub fn detects_hires(&self) -> bool {
+        self.detect_hires
+    }
+}
+
+
+// Loads font set into memory
+impl Chip8 {
+    fn load_fonts(&mut self) {
+        let fnt_addr = FONTSET_START_ADDRESS as usize;
+        self.memory[fnt_addr..fnt_addr + FONTSET_SIZE as usize].copy_from_slice(&FONTSET);
+    }
+}
+
+// Random numbers for RND. The generator is our own rather than one from `rand`
+// so a seed gives the same sequence on every platform and every release.
+impl Chip8 {
+    pub fn seed(&mut self, seed: u64) {
+        // xorshift gets stuck on an all-zero state
+        self.rng = match seed ^ 0x9E3779B97F4A7C15 {
+            0 => 0x9E3779B97F4A7C15,
+            state => state,
+        };
+    }
+
+    fn random_byte(&mut self) -> u8 {
+        let mut x = self.rng;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.rng = x;
+        (x.wrapping_mul(0x2545F4914F6CDD1D) >> 56) as u8
+    }
+}
+
+impl Chip8 {
+    // 00E0 - CLS: Clears display
+    fn op_00e0(&mut self) {
+        let planes = self.planes as u32;
+        for pixel in self.video.iter_mut() {
+            *pixel &= !planes;
+        }
+    }
+
+    // 00EE - RET: Return from a subroutine
+    fn op_00ee(&mut self) -> Result<(), Chip8Error> {
+        if self.sp == 0 {
+            return Err(Chip8Error::StackUnderflow { pc: self.pc.wrapping_sub(2) });
+        }
+        self.sp -= 1;
+        let sp = self.sp as usize;
+        self.pc = self.stack[sp];
+        Ok(())
+    }
+
+    // 1nnn - JP addr: Jump to address nnn
+    fn op_1nnn(&mut self) {
+        let address = self.opcode & 0x0FFF;
+        self.pc = address;
+    }
+
+    // 2nnn - CALL addr: Call subroutine at nnn
+    fn op_2nnn(&mut self) -> Result<(), Chip8Error> {
+        let sp = self.sp as usize;
+        if sp >= self.stack.len() {
+            return Err(Chip8Error::StackOverflow { pc: self.pc.wrapping_sub(2) });
+        }
+        self.stack[sp] = self.pc;
+        self.sp += 1;
+        let address = self.opcode & 0x0FFF;
+        self.pc = address;
+        Ok(())
+    }
+
+    // 3xkk - SE Vx, byte: Skip next instruction if Vx = kk
+    fn op_3xkk(&mut self) {
+        let vx = ((self.opcode & 0x0F00) >> 8) as u8;
+        let byte = (self.opcode & 0x00FF) as u8;
+        let vx_idx = vx as usize;
+        if self.registers[vx_idx] == byte {
+            self.skip();
+        }
+    }
+
+    // 4xkk - SNE Vx, byte: Skip next instruction if Vx != kk
+    fn op_4xkk(&mut self) {
+        let vx = ((self.opcode & 0x0F00) >> 8) as u8;
+        let byte = (self.opcode & 0x00FF) as u8;
+        let vx_idx = vx as usize;
+        if self.registers[vx_idx] != byte {
+            self.skip();
+        }
+    }
+
+    // 5xy0 - SE Vx, Vy: Skip next instruction if Vx = Vy
+    fn op_5xy0(&mut self) {
+        let vx = ((self.opcode & 0x0F00) >> 8) as u8;
+        let vy = ((self.opcode & 0x00F0) >> 4) as u8;
+        let vx_idx = vx as usize;
+        let vy_idx = vy as usize;
+        if self.registers[vx_idx] == self.registers[vy_idx] {
+            self.skip();
+        }
+    }
+
+    // 6xkk - LD Vx, byte: Interpreted puts value kk into register Vx
+    fn op_6xkk(&mut self) {
+        let vx = ((self.opcode & 0x0F00) >> 8) as u8;
+        let byte = (self.opcode & 0x00FF) as u8;
+
+        let vx_idx = vx as usize;
+
+        self.registers[vx_idx] = byte;
+    }
+
+    // 7xkk - ADD Vx, byte: Set Vx = Vx + kk
+    fn op_7xkk(&mut self) {
+        let vx = ((self.opcode & 0x0F00) >> 8) as u8;
+        let byte = (self.opcode & 0x00FF) as u8;
+
+        let vx_idx = vx as usize;
+
+        self.registers[vx_idx] = self.registers[vx_idx].wrapping_add(byte);
+    }
+
+    // 8xy0 - LD Vx, Vy: Set Vx = Vx + kk
+    fn op_8xy0(&mut self) {
+        let vx = ((self.opcode & 0x0F00) >> 8) as u8;
+        let vy = ((self.opcode & 0x00F0) >> 4) as u8;
+
+        let vx_idx = vx as usize;
+        let vy_idx = vy as usize;
+
+        self.registers[vx_idx] = self.registers[vy_idx];       
+    }
+
+    // 8xy1 - OR Vx, Vy: Set Vx = Vx OR Vy
+    fn op_8xy1(&mut self) {
+        let vx = ((self.opcode & 0x0F00) >> 8) as u8;
+        let vy = ((self.opcode & 0x00F0) >> 4) as u8;
+
+        let vx_idx = vx as usize;
+        let vy_idx = vy as usize;
+
+        self.registers[vx_idx] |= self.registers[vy_idx];
+        if self.quirks.vf_reset {
+            self.registers[0xF] = 0;
+        }
+    }
+
+    // 8xy2 - AND Vx, Vy: Set Vx = Vx AND Vy
+    fn op_8xy2(&mut self) {
+        let vx = ((self.opcode & 0x0F00) >> 8) as u8;
+        let vy = ((self.opcode & 0x00F0) >> 4) as u8;
+
+        let vx_idx = vx as usize;
+        let vy_idx = vy as usize;
+
+        self.registers[vx_idx] &= self.registers[vy_idx];
+        if self.quirks.vf_reset {
+            self.registers[0xF] = 0;
+        }
+    }
+
+    // 8xy3 - XOR Vx, Vy: Set Vx = Vx XOR Vy
+    fn op_8xy3(&mut self) {
+        let vx = ((self.opcode & 0x0F00) >> 8) as u8;
+        let vy = ((self.opcode & 0x00F0) >> 4) as u8;
+
+        let vx_idx = vx as usize;
+        let vy_idx = vy as usize;
+
+        self.registers[vx_idx] ^= self.registers[vy_idx];
+        if self.quirks.vf_reset {
+            self.registers[0xF] = 0;
+        }
+    }
+
+    // 8xy4 - ADD Vx, Vy: Set Vx = Vx + Vy, set VF = carry
+    fn op_8xy4(&mut self) {
+        let vx = ((self.opcode & 0x0F00) >> 8) as u8;
+        let vy = ((self.opcode & 0x00F0) >> 4) as u8;
+
+        let vx_idx = vx as usize;
+        let vy_idx = vy as usize;
+
+        let sum = self.registers[vx_idx] as u16 + self.registers[vy_idx] as u16;
+
+        self.registers[vx_idx] = (sum & 0xFF) as u8;
+        if sum > 255 {
+            self.registers[0xF] = 1;
+        } else {
+            self.registers[0xF] = 0;
+        }
+    }
+
+    // 8xy5 - SUB Vx, Vy: Set Vx = Vx - Vy, set VF = NOT borrow
+    fn op_8xy5(&mut self) {
+        let vx = ((self.opcode & 0x0F00) >> 8) as u8;
+        let vy = ((self.opcode & 0x00F0) >> 4) as u8;
+
+        let vx_idx = vx as usize;
+        let vy_idx = vy as usize;
+
+        let not_borrow = (self.registers[vx_idx] >= self.registers[vy_idx]) as u8;
+        self.registers[vx_idx] = self.registers[vx_idx].wrapping_sub(self.registers[vy_idx]);
+        self.registers[0xF] = not_borrow;
+    }
+
+    // 8xy6 - SHR Vx {, Vy}: Set Vx = Vx SHR 1, or Vy SHR 1 with the shift quirk
+    fn op_8xy6(&mut self) {
+        let vx = ((self.opcode & 0x0F00) >> 8) as u8;
+        let vy = ((self.opcode & 0x00F0) >> 4) as u8;
+
+        let vx_idx = vx as usize;
+        let vy_idx = vy as usize;
+
+        if self.quirks.shift {
+            self.registers[vx_idx] = self.registers[vy_idx];
+        }
+        let flag = self.registers[vx_idx] & 0x1;
+
+        self.registers[vx_idx] >>= 1;
+        self.registers[0xF] = flag;
+    }
+
+    // 8xy7 - SUBN Vx, Vy: Set Vx = Vy - Vx, set VF = NOT borrow
+    fn op_8xy7(&mut self) {
+        let vx = ((self.opcode & 0x0F00) >> 8) as u8;
+        let vy = ((self.opcode & 0x00F0) >> 4) as u8;
+
+        let vx_idx = vx as usize;
+        let vy_idx = vy as usize;
+
+        let not_borrow = (self.registers[vy_idx] >= self.registers[vx_idx]) as u8;
+        self.registers[vx_idx] = self.registers[vy_idx].wrapping_sub(self.registers[vx_idx]);
+        self.registers[0xF] = not_borrow;
+    }
+
+    // 8xyE - SHL Vx {, Vy}: Set Vx = Vx SHL 1, or Vy SHL 1 with the shift quirk
+    fn op_8xye(&mut self) {
+        let vx = ((self.opcode & 0x0F00) >> 8) as u8;
+        let vy = ((self.opcode & 0x00F0) >> 4) as u8;
+        let vx_idx = vx as usize;
+        let vy_idx = vy as usize;
+
+        if self.quirks.shift {
+            self.registers[vx_idx] = self.registers[vy_idx];
+        }
+        let flag = (self.registers[vx_idx] & 0x80) >> 7;
+
+        self.registers[vx_idx] <<= 1;
+        self.registers[0xF] = flag;
+    }
+
+    // 9xy0 - SNE Vx, Vy: Skip next instruction if Vx != Vy
+    fn op_9xy0(&mut self) {
+        let vx = ((self.opcode & 0x0F00) >> 8) as u8;
+        let vy = ((self.opcode & 0x00F0) >> 4) as u8;
+
+        let vx_idx = vx as usize;
+        let vy_idx = vy as usize;
+
+        if self.registers[vx_idx] != self.registers[vy_idx] {
+            self.skip();
+        }
+    }
+
+    // Annn - LD I, addr: Set I = nnn
+    fn op_annn(&mut self) {
+        let address = self.opcode & 0x0FFF;
+
+        self.index = address;
+    }
+
+    // Bnnn - JP V0, addr: Jump to location nnn + V0, or xnn + Vx with the jump quirk
+    fn op_bnnnn(&mut self) {
+        let address = self.opcode & 0x0FFF;
+        let vx = if self.quirks.jump { ((self.opcode & 0x0F00) >> 8) as usize } else { 0 };
+
+        self.pc = (self.registers[vx] as u16) + address;
+    }
+
+    // Cxkk - RND Vx, byte: Set Vx = random byte AND kk
+    fn op_cxkk(&mut self) {
+        let vx = ((self.opcode & 0x0F00) >> 8) as u8;
+        let byte = (self.opcode & 0x00FF) as u8;
+
+        let vx_idx = vx as usize;
+
+        self.registers[vx_idx] = self.random_byte() & byte;
+    }
+
+    // Dxyn - DRW Vx, Vy, nibble: Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision
+    fn op_dxyn(&mut self) {
+        let height = (self.opcode & 0x000F) as u32;
+
+        self.draw_sprite(height, false);
+    }
+
+    // Draws the sprite at I, `rows` rows of 8 pixels or of 16 when `wide`, at
+    // (Vx, Vy) into each selected plane in turn, each plane taking the next
+    // sprite's worth of data. VF is set if any lit pixel was turned off.
+    pub(crate) fn draw_sprite(&mut self, rows: u32, wide: bool) {
+        let vx = ((self.opcode & 0x0F00) >> 8) as usize;
+        let vy = ((self.opcode & 0x00F0) >> 4) as usize;
+
+        let width = self.video_width();
+        let height = self.video_height();
+        let x_pos = (self.registers[vx] as u32) % width;
+        let y_pos = (self.registers[vy] as u32) % height;
+        let (cols, row_bytes) = if wide { (16, 2) } else { (8, 1) };
+
+        self.registers[0xF] = 0;
+
+        let wrap = self.quirks.wrap;
+        let mut sprite = self.index;
+        for plane in [1, 2] {
+            if self.planes & plane == 0 {
+                continue;
+            }
+            for row in 0..rows {
+                // Sprites are clipped at the bottom edge of the screen, unless they wrap
+                if y_pos + row >= height && !wrap {
+                    break;
+                }
+                let addr = sprite.wrapping_add((row * row_bytes) as u16);
+                let sprite_row = if wide {
+                    ((self.read(addr) as u16) << 8) | self.read(addr.wrapping_add(1)) as u16
+                } else {
+                    (self.read(addr) as u16) << 8
+                };
+
+                for col in 0..cols {
+                    // ...and at the right edge
+                    if x_pos + col >= width && !wrap {
+                        break;
+                    }
+                    if sprite_row & (0x8000 >> col) == 0 {
+                        continue;
+                    }
+                    let (x, y) = ((x_pos + col) % width, (y_pos + row) % height);
+                    let screen_pixel = &mut self.video[(y * width + x) as usize];
+                    if *screen_pixel & plane as u32 != 0 {
+                        self.registers[0xF] = 1;
+                    }
+                    *screen_pixel ^= plane as u32;
+                }
+            }
+            sprite = sprite.wrapping_add((rows * row_bytes) as u16);
+        }
+    }
+
+    // Ex9E - SKP Vx: Skip next instruction if key with the value of Vx is pressed
+    fn op_ex9e(&mut self) {
+        let vx = ((self.opcode & 0x0F00) >> 8) as u8;
+        let vx_idx = vx as usize; 
+
+        let key = self.registers[vx_idx] & 0xF;
+        self.polled_keys |= 1 << key;
+
+        if self.keypad[key as usize] != 0 {
+            self.skip();
+        }
+    }
+
+    // ExA1 - SKNP Vx: Skip next instruction if key with the value of Vx is not pressed
+    fn op_exa1(&mut self) {
+        let vx = ((self.opcode & 0x0F00) >> 8) as u8;
+        let vx_idx = vx as usize; 
+
+        let key = self.registers[vx_idx] & 0xF;
+        self.polled_keys |= 1 << key;
+
+        if self.keypad[key as usize] == 0 {
+            self.skip();
+        }
+    }
+
+    // Fx07 - LD Vx, DT: Set Vx = delay timer value.
+    fn op_fx07(&mut self) {
+        let vx = ((self.opcode & 0x0F00) >> 8) as u8;
+        let vx_idx = vx as usize; 
+
+        self.registers[vx_idx] = self.delay_timer;
+    }
+
+    // Fx0A - LD Vx, K: Wait for a key press, store the value of the key in Vx.
+    fn op_fx0a(&mut self) {
+        let vx = ((self.opcode & 0x0F00) >> 8) as u8;
+        let vx_idx = vx as usize; 
+        self.waited_for_key = true;
+
+        match self.keypad.iter().position(|&key| key != 0) {
+            Some(key) => self.registers[vx_idx] = key as u8,
+            None => self.pc = self.pc.wrapping_sub(2),
+        }
+    }
+
+    // Fx15 - LD DT, Vx: Set delay timer = Vx
+    fn op_fx15(&mut self) {
+        let vx = ((self.opcode & 0x0F00) >> 8) as u8;
+        let vx_idx = vx as usize;
+
+        self.delay_timer = self.registers[vx_idx];
+    }
+
+    // Fx18 - LD ST, Vx: Set sound timer = Vx
+    fn op_fx18(&mut self) {
+        let vx = ((self.opcode & 0x0F00) >> 8) as u8;
+        let vx_idx = vx as usize;
+
+        self.sound_timer = self.beep.adjust(self.registers[vx_idx]);
+    }
+
+    // Fx1E - ADD I, Vx: Set I = I + Vx
+    fn op_fx1e(&mut self) {
+        let vx = ((self.opcode & 0x0F00) >> 8) as u8;
+        let vx_idx = vx as usize;
+
+        self.index = self.index.wrapping_add(self.registers[vx_idx] as u16);
+    }
+
+    // Fx29 - LD F, Vx: Set I = location of sprite for digit Vx
+    fn op_fx29(&mut self) {
+        let vx = ((self.opcode & 0x0F00) >> 8) as u8;
+        let vx_idx = vx as usize;
+        let digit = self.registers[vx_idx] & 0xF;
+
+        self.index = FONTSET_START_ADDRESS as u16 + (5 * digit) as u16;
+    }
+
+    // Fx33 - LD B, Vx: Store BCD representation of Vx in memory locations I, I+1, and I+2
+    fn op_fx33(&mut self) {
+        let vx = ((self.opcode & 0x0F00) >> 8) as u8;
+        let vx_idx = vx as usize;
+        let mut value = self.registers[vx_idx];
+
+        // Ones place
+        self.write(self.index.wrapping_add(2), value % 10);
+        value /= 10;
+
+        // Tens place
+        self.write(self.index.wrapping_add(1), value % 10);
+        value /= 10;
+
+        // Hundreds Place
+        self.write(self.index, value % 10);
+    }
+
+    // Fx55 - LD [I], Vx: Store registers V0 through Vx in memory starting at location I
+    fn op_fx55(&mut self) {
+        let vx = ((self.opcode & 0x0F00) >> 8) as u8;
+
+        for i in 0..=vx {
+            self.write(self.index.wrapping_add(i as u16), self.registers[i as usize]);
+        }
+        if self.quirks.load_store {
+            self.index = self.index.wrapping_add(vx as u16 + 1);
+        }
+    }
+
+    // Fx65 - LD Vx, [I]: Read registers V0 through Vx from memory starting at location I
+    fn op_fx65(&mut self) {
+        let vx = ((self.opcode & 0x0F00) >> 8) as u8;
+
+        for i in 0..=vx {
+            self.registers[i as usize] = self.read(self.index.wrapping_add(i as u16));
+        }
+        if self.quirks.load_store {
+            self.index = self.index.wrapping_add(vx as u16 + 1);
+        }
+    }
+
+    // NULL : function that does nothing, for the 0NNN machine code routines plain CHIP-8 ignores
+    fn op_null(&mut self) {
+        
+    }
+}
+
+impl Chip8 {
+    // One instruction followed by a timer tick
+    pub fn cycle(&mut self) -> Result<(), Chip8Error> {
+        self.tick()?;
+        self.tick_timers();
+        Ok(())
+    }
+
+    // Fetch, decode and execute a single instruction. One that fails leaves
+    // the PC on it.
+    pub fn tick(&mut self) -> Result<(), Chip8Error> {
+        // Fetch, from the memory the current mode can address
+        let pc = self.pc;
+        if pc >= self.address_mask() {
+            return Err(Chip8Error::MemoryOutOfBounds { pc });
+        }
+        self.opcode = ((self.memory[pc as usize] as u16) << 8) | (self.memory[pc as usize + 1] as u16);
+
+        // Increment program counter, which wraps at the top of XO-CHIP's 64KB
+        self.pc = pc.wrapping_add(2);
+
+        let before = self.tracer.is_some().then(|| self.trace_registers());
+        let result = self.execute().inspect_err(|_| self.pc = pc);
+        if let (Some(tracer), Some(before)) = (&self.tracer, before) {
+            let opcode = self.opcode;
+            let instruction = decode(opcode, self.hires);
+            tracer.trace(&TraceEntry { pc, opcode, instruction, before, after: self.trace_registers() });
+        }
+        result
+    }
+
+    fn execute(&mut self) -> Result<(), Chip8Error> {
+        use decode::Instruction::*;
+
+        // Decode and Execute, with the same decoder as the disassembler so the
+        // two agree on what every opcode is
+        let opcode = self.opcode;
+        match decode(opcode, self.hires) {
+            Cls => self.op_00e0(),
+            Ret => self.op_00ee()?,
+            Scd(_) if self.schip => self.op_00cn(),
+            Scr if self.schip => self.op_00fb(),
+            Scl if self.schip => self.op_00fc(),
+            Exit if self.schip => self.op_00fd(),
+            Low if self.schip => self.op_00fe(),
+            High if self.schip => self.op_00ff(),
+            Scu(_) if self.xochip => self.op_00dn(),
+            Jp(_) => self.op_1nnn(),
+            Call(_) => self.op_2nnn()?,
+            SeImm { .. } => self.op_3xkk(),
+            SneImm { .. } => self.op_4xkk(),
+            SeReg { .. } => self.op_5xy0(),
+            Save { .. } if self.xochip => self.op_5xy2(),
+            Load { .. } if self.xochip => self.op_5xy3(),
+            LdImm { .. } => self.op_6xkk(),
+            AddImm { .. } => self.op_7xkk(),
+            LdReg { .. } => self.op_8xy0(),
+            Or { .. } => self.op_8xy1(),
+            And { .. } => self.op_8xy2(),
+            Xor { .. } => self.op_8xy3(),
+            AddReg { .. } => self.op_8xy4(),
+            Sub { .. } => self.op_8xy5(),
+            Shr { .. } => self.op_8xy6(),
+            Subn { .. } => self.op_8xy7(),
+            Shl { .. } => self.op_8xye(),
+            SneReg { .. } => self.op_9xy0(),
+            LdI(_) => self.op_annn(),
+            JpV0(_) => self.op_bnnnn(),
+            Rnd { .. } => self.op_cxkk(),
+            Drw { n: 0, .. } if self.schip => self.op_dxy0(),
+            Drw { .. } => self.op_dxyn(),
+            Skp(_) => self.op_ex9e(),
+            Sknp(_) => self.op_exa1(),
+            LdVxDt(_) => self.op_fx07(),
+            LdVxK(_) => self.op_fx0a(),
+            LdDtVx(_) => self.op_fx15(),
+            LdStVx(_) => self.op_fx18(),
+            AddI(_) => self.op_fx1e(),
+            LdF(_) => self.op_fx29(),
+            LdHf(_) if self.schip => self.op_fx30(),
+            LdB(_) => self.op_fx33(),
+            LdIVx(_) => self.op_fx55(),
+            LdVxI(_) => self.op_fx65(),
+            LdRVx(_) if self.schip => self.op_fx75(),
+            LdVxR(_) if self.schip => self.op_fx85(),
+            LdILong if self.xochip => self.op_f000(),
+            Plane(_) if self.xochip => self.op_fn01(),
+            Audio if self.xochip => self.op_f002(),
+            Pitch(_) if self.xochip => self.op_fx3a(),
+            // Any other 0NNN, including the SCHIP ones outside SCHIP mode
+            _ if opcode & 0xF000 == 0 && self.cdp1802 => self.call_native(opcode & 0x0FFF),
+            _ if opcode & 0xF000 == 0 => self.op_null(),
+            _ => return Err(Chip8Error::InvalidOpcode { pc: self.pc.wrapping_sub(2), opcode }),
+        }
+        Ok(())
+    }
+
+    // Counts both timers down towards zero
+    pub fn tick_timers(&mut self) {
+        // Decrement the delay timer if it's been set
+        if self.delay_timer > 0 {
+            self.delay_timer -= 1;
+        }
+
+        // Decrement the sound timer if it's been set
+        if self.sound_timer > 0 {
+            self.sound_timer -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A machine that has run the first `steps` instructions of `program`
+    fn run(program: &[u16], steps: usize) -> Chip8 {
+        let rom: Vec<u8> = program.iter().flat_map(|opcode| opcode.to_be_bytes()).collect();
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&rom).unwrap();
+        for _ in 0..steps {
+            chip8.tick().unwrap();
+        }
+        chip8
+    }
+
+    fn lit(chip8: &Chip8, x: u32, y: u32) -> bool {
+        chip8.active_video()[(y * chip8.video_width() + x) as usize] != 0
+    }
+
+    #[test]
+    fn decodes_by_the_top_nibble() {
+        let chip8 = run(&[0x6A12, 0x7A01, 0xA345], 3);
+        assert_eq!((chip8.registers[0xA], chip8.index, chip8.opcode), (0x13, 0x345, 0xA345));
+    }
+
+    #[test]
+    fn returns_after_the_call() {
+        // CALL 0x206; JP 0x202; (skipped) 0000; LD V1, 1; RET
+        let chip8 = run(&[0x2206, 0x1202, 0x0000, 0x6101, 0x00EE], 3);
+        assert_eq!((chip8.pc, chip8.sp, chip8.registers[1]), (0x202, 0, 1));
+    }
+
+    #[test]
+    fn skips_on_the_keypad() {
+        // LD V0, 0x15 (key 5); SKP V0; LD V1, 1; SKNP V0; LD V2, 1
+        let program = [0x6015, 0xE09E, 0x6101, 0xE0A1, 0x6201];
+        let mut chip8 = run(&program, 1);
+        chip8.keypad_mut()[5] = 1;
+        for _ in 0..3 {
+            chip8.tick().unwrap();
+        }
+        assert_eq!((chip8.registers[1], chip8.registers[2]), (0, 1));
+
+        let chip8 = run(&program, 4);
+        assert_eq!((chip8.registers[1], chip8.registers[2]), (1, 0));
+    }
+
+    #[test]
+    fn draws_and_detects_collisions() {
+        // LD F, V0 (the 0 glyph at 0,0); DRW V0, V0, 5 twice
+        let mut chip8 = run(&[0xF029, 0xD005], 2);
+        assert_eq!(&chip8.memory[0x50..0x55], &[0xF0, 0x90, 0x90, 0x90, 0xF0]);
+        assert!(lit(&chip8, 0, 0) && lit(&chip8, 3, 0) && !lit(&chip8, 1, 1) && !lit(&chip8, 4, 0));
+        assert_eq!(chip8.registers[0xF], 0);
+
+        chip8.pc = 0x202;
+        chip8.tick().unwrap();
+        assert!(chip8.active_video().iter().all(|&pixel| pixel == 0));
+        assert_eq!(chip8.registers[0xF], 1);
+    }
+
+    #[test]
+    fn clips_sprites_at_the_edges() {
+        // LD V0, 62; LD V1, 30; LD F, V2 (the 0 glyph); DRW V0, V1, 5
+        let chip8 = run(&[0x603E, 0x611E, 0xF229, 0xD015], 4);
+        assert!(lit(&chip8, 62, 30) && lit(&chip8, 63, 30) && lit(&chip8, 62, 31));
+        assert!(!lit(&chip8, 0, 30) && !lit(&chip8, 62, 0));
+    }
+
+    #[test]
+    fn stores_and_loads_through_vx() {
+        // LD V0..V3; LD I, 0x300; LD [I], V2; LD V3, [I]
+        let chip8 = run(&[0x6001, 0x6102, 0x6203, 0x6309, 0xA300, 0xF255, 0xF365], 7);
+        assert_eq!(&chip8.memory[0x300..0x304], &[1, 2, 3, 0]);
+        assert_eq!(&chip8.registers[..4], &[1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn wraps_arithmetic_with_flags() {
+        // LD V0, 0xFF; ADD V0, 2; LD V1, 0xFF; ADD V1, V1; LD V2, 1; SUB V2, V1
+        let chip8 = run(&[0x60FF, 0x7002, 0x61FF, 0x8114, 0x6201, 0x8215], 6);
+        assert_eq!(chip8.registers[0], 0x01);
+        assert_eq!(chip8.registers[1], 0xFE);
+        assert_eq!((chip8.registers[2], chip8.registers[0xF]), (0x03, 0));
+
+        // LD V3, 5; SUBN V3, V3 doesn't borrow; LD VF, 0xFF; ADD VF, VF leaves the carry in VF
+        let chip8 = run(&[0x6305, 0x8337, 0x6FFF, 0x8FF4], 2);
+        assert_eq!((chip8.registers[3], chip8.registers[0xF]), (0, 1));
+        let chip8 = run(&[0x6305, 0x8337, 0x6FFF, 0x8FF4], 4);
+        assert_eq!(chip8.registers[0xF], 1);
+    }
+
+    #[test]
+    fn wraps_i() {
+        // ADD I, V0 past 0xFFFF
+        let mut chip8 = Chip8::new();
+        chip8.index = 0xFFFF;
+        chip8.registers[0] = 2;
+        chip8.memory[0x200..0x202].copy_from_slice(&[0xF0, 0x1E]);
+        chip8.tick().unwrap();
+        assert_eq!(chip8.index, 0x0001);
+    }
+
+    #[test]
+    fn waits_for_a_key() {
+        let mut chip8 = run(&[0xF50A], 2);
+        assert_eq!(chip8.pc, 0x200);
+        chip8.keypad_mut()[0xB] = 1;
+        chip8.tick().unwrap();
+        assert_eq!((chip8.pc, chip8.registers[5]), (0x202, 0xB));
+    }
+
+    #[test]
+    fn reports_stack_errors() {
+        let mut chip8 = run(&[0x00EE], 0);
+        assert!(matches!(chip8.tick(), Err(Chip8Error::StackUnderflow { pc: 0x200 })));
+        assert_eq!(chip8.pc, 0x200);
+
+        let mut chip8 = run(&[0x2200], 16);
+        assert!(matches!(chip8.tick(), Err(Chip8Error::StackOverflow { pc: 0x200 })));
+        assert_eq!(chip8.sp, 16);
+    }
+
+    #[test]
+    fn reports_invalid_opcodes() {
+        let mut chip8 = run(&[0xFFFF], 0);
+        assert!(matches!(chip8.tick(), Err(Chip8Error::InvalidOpcode { pc: 0x200, opcode: 0xFFFF })));
+        assert_eq!(chip8.pc, 0x200);
+    }
+
+    #[test]
+    fn bounds_the_pc_by_the_mode() {
+        let mut chip8 = Chip8::new();
+        chip8.pc = 0xFFF;
+        assert!(matches!(chip8.tick(), Err(Chip8Error::MemoryOutOfBounds { pc: 0xFFF })));
+
+        // XO-CHIP runs code above 4KB, up to the top of memory
+        let mut chip8 = Chip8::new();
+        chip8.set_xochip(true);
+        chip8.pc = 0xFFFE;
+        chip8.memory[0xFFFE..].copy_from_slice(&[0x60, 0x07]);
+        chip8.tick().unwrap();
+        assert_eq!((chip8.pc, chip8.registers[0]), (0x0000, 7));
+        chip8.pc = 0xFFFF;
+        assert!(matches!(chip8.tick(), Err(Chip8Error::MemoryOutOfBounds { pc: 0xFFFF })));
+    }
+
+    #[test]
+    fn executes_at_the_top_of_xochip_memory() {
+        let at_top = |opcode: u16, sp: u8| {
+            let mut chip8 = Chip8::new();
+            chip8.set_xochip(true);
+            chip8.set_schip(true);
+            chip8.pc = 0xFFFE;
+            chip8.sp = sp;
+            chip8.memory[0xFFFE..].copy_from_slice(&opcode.to_be_bytes());
+            let result = chip8.tick();
+            (chip8, result)
+        };
+
+        // Errors point at the instruction, not past the wrapped PC
+        let (chip8, result) = at_top(0x00EE, 0);
+        assert!(matches!(result, Err(Chip8Error::StackUnderflow { pc: 0xFFFE })));
+        assert_eq!(chip8.pc, 0xFFFE);
+        let (_, result) = at_top(0xE000, 0);
+        assert!(matches!(result, Err(Chip8Error::InvalidOpcode { pc: 0xFFFE, opcode: 0xE000 })));
+
+        let (_, result) = at_top(0x2300, 16);
+        assert!(matches!(result, Err(Chip8Error::StackOverflow { pc: 0xFFFE })));
+
+        // Waiting for a key and EXIT stay on the instruction
+        let (chip8, result) = at_top(0xF00A, 0);
+        assert!(result.is_ok());
+        assert_eq!(chip8.pc, 0xFFFE);
+        let (chip8, result) = at_top(0x00FD, 0);
+        assert!(result.is_ok());
+        assert_eq!(chip8.pc, 0xFFFE);
+    }
+
+    #[test]
+    fn detects_hires_roms() {
+        // JP 0x260, then setup clearing the screen through the patched interpreter
+        let mut hires = vec![0; 0xC2];
+        hires[..2].copy_from_slice(&[0x12, 0x60]);
+        hires[0x60..0x64].copy_from_slice(&[0x02, 0x30, 0x12, 0xC0]);
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&hires).unwrap();
+        assert_eq!((chip8.is_hires(), chip8.pc, chip8.video_height()), (true, HIRES_START_ADDRESS, 64));
+
+        // An ordinary ROM that starts by jumping over its data to 0x260
+        let mut plain = vec![0; 0xC2];
+        plain[..2].copy_from_slice(&[0x12, 0x60]);
+        plain[0x60..0x66].copy_from_slice(&[0x00, 0xE0, 0x60, 0x01, 0x12, 0x64]);
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&plain).unwrap();
+        assert_eq!((chip8.is_hires(), chip8.pc), (false, START_ADDRESS));
+        chip8.tick().unwrap();
+        assert_eq!(chip8.pc, 0x260);
+
+        // Only plain CHIP-8 has the two-page display, and only if asked
+        let mut chip8 = Chip8::new();
+        chip8.set_schip(true);
+        chip8.load_rom(&hires).unwrap();
+        assert_eq!((chip8.is_hires(), chip8.pc), (false, START_ADDRESS));
+        let mut chip8 = Chip8::new();
+        chip8.set_hires_detection(false);
+        chip8.load_rom(&hires).unwrap();
+        assert_eq!((chip8.is_hires(), chip8.pc), (false, START_ADDRESS));
+    }
+
+    #[test]
+    fn rejects_oversized_roms() {
+        let rom = vec![0; MAX_ROM_SIZE];
+        assert!(check_rom_size(&rom).is_ok());
+        assert!(matches!(Chip8::new().load_rom(&rom), Err(Chip8Error::RomTooLarge { max: 0xE00, .. })));
+        let mut chip8 = Chip8::new();
+        chip8.set_xochip(true);
+        assert!(chip8.load_rom(&rom).is_ok());
+        assert!(matches!(check_rom_size(&[0; MAX_ROM_SIZE + 1]), Err(Chip8Error::RomTooLarge { .. })));
+    }
+}
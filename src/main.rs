@@ -1,750 +1,941 @@
 extern crate sdl2;
 
-use std::fs::File;
-use std::io::Read;
+mod analysis;
+mod audio;
+#[cfg(feature = "broadcast")]
+mod broadcast;
+mod capture;
+mod cheats;
+mod commands;
+mod config;
+mod controls;
+mod costs;
+mod debugger;
+mod disasm;
+mod fuzz;
+mod gamepad;
+mod headless;
+mod hotkeys;
+mod info;
+mod keymap;
+mod osd;
+mod pacer;
+#[cfg(feature = "plugins")]
+mod plugins;
+mod replay;
+mod rewind;
+mod render;
+mod scenario;
+mod rom;
+mod session;
+mod slots;
+mod speedrun;
+mod sprite_editor;
+mod sprites;
+mod suite;
+mod timeline;
+mod tracelog;
+mod turbo;
+mod watch;
+
+use std::collections::HashSet;
 use std::env;
 use std::process;
 use std::mem;
-use std::rc::Rc;
-use std::time::Instant;
-use rand::Rng;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use sdl2::event::Event;
+use sdl2::event::{Event, WindowEvent};
 use sdl2::keyboard::Keycode;
-use sdl2::pixels::{Color, PixelFormatEnum};
-use sdl2::render::TextureCreator;
-use sdl2::render::{Canvas, Texture, TextureAccess};
-use sdl2::video;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, Texture};
+use sdl2::surface::Surface;
 use sdl2::video::Window;
-use sdl2::video::WindowContext;
 use sdl2::Sdl;
-use std::time::Duration;
-
-
-// Chip8’s memory from 0x000 to 0x1FF is reserved, so the ROM instructions must start at 0x200
-const START_ADDRESS: u16 = 0x200;
-const FONTSET_START_ADDRESS: u8 = 0x50;
-const FONTSET_SIZE: u32 = 80;
-const VIDEO_WIDTH: u32 = 64;
-const VIDEO_HEIGHT: u32 = 32;
-
-const fontset: [u8; 80] = 
-[
-    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-	0x20, 0x60, 0x20, 0x20, 0x70, // 1
-	0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-	0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-	0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-	0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-	0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-	0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-	0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-	0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-	0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-	0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-	0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-	0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-	0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-	0xF0, 0x80, 0xF0, 0x80, 0x80  // F
-];
-
-// Struct for CHIP8 structure
-struct Chip8 {
-    registers: [u8; 16],
-    memory: [u8; 4096],
-    index: u16,
-    pc: u16,
-    stack: [u16; 16],
-    sp: u8,
-    delay_timer: u8,
-    sound_timer: u8,
-    keypad: [u8; 16],
-    video: [u32; 64*32],
-    opcode: u16
-}
 
-// Constructor
-impl Chip8 {
-    fn new() -> Chip8 {
-        Chip8 {
-            registers: [0; 16],       // Default values for registers
-            memory: [0; 4096],        // Default values for memory
-            index: 0,                 // Default value for index
-            pc: START_ADDRESS,        // Initialize pc to 0x200
-            stack: [0; 16],           // Default values for stack
-            sp: 0,                    // Default value for stack pointer
-            delay_timer: 0,           // Default value for delay timer
-            sound_timer: 0,           // Default value for sound timer
-            keypad: [0; 16],          // Default values for keypad
-            video: [0; 64 * 32],      // Default values for video
-            opcode: 0,                // Default value for opcode
-        }
-    }
+use analysis::Analysis;
+use audio::Buzzer;
+use chip8_core::beep::Beep;
+use chip8_core::bus::SharedPeripheral;
+use chip8_core::error::Chip8Error;
+use chip8_core::palette;
+use chip8_core::quirks::Quirks;
+use chip8_core::renderer::Renderer;
+use chip8_core::trace::SharedTracer;
+use chip8_core::{Chip8, MAX_VIDEO_HEIGHT, MAX_VIDEO_WIDTH, VIDEO_HEIGHT, VIDEO_WIDTH};
+use capture::Capture;
+use replay::{Recording, Replay};
+use rewind::Rewind;
+use commands::CommandInput;
+use config::Config;
+use controls::ControlsHint;
+use debugger::{Debugger, Symbols};
+use gamepad::{ButtonMap, Gamepad};
+use hotkeys::{Action, Hotkeys};
+use keymap::Keymap;
+use osd::Osd;
+use session::Session;
+use slots::Slots;
+use speedrun::Speedrun;
+use chip8_core::timing::{Timing, CYCLES_PER_FRAME, FRAME_RATE};
+use pacer::Pacer;
+use tracelog::TraceLog;
+use turbo::Turbo;
+use watch::Watch;
+// Range the scale hotkeys step through
+const MIN_SCALE: u32 = 1;
+const MAX_SCALE: u32 = 16;
+// How long a first quit press waits for its confirmation
+const QUIT_CONFIRM_WINDOW: Duration = Duration::from_secs(2);
+// How long a reset can still be undone
+const UNDO_RESET_WINDOW: Duration = Duration::from_secs(5);
+// Window scale and cycle delay of sessions that don't set them
+const DEFAULT_SCALE: u32 = 10;
+const DEFAULT_DELAY: u32 = 2;
+// Instructions a second without --ips, the frames of Timing::default
+const DEFAULT_IPS: u32 = CYCLES_PER_FRAME * FRAME_RATE;
+
+// Cycles of --legacy-timing that take a 60Hz frame at `delay` ms each, as
+// many as at 1ms for no delay
+fn legacy_frame(delay: u32) -> u32 {
+    (1000 / (FRAME_RATE * delay.max(1))).max(1)
 }
-
-// Opens contents of ROM file into memory
-impl Chip8 {
-    fn load_rom(&mut self, filename: &String) {
-        let mut f = File::open(filename).expect("Error opening image...");
-        let mut buffer = Vec::new();
-
-        f.read_to_end(&mut buffer).expect("Error reading file..."); // Opens as a vector of bytes
-
-        for i in 0..buffer.len() {
-            let addr = START_ADDRESS as usize;
-            self.memory[addr + i] = buffer[i];
-        }
-    }
+// Frames drawn while fast-forwarding or uncapped: one out of every this many
+const DEFAULT_FRAME_SKIP: u32 = 8;
+
+// Where the main loop is at, moved along by the pause, frame advance and quit hotkeys
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum RunState {
+    Running,
+    Paused,
+    // Running until the end of the frame, then paused
+    Advancing,
+    Quitting,
 }
 
-
-// Loads font set into memory
-impl Chip8 {
-    fn load_fonts(&mut self) {
-        for i in 0..FONTSET_SIZE {
-            let fnt_addr = FONTSET_START_ADDRESS as usize;
-            let idx = i as usize;
-            self.memory[fnt_addr + idx] = fontset[idx];
+impl RunState {
+    fn after(self, action: Action) -> RunState {
+        match (self, action) {
+            (RunState::Quitting, _) | (_, Action::Quit) => RunState::Quitting,
+            (RunState::Paused, Action::Pause) => RunState::Running,
+            (_, Action::Pause) => RunState::Paused,
+            (_, Action::FrameAdvance) => RunState::Advancing,
+            (state, _) => state,
         }
     }
-}
-
-impl Chip8 {
-    // 00E0 - CLS: Clears display
-    fn op_00e0(&mut self) {
-        self.video.fill(0);
-    }
 
-    // 00EE - RET: Return from a subroutine
-    fn op_00ee(&mut self) {
-        self.sp -= 1;
-        let pc = self.pc as usize;
-        self.pc = self.stack[pc];
+    // Whether the machine runs, rather than standing still
+    fn running(self) -> bool {
+        matches!(self, RunState::Running | RunState::Advancing)
     }
+}
 
-    // 1nnn - JP addr: Jump to address nnn
-    fn op_1nnn(&mut self) {
-        let address = self.opcode & 0x0FFF;
-        self.pc = address;
+// The [controller] bindings with those of the game called `name` on top
+fn controller_buttons(config: &Config, name: &str) -> Result<ButtonMap, String> {
+    let mut buttons = ButtonMap::default();
+    buttons.apply_config(&config.controller).map_err(|e| format!("controller: {}", e))?;
+    if let Some(game) = config.games.get(name) {
+        buttons.apply_config(&game.controller).map_err(|e| format!("games.{}.controller: {}", name, e))?;
     }
+    Ok(buttons)
+}
 
-    // 2nnn - CALL addr: Call subroutine at nnn
-    fn op_2nnn(&mut self) {
-        let sp = self.sp as usize;
-        self.stack[sp] = self.pc;
-        self.sp += 1;
-        let address = self.opcode & 0x0FFF;
-        self.pc = address;
-    }
+struct Platform<'a> {
+    sdl: Sdl,
+    canvas: Canvas<Window>,
+    texture: Texture<'a>,
+    // ARGB colours of the framebuffer's plane combinations, see palette.rs
+    colors: [u32; 4],
+    buzzer: Option<Buzzer>,
+    keymap: Keymap,
+    hotkeys: Hotkeys,
+    turbo: Turbo,
+    gamepad: Gamepad,
+    osd: Osd,
+    slots: Slots,
+    // The slot browser is showing and takes the arrow keys
+    browsing: bool,
+    // Host keys currently held down, the keypad is derived from these
+    held: HashSet<Keycode>,
+    // A file dropped on the window, for the main loop to open
+    dropped: Option<String>,
+    // When set, the quit key has to be pressed twice within QUIT_CONFIRM_WINDOW
+    confirm_quit: bool,
+    quit_requested: Option<Instant>,
+}
 
-    // 3xkk - SE Vx, byte: Skip next instruction if Vx = kk
-    fn op_3xkk(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let byte = (self.opcode & 0x00FF) as u8;
-        let vx_idx = Vx as usize;
-        if self.registers[vx_idx] == byte {
-            self.pc += 2;
-        }
+impl<'a> Platform<'a> {
+    fn new(sdl: Sdl, canvas: Canvas<Window>, texture: Texture<'a>, keymap: Keymap, hotkeys: Hotkeys, turbo: Turbo, gamepad: Gamepad) -> Result<Self, String> {
+        // Return platform instance
+        Ok(Platform { 
+            sdl,
+            canvas,
+            texture,
+            colors: palette::DEFAULT,
+            buzzer: None,
+            keymap,
+            hotkeys,
+            turbo,
+            gamepad,
+            osd: Osd::default(),
+            slots: Slots::default(),
+            browsing: false,
+            held: HashSet::new(),
+            dropped: None,
+            confirm_quit: false,
+            quit_requested: None,
+        })
     }
 
-    // 4xkk - SNE Vx, byte: Skip next instruction if Vx != kk
-    fn op_4xkk(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let byte = (self.opcode & 0x00FF) as u8;
-        let vx_idx = Vx as usize;
-        if self.registers[vx_idx] != byte {
-            self.pc += 2;
+    // Whether a press of the quit key should end the session
+    fn quit_pressed(&mut self) -> bool {
+        if !self.confirm_quit {
+            return true;
         }
-    }
-
-    // 5xy0 - SE Vx, Vy: Skip next instruction if Vx = Vy
-    fn op_5xy0(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let Vy = ((self.opcode & 0x00F0) >> 4) as u8;
-        let vx_idx = Vx as usize;
-        let vy_idx = Vy as usize;
-        if self.registers[vx_idx] == self.registers[vy_idx] {
-            self.pc += 2;
+        let now = Instant::now();
+        match self.quit_requested {
+            Some(at) if now.duration_since(at) <= QUIT_CONFIRM_WINDOW => true,
+            _ => {
+                self.quit_requested = Some(now);
+                self.osd.show(format!("Press {} again to quit", self.hotkeys.key(Action::Quit).name()));
+                false
+            }
         }
     }
 
-    // 6xkk - LD Vx, byte: Interpreted puts value kk into register Vx
-    fn op_6xkk(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let byte = (self.opcode & 0x00FF) as u8;
-
-        let vx_idx = Vx as usize;
-
-        self.registers[vx_idx] = byte;
-    }
-
-    // 7xkk - ADD Vx, byte: Set Vx = Vx + kk
-    fn op_7xkk(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let byte = (self.opcode & 0x00FF) as u8;
+    // Draws `buffer`, ARGB pixels, with the overlays on top
+    fn present(&mut self, buffer: &[u8], width: u32, height: u32) -> Result<(), String> {
+        // The texture is sized for the largest mode, only the active part is used
+        let area = Rect::new(0, 0, width, height);
+        let pitch = mem::size_of::<u32>() * (width as usize);
 
-        let vx_idx = Vx as usize;
-
-        self.registers[vx_idx] += byte;
-    }
-
-    // 8xy0 - LD Vx, Vy: Set Vx = Vx + kk
-    fn op_8xy0(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let Vy = ((self.opcode & 0x00F0) >> 4) as u8;
+        // Update the texture with the buffer data
+        self.texture.update(area, buffer, pitch)
+            .map_err(|e| e.to_string())?;
 
-        let vx_idx = Vx as usize;
-        let vy_idx = Vy as usize;
+        // Clear the renderer, copy the texture, and present it to the screen
+        self.canvas.clear();
+        self.canvas.copy(&self.texture, area, None)
+            .map_err(|e| e.to_string())?;
+        if self.browsing {
+            self.slots.draw(&mut self.canvas)?;
+        }
+        self.osd.draw(&mut self.canvas)?;
+        self.canvas.present();
 
-        self.registers[vx_idx] = self.registers[vy_idx];       
+        Ok(())
     }
 
-    // 8xy1 - OR Vx, Vy: Set Vx = Vx OR Vy
-    fn op_8xy1(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let Vy = ((self.opcode & 0x00F0) >> 4) as u8;
-
-        let vx_idx = Vx as usize;
-        let vy_idx = Vy as usize;
-
-        self.registers[vx_idx] |= self.registers[vy_idx];       
+    // Saves what's currently on screen as a BMP next to the working directory
+    fn screenshot(&self) -> Result<String, String> {
+        let format = PixelFormatEnum::RGB24;
+        let (width, height) = self.canvas.output_size()?;
+        let mut pixels = self.canvas.read_pixels(None, format)?;
+        let surface = Surface::from_data(&mut pixels, width, height, width * 3, format)?;
+
+        let stamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0);
+        let path = format!("screenshot-{}.bmp", stamp);
+        surface.save_bmp(&path)?;
+        Ok(path)
     }
 
-    // 8xy2 - AND Vx, Vy: Set Vx = Vx AND Vy
-    fn op_8xy2(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let Vy = ((self.opcode & 0x00F0) >> 4) as u8;
-
-        let vx_idx = Vx as usize;
-        let vy_idx = Vy as usize;
-
-        self.registers[vx_idx] &= self.registers[vy_idx];       
+    // Sizes the window to show a width x height display at an integer scale. The
+    // 128 pixel wide SCHIP mode gets the window of the 64 pixel wide ones, with
+    // pixels half the size, so switching between them doesn't resize it.
+    fn resize(&mut self, width: u32, height: u32, scale: u32) -> Result<(), String> {
+        let shrink = (width / VIDEO_WIDTH).max(1);
+        self.canvas.window_mut()
+            .set_size(width * scale / shrink, height * scale / shrink)
+            .map_err(|e| e.to_string())
     }
 
-    // 8xy3 - XOR Vx, Vy: Set Vx = Vx XOR Vy
-    fn op_8xy3(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let Vy = ((self.opcode & 0x00F0) >> 4) as u8;
-
-        let vx_idx = Vx as usize;
-        let vy_idx = Vy as usize;
-
-        self.registers[vx_idx] ^= self.registers[vy_idx];       
+    // Whether the key bound to a hold-style action is down
+    fn holding(&self, action: Action) -> bool {
+        self.held.contains(&self.hotkeys.key(action))
     }
 
-    // 8xy4 - ADD Vx, Vy: Set Vx = Vx + Vy, set VF = carry
-    fn op_8xy4(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let Vy = ((self.opcode & 0x00F0) >> 4) as u8;
-
-        let vx_idx = Vx as usize;
-        let vy_idx = Vy as usize;
-
-        let sum = (self.registers[vx_idx] + self.registers[vy_idx]) as u16;
-
-        if sum > 255 {
-            self.registers[0xF] = 1;
-        } else {
-            self.registers[0xF] = 0;
-        }     
-        self.registers[vx_idx] = (sum & 0xFF) as u8;
+    // Navigation keys of the slot browser, returns whether `key` was one
+    fn browser_key(&mut self, key: Keycode, actions: &mut Vec<Action>) -> bool {
+        match key {
+            Keycode::Left => self.slots.select(-1),
+            Keycode::Right => self.slots.select(1),
+            Keycode::Return | Keycode::KpEnter => {
+                actions.push(Action::LoadState);
+                self.browsing = false;
+            }
+            Keycode::Escape => self.browsing = false,
+            _ => return false,
+        }
+        true
     }
 
-    // 8xy5 - SUB Vx, Vy: Set Vx = Vx - Vy, set VF = NOT borrow
-    fn op_8xy5(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let Vy = ((self.opcode & 0x00F0) >> 4) as u8;
-
-        let vx_idx = Vx as usize;
-        let vy_idx = Vy as usize;
+    // Updates the keypad from the host keyboard and returns the hotkey actions triggered
+    fn process_input(&mut self, keys: &mut [u8; 16]) -> Vec<Action> {
+        let mut event_pump = self.sdl.event_pump().unwrap();
+        let mut actions = Vec::new();
 
-        if self.registers[vx_idx] > self.registers[vy_idx] {
-            self.registers[0xF] = 1;
-        } else {
-            self.registers[0xF] = 0;
+        for event in event_pump.poll_iter() {
+            if let Some(notice) = self.gamepad.handle(&event) {
+                self.osd.show(notice);
+            }
+            match event {
+                Event::Quit {..} => {
+                    actions.push(Action::Quit);
+                }
+                Event::DropFile { filename, .. } => self.dropped = Some(filename),
+                Event::KeyDown { keycode: Some(key), repeat, .. } => {
+                    if self.browsing && self.browser_key(key, &mut actions) {
+                        continue;
+                    }
+                    match self.hotkeys.action_for(key) {
+                        Some(Action::Quit) if !repeat => {
+                            if self.quit_pressed() {
+                                actions.push(Action::Quit);
+                            }
+                        }
+                        Some(Action::FastForward | Action::Rewind) | None => {}
+                        Some(action) if !repeat => actions.push(action),
+                        Some(_) => {}
+                    }
+                    self.held.insert(key);
+                    self.turbo.press(key);
+                }
+                Event::KeyUp { keycode: Some(key), .. } => {
+                    self.held.remove(&key);
+                    self.turbo.release(key);
+                }
+                // Releases that happen while unfocused never reach us, don't leave keys stuck
+                Event::Window { win_event: WindowEvent::FocusLost, .. } => {
+                    self.held.clear();
+                    self.turbo.release_all();
+                }
+                _ => {}    
+            }
         }
-        self.registers[vx_idx] -= self.registers[vy_idx];
-    }
 
-    // 8xy6 - SHR Vx: Set Vx = Vx SHR 1
-    fn op_8xy6(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-
-        let vx_idx = Vx as usize;
-        
-        self.registers[0xF] = self.registers[vx_idx] & 0x1;
+        // A keypad key stays down as long as any host key bound to it is held,
+        // so overlapping presses and releases can't drop each other. Hotkeys win
+        // over keypad bindings of the same host key, turbo bindings too.
+        keys.fill(0);
+        for &key in &self.held {
+            if self.hotkeys.action_for(key).is_some() || self.turbo.is_turbo(key) {
+                continue;
+            }
+            if let Some(k) = self.keymap.key_for(key) {
+                keys[k as usize] = 1;
+            }
+        }
+        self.turbo.apply(keys);
+        self.gamepad.apply(keys);
 
-        self.registers[vx_idx] >>= 1;
+        actions
     }
+}
 
-    // 8xy7 - SUBN Vx, Vy: Set Vx = Vy - Vx, set VF = NOT borrow
-    fn op_8xy7(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let Vy = ((self.opcode & 0x00F0) >> 4) as u8;
-
-        let vx_idx = Vx as usize;
-        let vy_idx = Vy as usize;
-
-        if self.registers[vy_idx] > self.registers[vx_idx] {
-            self.registers[0xF] = 1;
-        } else {
-            self.registers[0xF] = 0;
-        }     
-        self.registers[vx_idx] = self.registers[vy_idx] - self.registers[vx_idx];
+// The main loop calls process_input itself for the hotkeys, this is for
+// renderer::run, which only needs to know about quitting
+impl Renderer for Platform<'_> {
+    fn update(&mut self, video: &[u32], width: u32, height: u32) -> Result<(), String> {
+        let colors = palette::colorize(video, &self.colors);
+        let buffer: &[u8] = unsafe {
+            // We cast the pointer to a u32 array to a u8 slice, ensuring we get the correct byte representation
+            std::slice::from_raw_parts(
+                colors.as_ptr() as *const u8,
+                std::mem::size_of_val(colors.as_slice())
+            )
+        };
+        self.present(buffer, width, height)
     }
 
-    // 8xyE - SHL Vx: Set Vx = Vx SHL 1
-    fn op_8xye(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let vx_idx = Vx as usize;
-
-        self.registers[0xF] = (self.registers[vx_idx] & 0x80) >> 7;
-
-        self.registers[vx_idx] <<= 1;
+    fn poll_input(&mut self, keypad: &mut [u8; 16]) -> Result<bool, String> {
+        Ok(!self.process_input(keypad).contains(&Action::Quit))
     }
 
-    // 9xy0 - SNE Vx, Vy: Skip next instruction if Vx != Vy
-    fn op_9xy0(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let Vy = ((self.opcode & 0x00F0) >> 4) as u8;
-
-        let vx_idx = Vx as usize;
-        let vy_idx = Vy as usize;
-
-        if self.registers[vx_idx] != self.registers[vy_idx] {
-            self.pc += 2;
+    fn beep(&mut self, on: bool) {
+        if let Some(buzzer) = &mut self.buzzer {
+            buzzer.set(on);
         }
     }
+}
 
-    // Annn - LD I, addr: Set I = nnn
-    fn op_annn(&mut self) {
-        let address = self.opcode & 0x0FFF;
-
-        self.index = address;
-    }
+fn usage(program: &str) -> ! {
+    eprintln!("Usage: {} [options] [<Scale> <Delay>] <ROM>", program);
+    eprintln!("       {} [options] <SESSION.c8session>", program);
+    eprintln!("       {} --headless <ROM> [--cycles N] [--png OUT] [--expect HASH] ...", program);
+    eprintln!("       {} suite|gen|fuzz|render|disasm|scenario|verify|sprite-edit|sprites ...\n", program);
+    eprintln!("Options:");
+    eprintln!("  --pause-at-start    halt at the first instruction and read debugger commands from stdin");
+    eprintln!("  --debug             read debugger commands from stdin without halting");
+    eprintln!("  --break ADDR        stop at ADDR (hex or label), may be repeated");
+    eprintln!("  --symbols FILE      labels for the debugger, one `ADDR LABEL` per line");
+    eprintln!("  --trace FILE        log every instruction run to FILE, or stdout for -");
+    eprintln!("  --trace-range A-B   only log instructions at addresses A to B, in hex");
+    eprintln!("  --trace-last N      keep the last N instructions and log them only if the ROM fails");
+    eprintln!("  --watch EXPR        show EXPR (like `[2F0]` or `lives=V3+1`) in an overlay, may be repeated");
+    eprintln!("  --keypad LAYOUT     host keyboard layout: qwerty (default) or cosmac");
+    eprintln!("  --key KEY=K         host key KEY (by SDL name) presses keypad key K, may be repeated");
+    eprintln!("  --scale N           window scale, over <Scale> (default 10)");
+    eprintln!("  --palette NAME      display colours: default, octo, amber or green");
+    eprintln!("  --foreground COLOR  colour of lit pixels as #RRGGBB");
+    eprintln!("  --background COLOR  colour of unlit pixels as #RRGGBB");
+    eprintln!("  --list-palettes     print the colours in effect and the palettes --palette accepts and exit");
+    eprintln!("  --config FILE       read settings from FILE instead of the default config.toml");
+    eprintln!("  --quit-key KEY      key that quits, by SDL name (default Escape)");
+    eprintln!("  --confirm-quit      require pressing the quit key twice");
+    eprintln!("  --fullscreen        start fullscreen");
+    eprintln!("  --monitor N         display to open on (remembered for next time)");
+    eprintln!("  --seed N            seed for RND, so runs can be repeated");
+    eprintln!("  --capture FILE      record the run as a regression fixture for `verify`, written on quit");
+    eprintln!("  --record FILE       record the keypad of every frame to FILE on quit, for --playback");
+    eprintln!("  --playback FILE     run a recording again exactly, with the seed and settings it was made with");
+    eprintln!("  --speedrun FILE     show a timer that restarts on reset, writing splits to FILE");
+    eprintln!("  --broadcast ADDR    stream the display to WebSocket viewers connecting to ADDR (host:port)");
+    eprintln!("  --plugin PATH       load a plugin library, may be repeated");
+    eprintln!("  --commands SRC      take text commands like `press 5 for 3 frames` from stdin (-) or TCP clients of ADDR");
+    eprintln!("  --cdp1802           run 0NNN machine code routines of hybrid VIP ROMs");
+    eprintln!("  --no-hires          don't start hi-res VIP ROMs in the 64x64 mode");
+    eprintln!("  --schip, --chip8    run as SUPER-CHIP, with the schip quirks, or plain CHIP-8 (default: SCHIP if the ROM uses it)");
+    eprintln!("  --xochip            run as XO-CHIP (default if the ROM uses it), with the xochip quirks");
+    eprintln!("  --quirks SPEC       instruction quirks: chip8, schip, xochip or quirk names, as in `chip8,-vf_reset`");
+    eprintln!("  --list-quirks       print the quirks in effect and exit");
+    eprintln!("  --list-profiles     print the quirk presets --quirks accepts and exit");
+    eprintln!("  --min-audible N     sound timer values below N make no sound (the VIP needs 2)");
+    eprintln!("  --min-beep N        lengthen shorter beeps to N frames");
+    eprintln!("  --ips N             run N instructions a second with the timers at 60Hz (default {})", DEFAULT_IPS);
+    eprintln!("  --legacy-timing     run one instruction and one timer tick every <Delay> ms instead");
+    eprintln!("  --timing FILE       run frames by the cycle-cost table in FILE instead");
+    eprintln!("  --frame-skip N      while fast-forwarding or at delay 0, draw only every Nth frame (default 8)");
+    eprintln!("  --audio-device NAME play the buzzer on NAME instead of the default output");
+    eprintln!("  --tone HZ           pitch of the buzzer (default 440)");
+    eprintln!("  --volume PERCENT    loudness of the buzzer, 0 to 100 (default 25)");
+    eprintln!("  --mute              play no sound");
+    eprintln!("  --list-audio-devices print the names --audio-device accepts and exit");
+    eprintln!("  --turbo KEY=K       holding host key KEY mashes keypad key K, may be repeated");
+    eprintln!("  --turbo-rate N      presses a second of turbo keys (default 10)");
+    eprintln!("  --list-keys         print the keypad, hotkey and controller bindings in effect and exit");
+    process::exit(1);
+}
 
-    // Bnnn - JP V0, addr: Jump to location nnn + V0
-    fn op_bnnnn(&mut self) {
-        let address = self.opcode & 0x0FFF;
+fn main() {
+    let args: Vec<String> = env::args().collect();
 
-        self.pc = (self.registers[0] as u16) + address;
+    if let Some(at) = args.iter().position(|arg| arg == "--headless") {
+        let mut rest = args[1..].to_vec();
+        rest.remove(at - 1);
+        process::exit(headless::run(&args[0], &rest));
     }
 
-    // Cxkk - RND Vx, byte: Set Vx = random byte AND kk
-    fn op_cxkk(&mut self) {
-        let mut rng = rand::thread_rng();
-        
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let byte = (self.opcode & 0x00FF) as u8;
-
-        let vx_idx = Vx as usize;
-
-        self.registers[vx_idx] = rng.gen::<u8>() & byte;
+    if args.len() > 1 {
+        match args[1].as_str() {
+            "suite" => process::exit(suite::run(&args[0], &args[2..])),
+            "gen" => process::exit(fuzz::run_gen(&args[0], &args[2..])),
+            "fuzz" => process::exit(fuzz::run_fuzz(&args[0], &args[2..])),
+            "render" => process::exit(render::run(&args[0], &args[2..])),
+            "disasm" => process::exit(disasm::run(&args[0], &args[2..])),
+            "scenario" => process::exit(scenario::run(&args[0], &args[2..])),
+            "verify" => process::exit(capture::run_verify(&args[0], &args[2..])),
+            "sprite-edit" => process::exit(sprite_editor::run(&args[0], &args[2..])),
+            "sprites" => process::exit(sprites::run(&args[0], &args[2..])),
+            _ => {}
+        }
     }
 
-    // Dxyn - DRW Vx, Vy, nibble: Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision
-    fn op_dxyn(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let Vy = ((self.opcode & 0x00F0) >> 4) as u8;
-        let height = (self.opcode & 0x000F) as u8;
-
-        
-        let vx_idx = Vx as usize;
-        let vy_idx = Vy as usize;
-
-
-        let xPos = self.registers[vx_idx] % (VIDEO_WIDTH as u8);
-        let yPos = self.registers[vy_idx] % (VIDEO_HEIGHT as u8);
-
-        self.registers[0xF] = 0;
-
-        for row in 0..height {
-            let spriteByte = self.memory[(self.index + (row as u16)) as usize];
-
-            for col in 0..8 {
-                let spritePixel = spriteByte & (0x80 >> col);
-                let mut screenPixel = self.video[(((yPos + row) as u32) * VIDEO_WIDTH + ((xPos + col) as u32)) as usize];
-
-                if spritePixel != 0 {
-                    if screenPixel == 0xFFFFFFFF {
-                        self.registers[0xF] = 1;
-                    }
-
-                    screenPixel ^= 0xFFFFFFFF;
+    let mut positional: Vec<&String> = Vec::new();
+    let mut pause_at_start = false;
+    let mut debug = false;
+    let mut breaks: Vec<&String> = Vec::new();
+    let mut symbols_file: Option<&String> = None;
+    let mut trace_file: Option<&String> = None;
+    let mut trace_range: Option<&String> = None;
+    let mut trace_last: Option<&String> = None;
+    let mut watch_exprs: Vec<&String> = Vec::new();
+    let mut keymap: Option<Keymap> = None;
+    let mut key_bindings: Vec<&String> = Vec::new();
+    let mut scale: Option<u32> = None;
+    let mut palette_name: Option<&String> = None;
+    let mut foreground: Option<&String> = None;
+    let mut background: Option<&String> = None;
+    let mut quit_key: Option<Keycode> = None;
+    let mut confirm_quit = false;
+    let mut config_file: Option<&String> = None;
+    let mut fullscreen = false;
+    let mut cdp1802 = false;
+    let mut hires_detection = true;
+    let mut schip: Option<bool> = None;
+    let mut xochip: Option<bool> = None;
+    let mut quirks: Option<Quirks> = None;
+    let mut list_quirks = false;
+    let mut list_profiles = false;
+    let mut list_palettes = false;
+    let mut frame_skip: Option<u32> = None;
+    let mut ips: Option<u32> = None;
+    let mut legacy_timing = false;
+    let mut timing_file: Option<&String> = None;
+    let mut monitor: Option<i32> = None;
+    let mut list_keys = false;
+    let mut list_audio_devices = false;
+    let mut audio_device: Option<&String> = None;
+    let mut tone_hz = audio::DEFAULT_TONE_HZ;
+    let mut volume = audio::DEFAULT_VOLUME;
+    let mut mute = false;
+    let mut turbo_keys: Vec<&String> = Vec::new();
+    let mut turbo_rate: Option<u32> = None;
+    // Kept small enough for the TOML integers of fixtures
+    let mut seed = rand::random::<u32>() as u64;
+    let mut capture_file: Option<&String> = None;
+    let mut record_file: Option<&String> = None;
+    let mut playback_file: Option<&String> = None;
+    let mut speedrun_file: Option<&String> = None;
+    let mut broadcast_addr: Option<&String> = None;
+    let mut commands_source: Option<&String> = None;
+    let mut plugin_paths: Vec<&String> = Vec::new();
+    let mut beep = Beep::default();
+
+    let mut iter = args[1..].iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--pause-at-start" => pause_at_start = true,
+            "--debug" => debug = true,
+            "--break" => breaks.push(iter.next().unwrap_or_else(|| usage(&args[0]))),
+            "--symbols" => symbols_file = Some(iter.next().unwrap_or_else(|| usage(&args[0]))),
+            "--trace" => trace_file = Some(iter.next().unwrap_or_else(|| usage(&args[0]))),
+            "--trace-range" => trace_range = Some(iter.next().unwrap_or_else(|| usage(&args[0]))),
+            "--trace-last" => trace_last = Some(iter.next().unwrap_or_else(|| usage(&args[0]))),
+            "--keypad" => {
+                let name = iter.next().unwrap_or_else(|| usage(&args[0]));
+                keymap = Some(Keymap::layout(name).unwrap_or_else(|| {
+                    eprintln!("Unknown keypad layout {}", name);
+                    usage(&args[0]);
+                }));
+            }
+            "--key" => key_bindings.push(iter.next().unwrap_or_else(|| usage(&args[0]))),
+            "--scale" => {
+                let n = iter.next().unwrap_or_else(|| usage(&args[0]));
+                scale = Some(n.parse().ok().filter(|&n| n > 0).unwrap_or_else(|| {
+                    eprintln!("--scale needs a positive integer");
+                    process::exit(1);
+                }));
+            }
+            "--palette" => palette_name = Some(iter.next().unwrap_or_else(|| usage(&args[0]))),
+            "--list-palettes" => list_palettes = true,
+            "--foreground" => foreground = Some(iter.next().unwrap_or_else(|| usage(&args[0]))),
+            "--background" => background = Some(iter.next().unwrap_or_else(|| usage(&args[0]))),
+            "--quit-key" => {
+                let name = iter.next().unwrap_or_else(|| usage(&args[0]));
+                quit_key = Some(Keycode::from_name(name).unwrap_or_else(|| {
+                    eprintln!("Unknown key {}", name);
+                    usage(&args[0]);
+                }));
+            }
+            "--confirm-quit" => confirm_quit = true,
+            "--config" => config_file = Some(iter.next().unwrap_or_else(|| usage(&args[0]))),
+            "--fullscreen" => fullscreen = true,
+            "--cdp1802" => cdp1802 = true,
+            "--no-hires" => hires_detection = false,
+            "--schip" => {
+                schip = Some(true);
+                xochip = Some(false);
+            }
+            "--chip8" => {
+                schip = Some(false);
+                xochip = Some(false);
+            }
+            "--xochip" => xochip = Some(true),
+            "--quirks" => {
+                let spec = iter.next().unwrap_or_else(|| usage(&args[0]));
+                quirks = Some(Quirks::parse(spec).unwrap_or_else(|e| {
+                    eprintln!("Bad --quirks: {}", e);
+                    process::exit(1);
+                }));
+            }
+            "--list-quirks" => list_quirks = true,
+            "--list-profiles" => list_profiles = true,
+            "--monitor" => {
+                let n = iter.next().unwrap_or_else(|| usage(&args[0]));
+                monitor = Some(n.parse().unwrap_or_else(|_| {
+                    eprintln!("This argument is not integer!");
+                    process::exit(1);
+                }));
+            }
+            "--frame-skip" => {
+                let n = iter.next().unwrap_or_else(|| usage(&args[0]));
+                frame_skip = Some(n.parse().ok().filter(|&n| n > 0).unwrap_or_else(|| {
+                    eprintln!("--frame-skip needs a positive integer");
+                    process::exit(1);
+                }));
+            }
+            "--ips" => {
+                let n = iter.next().unwrap_or_else(|| usage(&args[0]));
+                ips = Some(n.parse().ok().filter(|&n| n > 0).unwrap_or_else(|| {
+                    eprintln!("--ips needs a positive integer");
+                    process::exit(1);
+                }));
+            }
+            "--legacy-timing" => legacy_timing = true,
+            "--timing" => timing_file = Some(iter.next().unwrap_or_else(|| usage(&args[0]))),
+            "--list-keys" => list_keys = true,
+            "--list-audio-devices" => list_audio_devices = true,
+            "--turbo" => turbo_keys.push(iter.next().unwrap_or_else(|| usage(&args[0]))),
+            "--turbo-rate" => {
+                let n = iter.next().unwrap_or_else(|| usage(&args[0]));
+                turbo_rate = Some(n.parse().ok().filter(|&n| n > 0).unwrap_or_else(|| {
+                    eprintln!("--turbo-rate needs a positive integer");
+                    process::exit(1);
+                }));
+            }
+            "--tone" => {
+                let n = iter.next().unwrap_or_else(|| usage(&args[0]));
+                tone_hz = n.parse().ok().filter(|&n| n > 0).unwrap_or_else(|| {
+                    eprintln!("--tone needs a positive integer");
+                    process::exit(1);
+                });
+            }
+            "--volume" => {
+                let n = iter.next().unwrap_or_else(|| usage(&args[0]));
+                volume = n.parse().ok().filter(|&n| n <= 100).unwrap_or_else(|| {
+                    eprintln!("--volume needs an integer from 0 to 100");
+                    process::exit(1);
+                });
+            }
+            "--mute" => mute = true,
+            "--audio-device" => audio_device = Some(iter.next().unwrap_or_else(|| usage(&args[0]))),
+            "--seed" => {
+                let n = iter.next().unwrap_or_else(|| usage(&args[0]));
+                seed = n.parse().unwrap_or_else(|_| {
+                    eprintln!("This argument is not integer!");
+                    process::exit(1);
+                });
+            }
+            "--capture" => capture_file = Some(iter.next().unwrap_or_else(|| usage(&args[0]))),
+            "--record" => record_file = Some(iter.next().unwrap_or_else(|| usage(&args[0]))),
+            "--playback" => playback_file = Some(iter.next().unwrap_or_else(|| usage(&args[0]))),
+            "--watch" => watch_exprs.push(iter.next().unwrap_or_else(|| usage(&args[0]))),
+            "--speedrun" => speedrun_file = Some(iter.next().unwrap_or_else(|| usage(&args[0]))),
+            "--min-audible" | "--min-beep" => {
+                let n = iter.next().unwrap_or_else(|| usage(&args[0]));
+                let n = n.parse().unwrap_or_else(|_| {
+                    eprintln!("This argument is not integer!");
+                    process::exit(1);
+                });
+                if arg == "--min-audible" {
+                    beep.min_audible = n;
+                } else {
+                    beep.min_frames = n;
                 }
             }
+            "--commands" => commands_source = Some(iter.next().unwrap_or_else(|| usage(&args[0]))),
+            "--broadcast" => broadcast_addr = Some(iter.next().unwrap_or_else(|| usage(&args[0]))),
+            "--plugin" => plugin_paths.push(iter.next().unwrap_or_else(|| usage(&args[0]))),
+            flag if flag.starts_with("--") => {
+                eprintln!("Unknown option {}", flag);
+                usage(&args[0]);
+            }
+            _ => positional.push(arg),
         }
     }
 
-    // Ex9E - SKP Vx: Skip next instruction if key with the value of Vx is pressed
-    fn op_ex9e(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let vx_idx = Vx as usize; 
-
-        let key = self.registers[vx_idx];
-
-        let keypad: Option<u8> = Some(self.keypad[key as usize]);
+    let config = Config::load(config_file.map(|s| s.as_str())).unwrap_or_else(|e| {
+        eprintln!("Error loading config: {}", e);
+        process::exit(1);
+    });
+    let frame_skip = frame_skip.or(config.frame_skip).unwrap_or(DEFAULT_FRAME_SKIP).max(1);
+    let audio_device = audio_device.or(config.audio_device.as_ref());
+
+    // A lone .c8session argument stands in for <Scale> <Delay> <ROM>
+    let session_path = match positional.as_slice() {
+        [path] if session::is_session(path) => Some(Path::new(path.as_str())),
+        _ => None,
+    };
+    let session = match session_path {
+        Some(path) => Session::load(path).unwrap_or_else(|e| {
+            eprintln!("Error loading session: {}", e);
+            process::exit(1);
+        }),
+        None => Session::default(),
+    };
 
-        if keypad.is_some() {
-            self.pc += 2;
+    // Command line over session over config file over defaults
+    let mut keymap = keymap.unwrap_or_else(|| match session.keypad.as_ref().or(config.keypad.as_ref()) {
+        Some(name) => Keymap::layout(name).unwrap_or_else(|| {
+            eprintln!("Unknown keypad layout {}", name);
+            process::exit(1);
+        }),
+        None => Keymap::default(),
+    });
+    for (name, key) in &config.keys {
+        if let Err(e) = keymap.bind(name, key) {
+            eprintln!("Error in config: keys: {}", e);
+            process::exit(1);
         }
     }
-
-    // ExA1 - SKNP Vx: Skip next instruction if key with the value of Vx is not pressed
-    fn op_exa1(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let vx_idx = Vx as usize; 
-
-        let key = self.registers[vx_idx];
-
-        let keypad: Option<u8> = Some(self.keypad[key as usize]);
-        
-        if keypad.is_none() {
-            self.pc += 2;
+    for (name, key) in &session.keys {
+        if let Err(e) = keymap.bind(name, key) {
+            eprintln!("Error loading session: keys: {}", e);
+            process::exit(1);
+        }
+    }
+    for binding in key_bindings {
+        let result = match binding.split_once('=') {
+            Some((name, key)) => keymap.bind(name, key),
+            None => Err(format!("expected KEY=K, got `{}`", binding)),
+        };
+        if let Err(e) = result {
+            eprintln!("Bad --key: {}", e);
+            process::exit(1);
         }
     }
 
-    // Fx07 - LD Vx, DT: Set Vx = delay timer value.
-    fn op_fx07(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let vx_idx = Vx as usize; 
+    // Left to the ROM's mode when none of them gives any
+    let quirks = quirks.or_else(|| session.quirks.as_ref().or(config.quirks.as_ref()).map(|spec| Quirks::parse(spec).unwrap_or_else(|e| {
+        eprintln!("Bad quirks: {}", e);
+        process::exit(1);
+    })));
 
-        self.registers[vx_idx] = self.delay_timer;
+    let mut colors = match palette_name.or(config.palette.as_ref()) {
+        Some(name) => palette::preset(name).unwrap_or_else(|| {
+            eprintln!("Unknown palette `{}`, see --list-palettes", name);
+            process::exit(1);
+        }),
+        None => palette::DEFAULT,
+    };
+    for (i, color) in [(0, background.or(config.background.as_ref())), (1, foreground.or(config.foreground.as_ref()))] {
+        if let Some(color) = color {
+            colors[i] = palette::parse_color(color).unwrap_or_else(|e| {
+                eprintln!("Bad colour: {}", e);
+                process::exit(1);
+            });
+        }
     }
 
-    // Fx0A - LD Vx, K: Wait for a key press, store the value of the key in Vx.
-    fn op_fx0a(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let vx_idx = Vx as usize; 
-
-        let mut keypad: [Option<u8>; 16] = [Some(0); 16];
+    let mut overrides = config.hotkeys.clone();
+    overrides.extend(session.hotkeys.clone());
+    let mut hotkeys = Hotkeys::from_config(&overrides).unwrap_or_else(|e| {
+        eprintln!("Error in config: {}", e);
+        process::exit(1);
+    });
+    if let Some(key) = quit_key {
+        hotkeys.bind(Action::Quit, key);
+    }
 
-        for i in 0..16 {
-            keypad[i as usize] = Some(self.keypad[i as usize]);
+    let mut turbo = Turbo::from_config(&config.turbo).unwrap_or_else(|e| {
+        eprintln!("Error in config: turbo: {}", e);
+        process::exit(1);
+    });
+    for (name, key) in &session.turbo {
+        if let Err(e) = turbo.bind(name, key) {
+            eprintln!("Error loading session: turbo: {}", e);
+            process::exit(1);
         }
-
-        if keypad[0].is_some() {
-            self.registers[vx_idx] = 0;
-        } else if keypad[1].is_some() {
-            self.registers[vx_idx] = 1;
-        } else if keypad[2].is_some() {
-            self.registers[vx_idx] = 2;
-        } else if keypad[3].is_some() {
-            self.registers[vx_idx] = 3;
-        } else if keypad[4].is_some() {
-            self.registers[vx_idx] = 4;
-        } else if keypad[5].is_some() {
-            self.registers[vx_idx] = 5;
-        } else if keypad[6].is_some() {
-            self.registers[vx_idx] = 6;
-        } else if keypad[7].is_some() {
-            self.registers[vx_idx] = 7;
-        } else if keypad[8].is_some() {
-            self.registers[vx_idx] = 8;
-        } else if keypad[9].is_some() {
-            self.registers[vx_idx] = 9;
-        } else if keypad[10].is_some() {
-            self.registers[vx_idx] = 10;
-        } else if keypad[11].is_some() {
-            self.registers[vx_idx] = 11;
-        } else if keypad[12].is_some() {
-            self.registers[vx_idx] = 12;
-        } else if keypad[13].is_some() {
-            self.registers[vx_idx] = 13;
-        } else if keypad[14].is_some() {
-            self.registers[vx_idx] = 14;
-        } else if keypad[15].is_some() {
-            self.registers[vx_idx] = 15;
-        } else {
-            self.pc -= 2;
+    }
+    for binding in turbo_keys {
+        let result = match binding.split_once('=') {
+            Some((name, key)) => turbo.bind(name, key),
+            None => Err(format!("expected KEY=K, got `{}`", binding)),
+        };
+        if let Err(e) = result {
+            eprintln!("Bad --turbo: {}", e);
+            process::exit(1);
         }
     }
+    turbo.rate = turbo_rate.or(session.turbo_rate).or(config.turbo_rate).unwrap_or(turbo::DEFAULT_RATE);
 
-    // Fx15 - LD DT, Vx: Set delay timer = Vx
-    fn op_fx15(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let vx_idx = Vx as usize;
-
-        self.delay_timer = self.registers[vx_idx];
+    // Games go by the name their save states are kept under
+    let game = positional.last().map(|path| rom::name(Path::new(path.as_str()))).unwrap_or_default();
+    let mut buttons = controller_buttons(&config, &game).unwrap_or_else(|e| {
+        eprintln!("Error in config: {}", e);
+        process::exit(1);
+    });
+    if let Err(e) = buttons.apply_config(&session.controller) {
+        eprintln!("Error loading session: controller: {}", e);
+        process::exit(1);
     }
 
-    // Fx18 - LD ST, Vx: Set sound timer = Vx
-    fn op_fx18(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let vx_idx = Vx as usize;
-
-        self.sound_timer = self.registers[vx_idx];
+    if list_keys {
+        info::list_keys(&keymap, &hotkeys, &turbo, &buttons);
+        process::exit(0);
     }
-
-    // Fx1E - ADD I, Vx: Set I = I + Vx
-    fn op_fx1e(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let vx_idx = Vx as usize;
-
-        self.index += (self.registers[vx_idx]) as u16;
+    if list_quirks {
+        info::list_quirks(&quirks.unwrap_or_default());
+        process::exit(0);
     }
-
-    // Fx29 - LD F, Vx: Set I = location of sprite for digit Vx
-    fn op_fx29(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let vx_idx = Vx as usize;
-        let digit = self.registers[vx_idx];
-
-        self.index = (FONTSET_START_ADDRESS + (5 * digit)) as u16;
+    if list_profiles {
+        info::list_profiles(&quirks.unwrap_or_default());
+        process::exit(0);
     }
-
-    // Fx33 - LD B, Vx: Store BCD representation of Vx in memory locations I, I+1, and I+2
-    fn op_fx33(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let vx_idx = Vx as usize;
-        let mut value = self.registers[vx_idx];
-
-        // Ones place
-        self.memory[(self.index + 2) as usize] = value % 10;
-        value /= 10;
-
-        // Tens place
-        self.memory[(self.index + 1) as usize] = value % 10;
-        value /= 10;
-
-        // Hundreds Place
-        self.memory[self.index as usize] = value % 10;
+    if list_palettes {
+        info::list_palettes(&colors);
+        process::exit(0);
     }
-
-    // Fx55 - LD [I], Vx: Store registers V0 through Vx in memory starting at location I
-    fn op_fx55(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-
-        for i in 0..Vx {
-            self.memory[(self.index + i as u16) as usize] = self.registers[i as usize];
+    if list_audio_devices {
+        let audio_subsystem = sdl2::init().and_then(|sdl| audio::subsystem(&sdl)).unwrap_or_else(|e| {
+            eprintln!("Error initialising audio: {}", e);
+            process::exit(1);
+        });
+        if let Err(e) = info::list_audio_devices(&audio_subsystem) {
+            eprintln!("Error listing audio devices: {}", e);
+            process::exit(1);
         }
+        process::exit(0);
     }
 
-    // Fx65 - LD Vx, [I]: Read registers V0 through Vx from memory starting at location I
-    fn op_fx65(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-
-        for i in 0..Vx {
-            self.registers[i as usize] = self.memory[(self.index + i as u16) as usize];
-        }
+    // Name sessions saved from this run are written under
+    let mut rom_name: String;
+    let mut rom: Vec<u8>;
+    let mut video_scale: u32;
+    let cycle_delay: u32;
+    if [ips.is_some(), legacy_timing, timing_file.is_some()].iter().filter(|&&given| given).count() > 1 {
+        eprintln!("Only one of --ips, --legacy-timing and --timing can be given");
+        process::exit(1);
     }
-
-    // NULL : function that does nothing, but will be the default function called if a proper function pointer is not set
-    fn op_null(&mut self) {
-        
+    // Recordings and fixtures replay by instructions a second
+    if timing_file.is_some() && (capture_file.is_some() || record_file.is_some() || playback_file.is_some()) {
+        eprintln!("--timing can't be used with --capture, --record or --playback");
+        process::exit(1);
     }
-}
-
-impl Chip8 {
-    fn cycle(&mut self) {
-
-        // Fetch
-        let opcode: u16 = ((self.memory[self.pc as usize] as u16) << 8) | (self.memory[(self.pc+1) as usize] as u16);
-
-        // Increment program counter 
-        self.pc += 2;
-
-        // Decode and Execute
-        match opcode {
-            0x0 => {
-                match opcode & 0x000F {
-                    0x0 => self.op_00e0(),
-                    0xE => self.op_00ee(),
-                    _ => self.op_null(),
-                }
-            },
-            0x1 => self.op_1nnn(),
-            0x2 => self.op_2nnn(),
-            0x3 => self.op_3xkk(),
-            0x4 => self.op_4xkk(),
-            0x5 => self.op_5xy0(),
-            0x6 => self.op_6xkk(),
-            0x7 => self.op_7xkk(),
-            0x8 => {
-                match opcode & 0x000F  {
-                    0x0 => self.op_8xy0(),
-                    0x1 => self.op_8xy1(),
-                    0x2 => self.op_8xy2(),
-                    0x3 => self.op_8xy3(),
-                    0x4 => self.op_8xy4(),
-                    0x5 => self.op_8xy5(),
-                    0x6 => self.op_8xy6(),
-                    0x7 => self.op_8xy7(),
-                    0xE => self.op_8xye(),
-                    _ => self.op_null(),
-                }
-            },
-            0x9 => self.op_9xy0(),
-            0xA => self.op_annn(),
-            0xB => self.op_bnnnn(),
-            0xC => self.op_cxkk(),
-            0xD => self.op_dxyn(),
-            0xE => {
-                match opcode & 0x000F {
-                    0x1 => self.op_exa1(),
-                    0xE => self.op_ex9e(),
-                    _ => self.op_null(),
-                }
-            },
-            0xF => {
-                match opcode & 0x00FF {
-                    0x07 => self.op_fx07(),
-                    0x0A => self.op_fx0a(),
-                    0x15 => self.op_fx15(),
-                    0x18 => self.op_fx18(),
-                    0x1E => self.op_fx1e(),
-                    0x29 => self.op_fx29(),
-                    0x33 => self.op_fx33(),
-                    0x55 => self.op_fx55(),
-                    0x65 => self.op_fx65(),
-                    _ => self.op_null(),
-                }
-            },
-            _ => self.op_null()
-        }
+    let cost_table = timing_file.map(|path| costs::load(path).unwrap_or_else(|e| {
+        eprintln!("Error loading timing table: {}", e);
+        process::exit(1);
+    }));
 
-        // Decrement the delay timer if it's been set
-        if self.delay_timer > 0 {
-            self.delay_timer -= 1;
+    if let Some(path) = session_path {
+        rom_name = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+        rom = session.rom(path).unwrap_or_else(|e| {
+            eprintln!("Error loading session: {}", e);
+            process::exit(1);
+        });
+        video_scale = scale.or(session.scale).or(config.scale).unwrap_or(DEFAULT_SCALE);
+        cycle_delay = session.delay.unwrap_or(DEFAULT_DELAY);
+        if !legacy_timing && cost_table.is_none() {
+            ips = ips.or(session.ips);
+            legacy_timing = ips.is_none() && session.legacy_timing == Some(true);
         }
-
-        // Decrement the sound timer if it's been set
-        if self.sound_timer > 0 {
-            self.sound_timer -= 1;
+    } else if let [rom_file_name] = positional.as_slice() {
+        rom_name = rom::name(Path::new(rom_file_name.as_str()));
+        rom = rom::read(rom_file_name).unwrap_or_else(|e| {
+            eprintln!("Error reading {}: {}", rom_file_name, e);
+            process::exit(1);
+        });
+        video_scale = scale.or(config.scale).unwrap_or(DEFAULT_SCALE);
+        cycle_delay = DEFAULT_DELAY;
+    } else {
+        if positional.len() != 3 {
+            usage(&args[0]);
         }
-    }
-}
-
-struct Platform<'a> {
-    canvas: Canvas<Window>,
-    texture: Texture<'a>,
-}
 
-impl<'a> Platform<'a> {
-    fn new(canvas: Canvas<Window>, texture: Texture<'a>) -> Result<Self, String> {
-        // Return platform instance
-        Ok(Platform { 
-            canvas,
-            texture,
-        })
-    }
-
-    fn update(&mut self, buffer: &[u8], pitch: usize) -> Result<(), String> {
-        // Update the texture with the buffer data
-        self.texture.update(None, buffer, pitch as usize)
-            .map_err(|e| e.to_string())?;
-
-        // Clear the renderer, copy the texture, and present it to the screen
-        self.canvas.clear();
-        self.canvas.copy(&self.texture, None, None)
-            .map_err(|e| e.to_string())?;
-        self.canvas.present();
-
-        Ok(())
-    }
-
-    fn process_input(&mut self, sdl_context: &Sdl, mut keys: [u8; 16]) -> bool {
-        let mut event_pump = sdl_context.event_pump().unwrap();
-        let mut quit = false;
+        let rom_file_name = positional[2].clone();
+        rom_name = rom::name(Path::new(&rom_file_name));
+        rom = rom::read(&rom_file_name).unwrap_or_else(|e| {
+            eprintln!("Error reading {}: {}", rom_file_name, e);
+            process::exit(1);
+        });
 
-        for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit {..} => {
-                    quit = true;
-                }
-                Event::KeyDown { keycode: Some(key), .. } => {
-                    match key {
-                        Keycode::Escape => {
-                            quit = true;
-                        }
-                        Keycode::X => keys[0] = 1,
-                        Keycode::Num1 => keys[1] = 1,
-                        Keycode::Num2 => keys[2] = 1,
-                        Keycode::Num3 => keys[3] = 1,
-                        Keycode::Q => keys[4] = 1,
-                        Keycode::W => keys[5] = 1,
-                        Keycode::E => keys[6] = 1,
-                        Keycode::A => keys[7] = 1,
-                        Keycode::S => keys[8] = 1,
-                        Keycode::D => keys[9] = 1,
-                        Keycode::Z => keys[0xA] = 1,
-                        Keycode::C => keys[0xB] = 1,
-                        Keycode::Num4 => keys[0xC] = 1,
-                        Keycode::R => keys[0xD] = 1,
-                        Keycode::F => keys[0xE] = 1,
-                        Keycode::V => keys[0xF] = 1,
-                        _ => {}
-                    }
-                }
-                Event::KeyUp { keycode: Some(key), .. } => {
-                    match key {
-                        Keycode::X => keys[0] = 0,
-                        Keycode::Num1 => keys[1] = 0,
-                        Keycode::Num2 => keys[2] = 0,
-                        Keycode::Num3 => keys[3] = 0,
-                        Keycode::Q => keys[4] = 0,
-                        Keycode::W => keys[5] = 0,
-                        Keycode::E => keys[6] = 0,
-                        Keycode::A => keys[7] = 0,
-                        Keycode::S => keys[8] = 0,
-                        Keycode::D => keys[9] = 0,
-                        Keycode::Z => keys[0xA] = 0,
-                        Keycode::C => keys[0xB] = 0,
-                        Keycode::Num4 => keys[0xC] = 0,
-                        Keycode::R => keys[0xD] = 0,
-                        Keycode::F => keys[0xE] = 0,
-                        Keycode::V => keys[0xF] = 0,
-                        _ => {}
-                    }
-                }
-                _ => {}    
+        video_scale = match positional[0].parse::<u32>() {
+            Ok(num) => scale.unwrap_or(num),
+            Err(_) => {
+                eprintln!("This argument is not integer!");
+                process::exit(1);
             }
-        }
+        };
 
-        quit
+        cycle_delay = match positional[1].parse::<u32>() {
+            Ok(num) => num,
+            Err(_) => {
+                eprintln!("This argument is not integer!");
+                process::exit(1);
+            }
+        };
     }
-}
-
-fn main() {
-    let args: Vec<String> = env::args().collect();
-
-    if args.len() != 4 {
-        eprintln!("Usage: {} <Scale> <Delay> <ROM>\n", args[0]);
+    if let Err(e) = chip8_core::check_rom_size(&rom) {
+        eprintln!("Error loading {}: {}", rom_name, e);
         process::exit(1);
     }
+    // Frames of instructions at 60Hz. With --legacy-timing the timers tick with
+    // every instruction instead, and a frame is the instructions run in 1/60s.
+    let ips = match ips {
+        Some(ips) => ips,
+        None if legacy_timing => legacy_frame(cycle_delay) * FRAME_RATE,
+        None => DEFAULT_IPS,
+    };
+    // Kept for ROMs dropped on the window later
+    let (forced_schip, forced_xochip, forced_quirks) = (schip, xochip, quirks);
+    // A session's mode unless the command line picks one
+    let (schip, xochip) = match (schip, xochip) {
+        (None, None) => (session.schip, session.xochip),
+        given => given,
+    };
+    let (schip, xochip, quirks) = Analysis::new(&rom).mode(schip, xochip, quirks);
 
-    let mut video_scale: u32;
-    let mut cycle_delay: u32;
-    let mut rom_file_name = args[3].clone();
-
-    match args[1].parse::<u32>() {
-        Ok(num) => {
-            video_scale = num;
-        },
-        Err(_) => {
-            eprintln!("This argument is not integer!");
+    // A recording brings the settings it was made with
+    let mut playback = playback_file.map(|path| {
+        let replay = Replay::load(path).unwrap_or_else(|e| {
+            eprintln!("Error loading recording: {}", e);
             process::exit(1);
+        });
+        if !replay.matches(&rom) {
+            eprintln!("Warning: {} was recorded with a different ROM", path);
         }
+        println!("Playing back {} frames", replay.frames());
+        replay
+    });
+    let (seed, ips, legacy_timing, mut schip, mut xochip, cdp1802, hires_detection, mut quirks) = match &playback {
+        Some(replay) => (replay.seed, replay.ips, replay.legacy_timing, replay.schip, replay.xochip, replay.cdp1802, replay.hires_detection, replay.quirks),
+        None => (seed, ips, legacy_timing, schip, xochip, cdp1802, hires_detection, quirks),
     };
+    // Fixtures run a timer tick with every instruction without one
+    let fixture_ips = (!legacy_timing).then_some(ips);
 
-    match args[2].parse::<u32>() {
-        Ok(num) => {
-            cycle_delay = num;
-        },
-        Err(_) => {
-            eprintln!("This argument is not integer!");
+    let sdl_context = sdl2::init().map_err(|e| e.to_string()).unwrap();
+    let video_subsystem = sdl_context.video().map_err(|e| e.to_string()).unwrap();
+
+    // A missing default output only costs the sound, a missing chosen device is an error
+    let opened = (!mute).then(|| {
+        audio::subsystem(&sdl_context).and_then(|audio| Buzzer::open(&audio, audio_device.map(|s| s.as_str()), tone_hz, volume))
+    });
+    let buzzer = match opened {
+        None => None,
+        Some(Ok(buzzer)) => Some(buzzer),
+        Some(Err(e)) if audio_device.is_none() => {
+            eprintln!("No sound: {}", e);
+            None
+        }
+        Some(Err(e)) => {
+            eprintln!("Error opening audio device: {}", e);
             process::exit(1);
         }
     };
 
-    let sdl_context = sdl2::init().map_err(|e| e.to_string()).unwrap();
+    // An explicit --monitor is remembered, otherwise reuse the last one if it's still connected
+    let displays = video_subsystem.num_video_displays().map_err(|e| e.to_string()).unwrap();
+    let monitor = match monitor {
+        Some(n) if n < 0 || n >= displays => {
+            eprintln!("No display {} (found {})", n, displays);
+            process::exit(1);
+        }
+        Some(n) => {
+            if let Err(e) = config::remember_monitor(n) {
+                eprintln!("Couldn't remember display choice: {}", e);
+            }
+            n
+        }
+        None => config::last_monitor().filter(|&n| n >= 0 && n < displays).unwrap_or(0),
+    };
 
     // Create window
-    let window = sdl_context
-        .video()
-        .map_err(|e| e.to_string()).unwrap()
-        .window("CHIP-8 Emulator", VIDEO_WIDTH * video_scale, VIDEO_HEIGHT * video_scale)
-        .position_centered()
+    let (window_width, window_height) = (VIDEO_WIDTH * video_scale, VIDEO_HEIGHT * video_scale);
+    let bounds = video_subsystem.display_bounds(monitor).map_err(|e| e.to_string()).unwrap();
+    let mut window_builder = video_subsystem.window("CHIP-8 Emulator", window_width, window_height);
+    // Centre on the chosen display; fullscreen goes to whichever display holds the window
+    window_builder.position(
+        bounds.x() + (bounds.width() as i32 - window_width as i32) / 2,
+        bounds.y() + (bounds.height() as i32 - window_height as i32) / 2,
+    );
+    if fullscreen {
+        window_builder.fullscreen_desktop();
+    }
+    let window = window_builder
         .build()
         .map_err(|e| e.to_string()).unwrap();
 
@@ -756,40 +947,522 @@ fn main() {
     let texture_creator = canvas.texture_creator();
     let texture = texture_creator
         .create_texture_target(
-        PixelFormatEnum::RGBA8888,
-        VIDEO_WIDTH,
-        VIDEO_HEIGHT,
+        PixelFormatEnum::ARGB8888,
+        MAX_VIDEO_WIDTH,
+        MAX_VIDEO_HEIGHT,
     ).map_err(|e| e.to_string()).unwrap();
 
-    let mut pltf = Platform::new(canvas, texture).unwrap();
+    let gamepad = Gamepad::new(&sdl_context, buttons).unwrap();
+    let mut pltf = Platform::new(sdl_context.clone(), canvas, texture, keymap, hotkeys, turbo, gamepad).unwrap();
+    pltf.confirm_quit = confirm_quit;
+    pltf.colors = colors;
+    pltf.buzzer = buzzer;
+
+    #[cfg(feature = "plugins")]
+    let plugins = (!plugin_paths.is_empty()).then(|| {
+        Arc::new(plugins::Plugins::load(&plugin_paths).unwrap_or_else(|e| {
+            eprintln!("Error loading plugin: {}", e);
+            process::exit(1);
+        }))
+    });
+    #[cfg(feature = "plugins")]
+    let peripheral = plugins.clone().map(|plugins| plugins as SharedPeripheral);
+    #[cfg(not(feature = "plugins"))]
+    let peripheral: Option<SharedPeripheral> = if plugin_paths.is_empty() {
+        None
+    } else {
+        eprintln!("Built without plugin support, rebuild with --features plugins");
+        process::exit(1);
+    };
+
+    // Nothing is traced without one of the --trace options
+    let trace = (trace_file.is_some() || trace_range.is_some() || trace_last.is_some()).then(|| {
+        let trace = TraceLog::open(trace_file.map(|s| s.as_str()), trace_range.map(|s| s.as_str()), trace_last.map(|s| s.as_str()));
+        Arc::new(trace.unwrap_or_else(|e| {
+            eprintln!("Bad --trace: {}", e);
+            process::exit(1);
+        }))
+    });
+    let tracer = trace.clone().map(|trace| trace as SharedTracer);
 
     let mut chip8 = Chip8::new();
-    chip8.load_rom(&rom_file_name);
+    chip8.set_peripheral(peripheral.clone());
+    chip8.set_tracer(tracer.clone());
+    chip8.set_beep(beep);
+    chip8.set_cdp1802(cdp1802);
+    chip8.set_hires_detection(hires_detection);
+    chip8.set_schip(schip);
+    chip8.set_xochip(xochip);
+    chip8.set_quirks(quirks);
+    chip8.seed(seed);
+    if let Err(e) = chip8.load_rom(&rom) {
+        eprintln!("Error loading {}: {}", rom_name, e);
+        process::exit(1);
+    }
+    pltf.slots = Slots::open(config::states_dir(&rom_name, &rom), &chip8);
+    let mut capture = capture_file.map(|_| Capture::from_seed(seed, fixture_ips));
+    let mut recording = record_file.map(|_| Recording::from_seed(seed, ips, legacy_timing));
+    match session.state() {
+        Ok(Some(state)) => {
+            chip8.load_state(&state).unwrap_or_else(|e| {
+                eprintln!("Error loading session state: {}", e);
+                process::exit(1);
+            });
+            capture = capture.map(|_| Capture::from_state(&chip8, fixture_ips));
+            recording = recording.map(|_| Recording::from_state(&chip8, ips, legacy_timing));
+        }
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("Error loading session: {}", e);
+            process::exit(1);
+        }
+    }
+    if let Some(state) = playback.as_ref().and_then(|replay| replay.state.as_ref()) {
+        chip8.load_state(state).unwrap_or_else(|e| {
+            eprintln!("Error loading recording: {}", e);
+            process::exit(1);
+        });
+        recording = recording.map(|_| Recording::from_state(&chip8, ips, legacy_timing));
+    }
+
+    let symbols = match symbols_file {
+        Some(path) => Symbols::load(path).unwrap_or_else(|e| {
+            eprintln!("Error loading symbols: {}", e);
+            process::exit(1);
+        }),
+        None => Symbols::default(),
+    };
+    let breakpoints: Vec<u16> = breaks.iter()
+        .map(|b| symbols.resolve(b).unwrap_or_else(|| {
+            eprintln!("Unknown breakpoint address or label {}", b);
+            process::exit(1);
+        }))
+        .collect();
+    let watches: Vec<Watch> = config.watch.iter().chain(watch_exprs)
+        .map(|text| Watch::parse(text, &symbols).unwrap_or_else(|e| {
+            eprintln!("Bad watch expression `{}`: {}", text, e);
+            process::exit(1);
+        }))
+        .collect();
+    let mut show_watches = true;
+    let mut controls_hint = ControlsHint::default();
+
+    let mut debugger = if debug || pause_at_start || !breakpoints.is_empty() {
+        let mut debugger = Debugger::new(pause_at_start, symbols);
+        for addr in breakpoints {
+            debugger.add_breakpoint(addr);
+        }
+        Some(debugger)
+    } else {
+        None
+    };
+    if let Some(debugger) = &debugger {
+        debugger.announce(&chip8);
+    }
 
-    let video_pitch = (mem::size_of::<u32>()) * (VIDEO_WIDTH as usize);
+    let mut commands = commands_source.map(|source| match source.as_str() {
+        "-" if debugger.is_some() => {
+            eprintln!("The debugger already reads stdin, give --commands an address instead");
+            process::exit(1);
+        }
+        "-" => CommandInput::stdin(),
+        addr => CommandInput::listen(addr).unwrap_or_else(|e| {
+            eprintln!("Error listening on {}: {}", addr, e);
+            process::exit(1);
+        }),
+    });
+
+    #[cfg(feature = "broadcast")]
+    let mut broadcaster = broadcast_addr.map(|addr| {
+        let broadcaster = broadcast::Broadcaster::start(addr).unwrap_or_else(|e| {
+            eprintln!("Error listening on {}: {}", addr, e);
+            process::exit(1);
+        });
+        println!("Broadcasting on ws://{}", addr);
+        broadcaster
+    });
+    #[cfg(not(feature = "broadcast"))]
+    if broadcast_addr.is_some() {
+        eprintln!("Built without broadcast support, rebuild with --features broadcast");
+        process::exit(1);
+    }
+
+    let mut video_size = (VIDEO_WIDTH, VIDEO_HEIGHT);
 
     let mut last_cycle_time = Instant::now();
-    let mut quit = false;
+    // The instructions of each 60Hz frame and, without --legacy-timing, when
+    // it's due. A reset starts over from `frame_timing`.
+    let frame_timing = cost_table.unwrap_or_else(|| Timing::from_ips(ips));
+    let mut timing = frame_timing.clone();
+    let mut pacer = Pacer::default();
+    let mut run_state = RunState::Running;
+    // Whether the ROM or the window failed, for the exit status. Either stops
+    // the loop, which still writes out what was recorded.
+    let mut failed = false;
+    // Emulated frames since the display was last drawn
+    let mut undrawn_frames = 0;
+    let mut speedrun = speedrun_file.map(|path| Speedrun::new(path, &rom_name));
+    // The machine and timer just before the last reset, and when that was
+    let mut before_reset: Option<(Chip8, Option<Speedrun>, Instant)> = None;
+    let mut rewind = Rewind::default();
+    // Frames run since the start, or since the recording last started over,
+    // and whether the next has yet to take its keypad from the recordings
+    let mut frame: u64 = 0;
+    let mut inputs_due = true;
+
+    while run_state != RunState::Quitting {
+        // The machine starts over from the ROM once the input is handled, after
+        // a reset or with a newly opened ROM and its name
+        let mut restart = false;
+        let mut opened: Option<(Vec<u8>, String)> = None;
+
+        for action in pltf.process_input(chip8.keypad_mut()) {
+            run_state = run_state.after(action);
+            match action {
+                Action::Quit | Action::FrameAdvance => {}
+                Action::Pause => pltf.osd.show(if run_state == RunState::Paused { "Paused" } else { "Resumed" }),
+                Action::Reset => {
+                    before_reset = Some((chip8.clone(), speedrun.clone(), Instant::now()));
+                    restart = true;
+                    pltf.osd.show(format!("Reset, {} to undo", pltf.hotkeys.key(Action::UndoReset).name()));
+                }
+                Action::UndoReset => match before_reset.take() {
+                    Some((state, timer, at)) if at.elapsed() < UNDO_RESET_WINDOW => {
+                        chip8 = state;
+                        speedrun = timer;
+                        capture = capture.map(|_| Capture::from_state(&chip8, fixture_ips));
+                        recording = recording.map(|_| Recording::from_state(&chip8, ips, legacy_timing));
+                        playback = None;
+                        (frame, inputs_due) = (0, true);
+                        rewind.clear();
+                        timing = frame_timing.clone();
+                        pltf.osd.show("Reset undone");
+                    }
+                    _ => pltf.osd.show("Nothing to undo"),
+                },
+                Action::SaveState => match pltf.slots.save(&chip8) {
+                    Ok(()) => pltf.osd.show(format!("Saved slot {}", pltf.slots.selected + 1)),
+                    Err(e) => {
+                        eprintln!("Error saving state: {}", e);
+                        pltf.osd.show(format!("Slot {} saved for this run only", pltf.slots.selected + 1));
+                    }
+                },
+                Action::LoadState => match pltf.slots.load() {
+                    Some(state) => {
+                        chip8 = state.clone();
+                        // The fixture starts over from the loaded state
+                        capture = capture.map(|_| Capture::from_state(&chip8, fixture_ips));
+                        recording = recording.map(|_| Recording::from_state(&chip8, ips, legacy_timing));
+                        playback = None;
+                        (frame, inputs_due) = (0, true);
+                        rewind.clear();
+                        timing = frame_timing.clone();
+                        pltf.osd.show(format!("Loaded slot {}", pltf.slots.selected + 1));
+                    }
+                    None => pltf.osd.show(format!("Slot {} is empty", pltf.slots.selected + 1)),
+                },
+                Action::SlotBrowser => pltf.browsing = !pltf.browsing,
+                Action::SaveSession => {
+                    let mut session = Session {
+                        scale: Some(video_scale),
+                        delay: Some(cycle_delay),
+                        ips: (!legacy_timing).then_some(ips),
+                        legacy_timing: legacy_timing.then_some(true),
+                        keypad: Some(pltf.keymap.name.to_string()),
+                        schip: Some(chip8.is_schip()),
+                        xochip: Some(chip8.is_xochip()),
+                        quirks: Some(chip8.quirks().enabled().join(",")),
+                        turbo_rate: Some(pltf.turbo.rate),
+                        keys: pltf.keymap.to_config(),
+                        turbo: pltf.turbo.to_config(),
+                        controller: pltf.gamepad.buttons().to_config(),
+                        hotkeys: pltf.hotkeys.to_config(),
+                        ..Session::default()
+                    };
+                    session.embed_rom(&rom);
+                    session.set_state(&chip8.save_state());
+                    let path = format!("{}.{}", rom_name, session::EXTENSION);
+                    match session.save(Path::new(&path)) {
+                        Ok(()) => {
+                            println!("Saved {}", path);
+                            pltf.osd.show(format!("Saved {}", path));
+                        }
+                        Err(e) => eprintln!("Error saving session: {}", e),
+                    }
+                }
+                Action::Screenshot => match pltf.screenshot() {
+                    Ok(path) => {
+                        println!("Saved {}", path);
+                        pltf.osd.show(format!("Saved {}", path));
+                    }
+                    Err(e) => eprintln!("Error saving screenshot: {}", e),
+                },
+                Action::ScaleUp | Action::ScaleDown => {
+                    video_scale = if action == Action::ScaleUp {
+                        (video_scale + 1).min(MAX_SCALE)
+                    } else {
+                        video_scale.saturating_sub(1).max(MIN_SCALE)
+                    };
+                    if let Err(e) = pltf.resize(chip8.video_width(), chip8.video_height(), video_scale) {
+                        eprintln!("Error resizing window: {}", e);
+                        (run_state, failed) = (RunState::Quitting, true);
+                        break;
+                    }
+                    pltf.osd.show(format!("Scale {}x", video_scale));
+                }
+                Action::Split => match speedrun.as_mut().map(Speedrun::split) {
+                    Some(Ok(split)) => pltf.osd.show(split),
+                    Some(Err(e)) => eprintln!("Error writing splits: {}", e),
+                    None => pltf.osd.show("No timer, start with --speedrun"),
+                },
+                Action::Controls => pltf.osd.show(controls_hint.describe(&pltf.keymap)),
+                Action::Watches if watches.is_empty() => pltf.osd.show("No watches, add some with --watch"),
+                Action::Watches => show_watches = !show_watches,
+                Action::FastForward | Action::Rewind => {}
+            }
+        }
 
-    while !quit {
-        quit = pltf.process_input(&sdl_context, chip8.keypad);
+        // A ROM dropped on the window takes over, in the mode it calls for
+        if let Some(path) = pltf.dropped.take() {
+            if session::is_session(&path) {
+                pltf.osd.show("Open sessions from the command line");
+            } else {
+                let image = rom::read(&path).map_err(Chip8Error::from)
+                    .and_then(|image| chip8_core::check_rom_size(&image).map(|()| image));
+                match image {
+                    Ok(image) => {
+                        opened = Some((image, rom::name(Path::new(&path))));
+                        restart = true;
+                    }
+                    Err(e) => {
+                        eprintln!("Error loading {}: {}", path, e);
+                        pltf.osd.show(format!("Couldn't open {}: {}", rom::name(Path::new(&path)), e));
+                    }
+                }
+            }
+        }
+
+        if restart {
+            let (image, mode) = match &opened {
+                Some((image, _)) => (image, Analysis::new(image).mode(forced_schip, forced_xochip, forced_quirks)),
+                None => (&rom, (schip, xochip, quirks)),
+            };
+            let mut machine = Chip8::new();
+            machine.set_peripheral(peripheral.clone());
+            machine.set_tracer(tracer.clone());
+            machine.set_beep(beep);
+            machine.set_cdp1802(cdp1802);
+            machine.set_hires_detection(hires_detection);
+            machine.set_schip(mode.0);
+            machine.set_xochip(mode.1);
+            machine.set_quirks(mode.2);
+            machine.seed(seed);
+            // Only a ROM that loads replaces the running one
+            match machine.load_rom(image) {
+                Ok(()) => {
+                    chip8 = machine;
+                    if let Some((image, name)) = opened {
+                        (rom, rom_name) = (image, name);
+                        (schip, xochip, quirks) = mode;
+                        match controller_buttons(&config, &rom_name) {
+                            Ok(buttons) => pltf.gamepad.set_buttons(buttons),
+                            Err(e) => eprintln!("Error in config: {}", e),
+                        }
+                        pltf.slots = Slots::open(config::states_dir(&rom_name, &rom), &chip8);
+                        speedrun = speedrun_file.map(|path| Speedrun::new(path, &rom_name));
+                        controls_hint = ControlsHint::default();
+                        before_reset = None;
+                        pltf.osd.show(format!("Opened {}", rom_name));
+                    }
+                    capture = capture.map(|_| Capture::from_seed(seed, fixture_ips));
+                    recording = recording.map(|_| Recording::from_seed(seed, ips, legacy_timing));
+                    playback = None;
+                    (frame, inputs_due) = (0, true);
+                    rewind.clear();
+                    timing = frame_timing.clone();
+                    if let Some(speedrun) = &mut speedrun {
+                        speedrun.restart();
+                    }
+                }
+                Err(e) => {
+                    let name = opened.as_ref().map_or(&rom_name, |(_, name)| name);
+                    eprintln!("Error loading {}: {}", name, e);
+                    pltf.osd.show(format!("Couldn't open {}: {}", name, e));
+                }
+            }
+        }
+
+        if let Some(commands) = &mut commands {
+            commands.poll();
+            commands.apply(chip8.keypad_mut());
+        }
+
+        if let Some(debugger) = &mut debugger {
+            debugger.poll(&chip8);
+        }
 
         let current_time = Instant::now();
         let duration = current_time.duration_since(last_cycle_time);
         let dt = duration.as_secs_f32() * 1000.0;
 
-        if dt > (cycle_delay as f32) {
+        let uncapped = pltf.holding(Action::FastForward) || (cycle_delay == 0 && legacy_timing);
+        let due = uncapped || if legacy_timing { dt > (cycle_delay as f32) } else { pacer.frame_due() };
+        if !due && !legacy_timing {
+            thread::sleep(pacer.until_next());
+        }
+        if due {
             last_cycle_time = current_time;
-            chip8.cycle();
-            let buffer: &[u8] = unsafe {
-                // We cast the pointer to a u32 array to a u8 slice, ensuring we get the correct byte representation
-                std::slice::from_raw_parts(
-                    chip8.video.as_ptr() as *const u8, 
-                    chip8.video.len() * std::mem::size_of::<u32>()
-                )
-            };
-            pltf.update(buffer, video_pitch).expect("Error updating");
+
+            if run_state.running() && !pltf.browsing && pltf.holding(Action::Rewind) {
+                if let Some(snapshot) = rewind.step_back() {
+                    if let Err(e) = chip8.load_state(&snapshot.state) {
+                        eprintln!("Error rewinding: {}", e);
+                        (run_state, failed) = (RunState::Quitting, true);
+                        continue;
+                    }
+                    timing = snapshot.timing;
+                    (frame, inputs_due) = (snapshot.frame, true);
+                    if let Some(recording) = &mut recording {
+                        recording.truncate(frame);
+                    }
+                    capture = capture.map(|_| Capture::from_state(&chip8, fixture_ips));
+                    undrawn_frames += 1;
+                }
+                pltf.osd.show(if rewind.is_empty() { "Nothing more to rewind" } else { "Rewinding" });
+            } else if run_state.running() && !pltf.browsing {
+                // The instructions of a frame and then a timer tick, or one of
+                // each a cycle with --legacy-timing. A frame the debugger halts
+                // in carries on where it stopped.
+                if !timing.due() {
+                    timing.start_frame();
+                }
+                while timing.due() && debugger.as_mut().is_none_or(|d| d.should_run(&chip8)) {
+                    if let Some(debugger) = &debugger {
+                        debugger.apply_freezes(&mut chip8);
+                    }
+                    if inputs_due {
+                        inputs_due = false;
+                        if playback.as_ref().is_some_and(|replay| !replay.play(frame, chip8.keypad_mut())) {
+                            playback = None;
+                            pltf.osd.show("Playback finished");
+                        }
+                        if let Some(recording) = &mut recording {
+                            recording.record(chip8.keypad());
+                        }
+                        frame += 1;
+                    }
+                    if let Some(capture) = &mut capture {
+                        capture.record(chip8.keypad(), debugger.as_ref().map_or(&[], |d| d.frozen()));
+                    }
+                    // Stop rather than run on into garbage, keeping what was recorded
+                    if let Err(e) = timing.tick(&mut chip8) {
+                        if let Some(trace) = &trace {
+                            trace.dump();
+                        }
+                        eprintln!("{} stopped: {}", rom_name, e);
+                        (run_state, failed) = (RunState::Quitting, true);
+                        break;
+                    }
+                    if let Some(debugger) = &mut debugger {
+                        debugger.after_cycle(&chip8);
+                    }
+                    if legacy_timing {
+                        chip8.tick_timers();
+                        break;
+                    }
+                }
+                // Everything counting frames counts these 60Hz ones
+                let frame_done = !timing.due();
+                if frame_done && !legacy_timing {
+                    chip8.tick_timers();
+                }
+                if frame_done {
+                    if run_state == RunState::Advancing {
+                        run_state = RunState::Paused;
+                        pltf.osd.show(format!("Frame {}", frame));
+                    }
+                    inputs_due = true;
+                    rewind.record(frame, &chip8, &timing);
+                    undrawn_frames += 1;
+                    if let Some(speedrun) = &mut speedrun {
+                        speedrun.tick();
+                    }
+                    if let Some(commands) = &mut commands {
+                        commands.tick();
+                    }
+                }
+            }
+
+            if let Some(buzzer) = &mut pltf.buzzer {
+                buzzer.set_pattern(chip8.audio_pattern());
+            }
+            pltf.beep(run_state.running() && !pltf.browsing && chip8.beeping());
+
+            // Keep square pixels when the ROM switches display mode
+            if (chip8.video_width(), chip8.video_height()) != video_size {
+                video_size = (chip8.video_width(), chip8.video_height());
+                if let Err(e) = pltf.resize(video_size.0, video_size.1, video_scale) {
+                    eprintln!("Error resizing window: {}", e);
+                    (run_state, failed) = (RunState::Quitting, true);
+                    continue;
+                }
+            }
+
+            // Running flat out, the time is better spent emulating than drawing
+            if uncapped && undrawn_frames > 0 && undrawn_frames < frame_skip {
+                continue;
+            }
+            undrawn_frames = 0;
+
+            if let Some(hint) = controls_hint.update(&chip8, &pltf.keymap) {
+                pltf.osd.show(hint);
+            }
+            pltf.osd.set_status(speedrun.as_ref().map(Speedrun::status));
+            pltf.osd.set_watches(if show_watches { watches.iter().map(|w| w.show(&chip8)).collect() } else { Vec::new() });
+            if let Err(e) = pltf.update(chip8.active_video(), chip8.video_width(), chip8.video_height()) {
+                eprintln!("Error updating window: {}", e);
+                (run_state, failed) = (RunState::Quitting, true);
+                continue;
+            }
+
+            #[cfg(feature = "broadcast")]
+            if let Some(broadcaster) = &mut broadcaster {
+                broadcaster.frame(chip8.video_width(), chip8.video_height(), chip8.active_video());
+                broadcaster.sound(chip8.beeping());
+            }
+            #[cfg(feature = "plugins")]
+            if let Some(plugins) = &plugins {
+                plugins.frame(chip8.video_width(), chip8.video_height(), &palette::colorize(chip8.active_video(), &pltf.colors));
+                plugins.audio(chip8.beeping());
+            }
         }
     }
 
+    if let Some(trace) = &trace {
+        trace.flush();
+    }
+
+    if let (Some(recording), Some(path)) = (recording, record_file) {
+        match recording.finish(&rom, &chip8).save(path) {
+            Ok(()) => println!("Wrote {}", path),
+            Err(e) => {
+                eprintln!("Error writing recording: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+
+    if let (Some(capture), Some(path)) = (capture, capture_file) {
+        match capture.finish(&rom, &chip8).save(Path::new(path)) {
+            Ok(()) => println!("Wrote {}", path),
+            Err(e) => {
+                eprintln!("Error writing fixture: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+
+    if failed {
+        process::exit(1);
+    }
 }
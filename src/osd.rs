@@ -0,0 +1,214 @@
+// On-screen display
+//
+// Short status messages drawn over the game for a couple of seconds, so hotkeys
+// give visible feedback, a status line that stays up in the top right corner
+// while it's set, and the watch expressions in the top left. Text is drawn with a built-in 3x5 font, scaled with the
+// window, so nothing beyond SDL's renderer is needed.
+
+use std::time::{Duration, Instant};
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas};
+use sdl2::video::Window;
+
+const MESSAGE_DURATION: Duration = Duration::from_secs(2);
+
+const GLYPH_WIDTH: i32 = 3;
+const GLYPH_HEIGHT: i32 = 5;
+
+// One row per byte, the low three bits left to right
+const FONT: &[(char, [u8; 5])] = &[
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b111, 0b001, 0b111, 0b100, 0b111]),
+    ('3', [0b111, 0b001, 0b111, 0b001, 0b111]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b111, 0b001, 0b111]),
+    ('6', [0b111, 0b100, 0b111, 0b101, 0b111]),
+    ('7', [0b111, 0b001, 0b010, 0b010, 0b010]),
+    ('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+    ('9', [0b111, 0b101, 0b111, 0b001, 0b111]),
+    ('A', [0b010, 0b101, 0b111, 0b101, 0b101]),
+    ('B', [0b110, 0b101, 0b110, 0b101, 0b110]),
+    ('C', [0b011, 0b100, 0b100, 0b100, 0b011]),
+    ('D', [0b110, 0b101, 0b101, 0b101, 0b110]),
+    ('E', [0b111, 0b100, 0b110, 0b100, 0b111]),
+    ('F', [0b111, 0b100, 0b110, 0b100, 0b100]),
+    ('G', [0b011, 0b100, 0b101, 0b101, 0b011]),
+    ('H', [0b101, 0b101, 0b111, 0b101, 0b101]),
+    ('I', [0b111, 0b010, 0b010, 0b010, 0b111]),
+    ('J', [0b001, 0b001, 0b001, 0b101, 0b010]),
+    ('K', [0b101, 0b101, 0b110, 0b101, 0b101]),
+    ('L', [0b100, 0b100, 0b100, 0b100, 0b111]),
+    ('M', [0b101, 0b111, 0b111, 0b101, 0b101]),
+    ('N', [0b110, 0b101, 0b101, 0b101, 0b101]),
+    ('O', [0b010, 0b101, 0b101, 0b101, 0b010]),
+    ('P', [0b110, 0b101, 0b110, 0b100, 0b100]),
+    ('Q', [0b010, 0b101, 0b101, 0b110, 0b011]),
+    ('R', [0b110, 0b101, 0b110, 0b101, 0b101]),
+    ('S', [0b011, 0b100, 0b010, 0b001, 0b110]),
+    ('T', [0b111, 0b010, 0b010, 0b010, 0b010]),
+    ('U', [0b101, 0b101, 0b101, 0b101, 0b111]),
+    ('V', [0b101, 0b101, 0b101, 0b101, 0b010]),
+    ('W', [0b101, 0b101, 0b111, 0b111, 0b101]),
+    ('X', [0b101, 0b101, 0b010, 0b101, 0b101]),
+    ('Y', [0b101, 0b101, 0b010, 0b010, 0b010]),
+    ('Z', [0b111, 0b001, 0b010, 0b100, 0b111]),
+    ('.', [0b000, 0b000, 0b000, 0b000, 0b010]),
+    (',', [0b000, 0b000, 0b000, 0b010, 0b100]),
+    (':', [0b000, 0b010, 0b000, 0b010, 0b000]),
+    ('-', [0b000, 0b000, 0b111, 0b000, 0b000]),
+    ('+', [0b000, 0b010, 0b111, 0b010, 0b000]),
+    ('=', [0b000, 0b111, 0b000, 0b111, 0b000]),
+    ('/', [0b001, 0b001, 0b010, 0b100, 0b100]),
+    ('%', [0b101, 0b001, 0b010, 0b100, 0b101]),
+    ('!', [0b010, 0b010, 0b010, 0b000, 0b010]),
+    ('?', [0b110, 0b001, 0b010, 0b000, 0b010]),
+    ('(', [0b001, 0b010, 0b010, 0b010, 0b001]),
+    (')', [0b100, 0b010, 0b010, 0b010, 0b100]),
+    ('[', [0b011, 0b010, 0b010, 0b010, 0b011]),
+    (']', [0b110, 0b010, 0b010, 0b010, 0b110]),
+    ('\'', [0b010, 0b010, 0b000, 0b000, 0b000]),
+    ('_', [0b000, 0b000, 0b000, 0b000, 0b111]),
+    ('*', [0b000, 0b101, 0b010, 0b101, 0b000]),
+    ('<', [0b001, 0b010, 0b100, 0b010, 0b001]),
+    ('>', [0b100, 0b010, 0b001, 0b010, 0b100]),
+    ('&', [0b010, 0b101, 0b010, 0b101, 0b011]),
+    ('|', [0b010, 0b010, 0b010, 0b010, 0b010]),
+    ('^', [0b010, 0b101, 0b000, 0b000, 0b000]),
+];
+
+// Unknown characters are drawn as a hollow box
+fn glyph(c: char) -> [u8; 5] {
+    match c {
+        ' ' => [0; 5],
+        _ => FONT.iter()
+            .find(|&&(g, _)| g == c.to_ascii_uppercase())
+            .map(|&(_, rows)| rows)
+            .unwrap_or([0b111, 0b101, 0b101, 0b101, 0b111]),
+    }
+}
+
+// Size of one font pixel, so text grows with the window
+pub fn dot_size(canvas: &Canvas<Window>) -> Result<i32, String> {
+    let (_, height) = canvas.output_size()?;
+    Ok((height as i32 / 100).max(1))
+}
+
+pub fn text_width(text: &str, dot: i32) -> i32 {
+    text.chars().count() as i32 * (GLYPH_WIDTH + 1) * dot - dot
+}
+
+pub fn text_height(dot: i32) -> i32 {
+    GLYPH_HEIGHT * dot
+}
+
+// Draws `text` in the current draw color with its top left corner at (x, y)
+pub fn draw_text(canvas: &mut Canvas<Window>, text: &str, x: i32, y: i32, dot: i32) -> Result<(), String> {
+    let advance = (GLYPH_WIDTH + 1) * dot;
+    let mut dots = Vec::new();
+    for (i, c) in text.chars().enumerate() {
+        let x0 = x + i as i32 * advance;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                if bits & (0b100 >> col) != 0 {
+                    dots.push(Rect::new(x0 + col * dot, y + row as i32 * dot, dot as u32, dot as u32));
+                }
+            }
+        }
+    }
+    canvas.fill_rects(&dots)
+}
+
+// Draws `text` on a translucent box in the bottom left or the top right corner
+fn draw_label(canvas: &mut Canvas<Window>, text: &str, top_right: bool) -> Result<(), String> {
+    let (window_width, height) = canvas.output_size()?;
+    let dot = dot_size(canvas)?;
+    let margin = 2 * dot;
+
+    let width = text_width(text, dot) + 2 * margin;
+    let box_height = text_height(dot) + 2 * margin;
+    let (origin_x, origin_y) = if top_right {
+        (window_width as i32 - width - margin, margin)
+    } else {
+        (margin, height as i32 - box_height - margin)
+    };
+
+    canvas.set_blend_mode(BlendMode::Blend);
+    canvas.set_draw_color(Color::RGBA(0, 0, 0, 160));
+    canvas.fill_rect(Rect::new(origin_x, origin_y, width as u32, box_height as u32))?;
+
+    canvas.set_draw_color(Color::RGB(255, 255, 255));
+    draw_text(canvas, text, origin_x + margin, origin_y + margin, dot)?;
+
+    // Clearing uses the draw color, put it back for the next frame
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.set_blend_mode(BlendMode::None);
+    Ok(())
+}
+
+// Draws `lines` on a translucent box in the top left corner
+fn draw_panel(canvas: &mut Canvas<Window>, lines: &[String]) -> Result<(), String> {
+    let dot = dot_size(canvas)?;
+    let margin = 2 * dot;
+    let line_height = text_height(dot) + 2 * dot;
+
+    let width = lines.iter().map(|l| text_width(l, dot)).max().unwrap_or(0) + 2 * margin;
+    let height = lines.len() as i32 * line_height - 2 * dot + 2 * margin;
+
+    canvas.set_blend_mode(BlendMode::Blend);
+    canvas.set_draw_color(Color::RGBA(0, 0, 0, 160));
+    canvas.fill_rect(Rect::new(margin, margin, width as u32, height as u32))?;
+
+    canvas.set_draw_color(Color::RGB(255, 255, 255));
+    for (i, line) in lines.iter().enumerate() {
+        draw_text(canvas, line, 2 * margin, 2 * margin + i as i32 * line_height, dot)?;
+    }
+
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.set_blend_mode(BlendMode::None);
+    Ok(())
+}
+
+#[derive(Default)]
+pub struct Osd {
+    message: Option<(String, Instant)>,
+    // Shown in the top right corner until cleared, like the speedrun timer
+    status: Option<String>,
+    // Watch expressions with their current values, one a line
+    watches: Vec<String>,
+}
+
+impl Osd {
+    // Replaces whatever message is showing
+    pub fn show(&mut self, text: impl Into<String>) {
+        self.message = Some((text.into(), Instant::now()));
+    }
+
+    pub fn set_status(&mut self, text: Option<String>) {
+        self.status = text;
+    }
+
+    pub fn set_watches(&mut self, lines: Vec<String>) {
+        self.watches = lines;
+    }
+
+    // Draws the status and the current message in the bottom left corner, if it hasn't expired
+    pub fn draw(&mut self, canvas: &mut Canvas<Window>) -> Result<(), String> {
+        if !self.watches.is_empty() {
+            draw_panel(canvas, &self.watches)?;
+        }
+        if let Some(status) = &self.status {
+            draw_label(canvas, status, true)?;
+        }
+        match &self.message {
+            Some((text, shown)) if shown.elapsed() < MESSAGE_DURATION => draw_label(canvas, text, false),
+            Some(_) => {
+                self.message = None;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+}
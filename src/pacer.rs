@@ -0,0 +1,42 @@
+// Wall-clock pacing of 60Hz frames for the window
+//
+// With --ips the main loop runs frames of chip8_core::timing::Timing, and a
+// Pacer decides when each is due.
+
+use std::time::{Duration, Instant};
+
+use chip8_core::timing::FRAME_RATE;
+
+const FRAME: Duration = Duration::from_nanos(1_000_000_000 / FRAME_RATE as u64);
+// Further behind than this, after a breakpoint or a dragged window, and the
+// pacer starts over rather than racing through the missed frames
+const MAX_LAG: Duration = Duration::from_millis(100);
+
+// Frame deadlines against the wall clock, so time spent emulating and drawing
+// doesn't add up to a slower game the way a fixed sleep between frames does
+pub struct Pacer {
+    next: Instant,
+}
+
+impl Default for Pacer {
+    fn default() -> Pacer {
+        Pacer { next: Instant::now() }
+    }
+}
+
+impl Pacer {
+    // Whether the next frame is due, moving the deadline on if it is
+    pub fn frame_due(&mut self) -> bool {
+        let now = Instant::now();
+        if now < self.next {
+            return false;
+        }
+        self.next = if now - self.next > MAX_LAG { now + FRAME } else { self.next + FRAME };
+        true
+    }
+
+    // How long until the next frame is due
+    pub fn until_next(&self) -> Duration {
+        self.next.saturating_duration_since(Instant::now())
+    }
+}
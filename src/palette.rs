@@ -0,0 +1,44 @@
+// Display colours
+//
+// Each pixel of the framebuffer holds the display planes lit in it (bit 0 the
+// first plane, bit 1 XO-CHIP's second), so there are four colours to pick from
+// by that number: unlit, first plane only, second plane only, and both. ROMs
+// that only draw on the first plane come out white on black as they always
+// have. The chipeight binary picks one of PALETTES with `palette` in its config
+// file or --palette, and takes `foreground` and `background`, or --foreground
+// and --background, to change the first two.
+
+pub const DEFAULT: [u32; 4] = [0x00000000, 0xFFFFFFFF, 0xFFAAAAAA, 0xFF555555];
+
+// What each colour is used for, by the planes lit
+pub const PLANES: [&str; 4] = ["unlit", "plane 1", "plane 2", "both planes"];
+
+pub const PALETTES: [(&str, &str, [u32; 4]); 4] = [
+    ("default", "white on black", DEFAULT),
+    ("octo", "Octo's yellow and orange", [0xFF996600, 0xFFFFCC00, 0xFFFF6600, 0xFF662200]),
+    ("amber", "an amber monochrome monitor", [0xFF1A1000, 0xFFFFB000, 0xFF805800, 0xFFFFD880]),
+    ("green", "a green phosphor terminal", [0xFF001A00, 0xFF33FF33, 0xFF1A801A, 0xFFAAFFAA]),
+];
+
+pub fn preset(name: &str) -> Option<[u32; 4]> {
+    PALETTES.iter().find(|(n, _, _)| n.eq_ignore_ascii_case(name)).map(|&(_, _, colors)| colors)
+}
+
+// The framebuffer as 32-bit ARGB pixels
+pub fn colorize(video: &[u32], palette: &[u32; 4]) -> Vec<u32> {
+    video.iter().map(|&planes| palette[planes as usize & 3]).collect()
+}
+
+// `#RRGGBB` or `RRGGBB` as an opaque ARGB colour
+pub fn parse_color(text: &str) -> Result<u32, String> {
+    let hex = text.trim().trim_start_matches('#');
+    match u32::from_str_radix(hex, 16) {
+        Ok(rgb) if hex.len() == 6 => Ok(0xFF000000 | rgb),
+        _ => Err(format!("`{}` is not a #RRGGBB colour", text)),
+    }
+}
+
+// An ARGB colour as `#RRGGBB`
+pub fn format_color(color: u32) -> String {
+    format!("#{:06X}", color & 0xFFFFFF)
+}
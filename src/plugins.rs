@@ -0,0 +1,134 @@
+// Dynamically loaded plugins
+//
+// `--plugin PATH` loads a shared library exporting
+//
+//   const struct chipeight_plugin *chipeight_plugin(void);
+//
+// which returns a table of C callbacks. Every callback except `create` may be
+// null, and `state` is whatever `create` returned:
+//
+//   struct chipeight_plugin {
+//       uint32_t abi_version;                         /* PLUGIN_ABI_VERSION */
+//       void *(*create)(void);
+//       void (*destroy)(void *state);
+//       bool (*bus_read)(void *state, uint16_t addr, uint8_t *value);
+//       bool (*bus_write)(void *state, uint16_t addr, uint8_t value);
+//       void (*frame)(void *state, const uint32_t *pixels, uint32_t width, uint32_t height);
+//       void (*audio)(void *state, bool on);
+//   };
+//
+// The bus callbacks return whether they handled the access, see bus.rs. `frame`
+// is called with the display whenever it's presented, `audio` when the buzzer
+// switches on or off. Plugins are called one at a time, never concurrently.
+
+use std::ffi::c_void;
+use std::sync::Mutex;
+
+use libloading::{Library, Symbol};
+
+use chip8_core::bus::Peripheral;
+
+pub const PLUGIN_ABI_VERSION: u32 = 1;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct PluginApi {
+    abi_version: u32,
+    create: Option<unsafe extern "C" fn() -> *mut c_void>,
+    destroy: Option<unsafe extern "C" fn(*mut c_void)>,
+    bus_read: Option<unsafe extern "C" fn(*mut c_void, u16, *mut u8) -> bool>,
+    bus_write: Option<unsafe extern "C" fn(*mut c_void, u16, u8) -> bool>,
+    frame: Option<unsafe extern "C" fn(*mut c_void, *const u32, u32, u32)>,
+    audio: Option<unsafe extern "C" fn(*mut c_void, bool)>,
+}
+
+struct Plugin {
+    api: PluginApi,
+    state: *mut c_void,
+    // Dropped last, the callbacks point into it
+    _library: Library,
+}
+
+// The state pointer is only ever used behind the Plugins mutex
+unsafe impl Send for Plugin {}
+
+impl Plugin {
+    fn load(path: &str) -> Result<Plugin, String> {
+        unsafe {
+            let library = Library::new(path).map_err(|e| format!("{}: {}", path, e))?;
+            let entry: Symbol<unsafe extern "C" fn() -> *const PluginApi> = library.get(b"chipeight_plugin")
+                .map_err(|e| format!("{}: {}", path, e))?;
+            let api = entry().as_ref().copied().ok_or_else(|| format!("{}: no plugin table", path))?;
+            if api.abi_version != PLUGIN_ABI_VERSION {
+                return Err(format!("{}: plugin ABI version {}, expected {}", path, api.abi_version, PLUGIN_ABI_VERSION));
+            }
+            let create = api.create.ok_or_else(|| format!("{}: plugin has no create callback", path))?;
+            let state = create();
+            Ok(Plugin { api, state, _library: library })
+        }
+    }
+}
+
+impl Drop for Plugin {
+    fn drop(&mut self) {
+        if let Some(destroy) = self.api.destroy {
+            unsafe { destroy(self.state) }
+        }
+    }
+}
+
+pub struct Plugins {
+    plugins: Mutex<Vec<Plugin>>,
+    sounding: Mutex<bool>,
+}
+
+impl Plugins {
+    pub fn load(paths: &[&String]) -> Result<Plugins, String> {
+        let plugins = paths.iter().map(|path| Plugin::load(path)).collect::<Result<_, _>>()?;
+        Ok(Plugins { plugins: Mutex::new(plugins), sounding: Mutex::new(false) })
+    }
+
+    pub fn frame(&self, width: u32, height: u32, video: &[u32]) {
+        for plugin in self.plugins.lock().unwrap().iter() {
+            if let Some(frame) = plugin.api.frame {
+                unsafe { frame(plugin.state, video.as_ptr(), width, height) }
+            }
+        }
+    }
+
+    // Tells the plugins when the buzzer changes
+    pub fn audio(&self, on: bool) {
+        let mut sounding = self.sounding.lock().unwrap();
+        if *sounding == on {
+            return;
+        }
+        *sounding = on;
+        for plugin in self.plugins.lock().unwrap().iter() {
+            if let Some(audio) = plugin.api.audio {
+                unsafe { audio(plugin.state, on) }
+            }
+        }
+    }
+}
+
+// The first plugin to handle an access wins
+impl Peripheral for Plugins {
+    fn read(&self, addr: u16) -> Option<u8> {
+        for plugin in self.plugins.lock().unwrap().iter() {
+            if let Some(bus_read) = plugin.api.bus_read {
+                let mut value = 0;
+                if unsafe { bus_read(plugin.state, addr, &mut value) } {
+                    return Some(value);
+                }
+            }
+        }
+        None
+    }
+
+    fn write(&self, addr: u16, value: u8) -> bool {
+        self.plugins.lock().unwrap().iter().any(|plugin| match plugin.api.bus_write {
+            Some(bus_write) => unsafe { bus_write(plugin.state, addr, value) },
+            None => false,
+        })
+    }
+}
@@ -0,0 +1,181 @@
+// Compatibility quirks
+//
+// A handful of instructions behave differently from one interpreter to the
+// next, and games written for one often break on another:
+//
+//   shift      8XY6/8XYE shift VY into VX (COSMAC VIP) rather than VX in place
+//   load_store FX55/FX65 leave I past the last register (COSMAC VIP)
+//   jump       BNNN jumps to XNN + VX (BXNN, CHIP-48 and SCHIP) rather than NNN + V0
+//   wrap       sprites wrap around the screen edges rather than being clipped
+//   vf_reset   8XY1/8XY2/8XY3 reset VF to 0 (COSMAC VIP)
+//
+// The presets set them all the way the interpreters they're named after did.
+// The default is what this emulator has always done, which most ROMs floating
+// around expect: CHIP-48 shifts and loads, BNNN with V0 and clipped sprites.
+// SCHIP and XO-CHIP ROMs get their own presets unless told otherwise.
+//
+// Frontends take them as a comma-separated list that may start with a preset,
+// each quirk after it turned on, or off with a leading `-`:
+//
+//   schip            chip8,-vf_reset            shift,load_store,wrap
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Quirks {
+    pub shift: bool,
+    pub load_store: bool,
+    pub jump: bool,
+    pub wrap: bool,
+    pub vf_reset: bool,
+}
+
+// Names with a line about what turning them on does, for --list-quirks
+pub const QUIRKS: [(&str, &str); 5] = [
+    ("shift", "8XY6/8XYE shift VY into VX"),
+    ("load_store", "FX55/FX65 advance I past the last register"),
+    ("jump", "BNNN jumps to XNN + VX"),
+    ("wrap", "sprites wrap around the screen edges"),
+    ("vf_reset", "8XY1/8XY2/8XY3 reset VF"),
+];
+
+pub const PRESETS: [(&str, &str, Quirks); 3] = [
+    ("chip8", "the COSMAC VIP interpreter", Quirks { shift: true, load_store: true, jump: false, wrap: false, vf_reset: true }),
+    ("schip", "SUPER-CHIP 1.1 on the HP48", Quirks { shift: false, load_store: false, jump: true, wrap: false, vf_reset: false }),
+    ("xochip", "XO-CHIP as Octo runs it", Quirks { shift: true, load_store: true, jump: false, wrap: true, vf_reset: false }),
+];
+
+impl Quirks {
+    pub fn preset(name: &str) -> Option<Quirks> {
+        PRESETS.iter().find(|(n, _, _)| n.eq_ignore_ascii_case(name)).map(|&(_, _, quirks)| quirks)
+    }
+
+    // What a ROM runs with when no quirks are given: the preset of its mode, and
+    // none of them for plain CHIP-8
+    pub fn for_mode(schip: bool, xochip: bool) -> Quirks {
+        match (schip, xochip) {
+            (_, true) => Quirks::preset("xochip").unwrap_or_default(),
+            (true, false) => Quirks::preset("schip").unwrap_or_default(),
+            (false, false) => Quirks::default(),
+        }
+    }
+
+    // The preset these match, if any
+    pub fn preset_name(&self) -> Option<&'static str> {
+        PRESETS.iter().find(|(_, _, quirks)| quirks == self).map(|&(name, _, _)| name)
+    }
+
+    // A preset and/or quirk names as described at the top
+    pub fn parse(spec: &str) -> Result<Quirks, String> {
+        let mut quirks = Quirks::default();
+        for (i, item) in spec.split(',').map(str::trim).filter(|item| !item.is_empty()).enumerate() {
+            match Quirks::preset(item) {
+                Some(preset) if i == 0 => quirks = preset,
+                Some(_) => return Err(format!("preset `{}` has to come first", item)),
+                None => match item.strip_prefix('-') {
+                    Some(name) => quirks.set(name, false)?,
+                    None => quirks.set(item, true)?,
+                },
+            }
+        }
+        Ok(quirks)
+    }
+
+    // The names of the quirks that are on, what `parse` takes back
+    pub fn enabled(&self) -> Vec<&'static str> {
+        QUIRKS.iter().map(|&(name, _)| name).filter(|name| self.get(name) == Some(true)).collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<bool> {
+        Some(match name {
+            "shift" => self.shift,
+            "load_store" => self.load_store,
+            "jump" => self.jump,
+            "wrap" => self.wrap,
+            "vf_reset" => self.vf_reset,
+            _ => return None,
+        })
+    }
+
+    pub fn set(&mut self, name: &str, on: bool) -> Result<(), String> {
+        let quirk = match name {
+            "shift" => &mut self.shift,
+            "load_store" => &mut self.load_store,
+            "jump" => &mut self.jump,
+            "wrap" => &mut self.wrap,
+            "vf_reset" => &mut self.vf_reset,
+            _ => return Err(format!("unknown quirk `{}`", name)),
+        };
+        *quirk = on;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn looks_up_presets() {
+        for &(name, _, quirks) in PRESETS.iter() {
+            assert_eq!(Quirks::preset(name), Some(quirks));
+            assert_eq!(quirks.preset_name(), Some(name));
+        }
+        assert_eq!(Quirks::preset("SCHIP"), Quirks::preset("schip"));
+        assert_eq!(Quirks::preset("vip"), None);
+        assert_eq!(Quirks::default().preset_name(), None);
+    }
+
+    #[test]
+    fn picks_the_preset_of_the_mode() {
+        assert_eq!(Quirks::for_mode(false, false), Quirks::default());
+        assert_eq!(Quirks::for_mode(true, false).preset_name(), Some("schip"));
+        assert_eq!(Quirks::for_mode(true, true).preset_name(), Some("xochip"));
+        assert_eq!(Quirks::for_mode(false, true).preset_name(), Some("xochip"));
+    }
+
+    #[test]
+    fn parses_the_examples() {
+        assert_eq!(Quirks::parse("schip"), Ok(Quirks::preset("schip").unwrap()));
+        assert_eq!(
+            Quirks::parse("chip8,-vf_reset"),
+            Ok(Quirks { shift: true, load_store: true, jump: false, wrap: false, vf_reset: false }),
+        );
+        assert_eq!(
+            Quirks::parse("shift,load_store,wrap"),
+            Ok(Quirks { shift: true, load_store: true, jump: false, wrap: true, vf_reset: false }),
+        );
+    }
+
+    #[test]
+    fn parses_loosely_written_specs() {
+        assert_eq!(Quirks::parse(""), Ok(Quirks::default()));
+        assert_eq!(Quirks::parse(" xochip , -wrap ,"), Ok(Quirks { wrap: false, ..Quirks::preset("xochip").unwrap() }));
+        assert_eq!(Quirks::parse("jump,-jump"), Ok(Quirks::default()));
+    }
+
+    #[test]
+    fn rejects_bad_specs() {
+        assert_eq!(Quirks::parse("shift,chip8"), Err("preset `chip8` has to come first".to_string()));
+        assert_eq!(Quirks::parse("warp"), Err("unknown quirk `warp`".to_string()));
+        assert_eq!(Quirks::parse("-schip"), Err("unknown quirk `schip`".to_string()));
+    }
+
+    #[test]
+    fn round_trips_through_enabled() {
+        for &(_, _, quirks) in PRESETS.iter() {
+            assert_eq!(Quirks::parse(&quirks.enabled().join(",")), Ok(quirks));
+        }
+        assert_eq!(Quirks::preset("chip8").unwrap().enabled(), ["shift", "load_store", "vf_reset"]);
+    }
+
+    #[test]
+    fn names_every_quirk() {
+        for &(name, _) in QUIRKS.iter() {
+            let mut quirks = Quirks::default();
+            assert_eq!(quirks.get(name), Some(false));
+            quirks.set(name, true).unwrap();
+            assert_eq!(quirks.get(name), Some(true));
+            assert_eq!(quirks.enabled(), [name]);
+        }
+        assert_eq!(Quirks::default().get("warp"), None);
+    }
+}
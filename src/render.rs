@@ -0,0 +1,112 @@
+// `render` subcommand: write the frames of a ROM out as images
+//
+// Runs a ROM with no keys pressed through Chip8::frames and writes every frame
+// as a PBM image, for making videos and thumbnails. `--timing` takes a
+// cycle-cost table, see costs.rs.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::iter;
+use std::path::{Path, PathBuf};
+
+use crate::analysis::Analysis;
+use crate::costs;
+use crate::rom;
+use chip8_core::error::Chip8Error;
+use chip8_core::frames::Frame;
+use chip8_core::timing::Timing;
+use chip8_core::Chip8;
+
+const DEFAULT_FRAMES: u64 = 600;
+
+// Binary PBM, readable by most image tools and by ffmpeg as an image sequence
+fn write_pbm(path: &Path, frame: &Frame) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write!(out, "P4\n{} {}\n", frame.width, frame.height)?;
+    for y in 0..frame.height {
+        let row: Vec<u8> = (0..frame.width)
+            .step_by(8)
+            .map(|x| (0..8).fold(0, |byte, bit| {
+                let lit = x + bit < frame.width && frame.pixel(x + bit, y);
+                byte | ((lit as u8) << (7 - bit))
+            }))
+            .collect();
+        out.write_all(&row)?;
+    }
+    out.flush()
+}
+
+// `render <ROM> <OUT_DIR> [--frames N] [--timing FILE]`
+pub fn run(program: &str, args: &[String]) -> i32 {
+    let mut positional = Vec::new();
+    let mut count = DEFAULT_FRAMES;
+    let mut timing_file = None;
+
+    let mut iter = args.iter();
+    let parsed = (|| {
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--frames" => count = iter.next()?.parse().ok()?,
+                "--timing" => timing_file = Some(iter.next()?),
+                _ => positional.push(arg),
+            }
+        }
+        Some(())
+    })();
+
+    if parsed.is_none() || positional.len() != 2 {
+        eprintln!("Usage: {} render <ROM> <OUT_DIR> [--frames N] [--timing FILE]\n", program);
+        return 1;
+    }
+
+    let out_dir = PathBuf::from(positional[1]);
+    if let Err(e) = fs::create_dir_all(&out_dir) {
+        eprintln!("Error creating {}: {}", out_dir.display(), e);
+        return 1;
+    }
+
+    let timing = match timing_file {
+        Some(path) => match costs::load(path) {
+            Ok(timing) => timing,
+            Err(e) => {
+                eprintln!("Error loading timing table: {}", e);
+                return 1;
+            }
+        },
+        None => Timing::default(),
+    };
+
+    let mut chip8 = Chip8::new();
+    let loaded = rom::read(positional[0]).map_err(Chip8Error::from).and_then(|rom| {
+        chip8_core::check_rom_size(&rom)?;
+        // The same mode and quirks the window would pick
+        let (schip, xochip, quirks) = Analysis::new(&rom).mode(None, None, None);
+        chip8.set_schip(schip);
+        chip8.set_xochip(xochip);
+        chip8.set_quirks(quirks);
+        chip8.load_rom(&rom)
+    });
+    if let Err(e) = loaded {
+        eprintln!("Error loading {}: {}", positional[0], e);
+        return 1;
+    }
+
+    let inputs = iter::repeat_n([0; 16], count as usize);
+    for (number, frame) in chip8.frames(inputs).with_timing(timing).enumerate() {
+        let frame = match frame {
+            Ok(frame) => frame,
+            Err(e) => {
+                eprintln!("Error in frame {}: {}", number, e);
+                return 1;
+            }
+        };
+        let path = out_dir.join(format!("frame-{:05}.pbm", number));
+        if let Err(e) = write_pbm(&path, &frame) {
+            eprintln!("Error writing {}: {}", path.display(), e);
+            return 1;
+        }
+    }
+
+    println!("Wrote {} frames to {}", count, out_dir.display());
+    0
+}
@@ -0,0 +1,48 @@
+// Display, input and sound backends
+//
+// A frontend is anything that can show the framebuffer, read the keypad from
+// the host and sound the buzzer. The SDL2 window of the chipeight binary is one;
+// the terminal of chipeight-tui is another. `run` drives a Chip8 with any of
+// them at 60 frames a second, for frontends that need no more than that.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::Chip8;
+
+const FRAME: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+pub trait Renderer {
+    // Shows the display, `video` holding the planes lit in each pixel as
+    // Chip8::active_video does (see palette.rs for their colours)
+    fn update(&mut self, video: &[u32], width: u32, height: u32) -> Result<(), String>;
+
+    // Brings the keypad up to date with the host, false once the user has
+    // asked to quit
+    fn poll_input(&mut self, keypad: &mut [u8; 16]) -> Result<bool, String>;
+
+    // Turns the buzzer on or off
+    fn beep(&mut self, on: bool);
+}
+
+// Runs `instructions_per_frame` instructions and a timer tick a frame until
+// the renderer asks to quit, or the ROM fails
+pub fn run(chip8: &mut Chip8, renderer: &mut impl Renderer, instructions_per_frame: u32) -> Result<(), String> {
+    let mut next = Instant::now();
+    while renderer.poll_input(chip8.keypad_mut())? {
+        for _ in 0..instructions_per_frame {
+            chip8.tick().map_err(|e| e.to_string())?;
+        }
+        chip8.tick_timers();
+        renderer.beep(chip8.beeping());
+        renderer.update(chip8.active_video(), chip8.video_width(), chip8.video_height())?;
+
+        // Deadlines rather than a fixed sleep, starting over when far behind
+        next += FRAME;
+        match next.checked_duration_since(Instant::now()) {
+            Some(wait) => thread::sleep(wait),
+            None => next = Instant::now(),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,300 @@
+// Input recordings
+//
+// `--record FILE` writes the keypad of every frame to FILE on quit, along with
+// everything else that decides how a run goes: the RND seed, the timing, the
+// mode and quirks, and the state it started from when that wasn't a fresh start.
+// `--playback FILE` runs the ROM again with those settings, feeding the keypad
+// from the recording frame by frame instead of the keyboard, so the run comes
+// out exactly the same. The keyboard takes over when the recording runs out.
+//
+// Like --capture, a recording starts over from the current state on a reset
+// or a loaded state, and playback stops there. Rewinding goes back in the
+// recording too.
+//
+// The file is little-endian binary:
+//
+//   "C8RP" u8              magic and version
+//   u32                    FNV-1a hash of the ROM, to warn about a different one
+//   u64                    RND seed
+//   u32                    instructions a second, 0 for one a frame
+//   u8                     bit 0 SCHIP, bit 1 XO-CHIP, bit 2 CDP1802, bit 3
+//                          no hi-res detection, bit 4 --legacy-timing
+//   u32 + bytes            the quirks that were on, comma-separated
+//   u32 + bytes            Chip8::save_state to start from, empty for a fresh start
+//   u32 + u16 per frame    keypad bitmask (bit n = key n) of each frame
+
+use std::fs;
+
+use crate::capture::{keypad_mask, set_keypad};
+use chip8_core::quirks::Quirks;
+use chip8_core::timing::FRAME_RATE;
+use chip8_core::Chip8;
+
+const MAGIC: &[u8; 4] = b"C8RP";
+const VERSION: u8 = 1;
+
+fn rom_hash(rom: &[u8]) -> u32 {
+    rom.iter().fold(0x811c9dc5u32, |hash, &byte| (hash ^ byte as u32).wrapping_mul(0x01000193))
+}
+
+// A run being recorded
+pub struct Recording {
+    seed: u64,
+    state: Option<Vec<u8>>,
+    ips: u32,
+    legacy_timing: bool,
+    inputs: Vec<u16>,
+}
+
+impl Recording {
+    // A run starting from a freshly loaded ROM with the RNG seeded with `seed`
+    pub fn from_seed(seed: u64, ips: u32, legacy_timing: bool) -> Recording {
+        Recording { seed, state: None, ips, legacy_timing, inputs: Vec::new() }
+    }
+
+    // A run starting from an arbitrary machine state
+    pub fn from_state(chip8: &Chip8, ips: u32, legacy_timing: bool) -> Recording {
+        Recording { seed: 0, state: Some(chip8.save_state()), ips, legacy_timing, inputs: Vec::new() }
+    }
+
+    // Call at the start of every frame
+    pub fn record(&mut self, keypad: &[u8; 16]) {
+        self.inputs.push(keypad_mask(keypad));
+    }
+
+    // Forgets the frames after the first `frames`, after rewinding
+    pub fn truncate(&mut self, frames: u64) {
+        self.inputs.truncate(frames as usize);
+    }
+
+    pub fn finish(self, rom: &[u8], chip8: &Chip8) -> Replay {
+        Replay {
+            rom_hash: rom_hash(rom),
+            seed: self.seed,
+            ips: self.ips,
+            legacy_timing: self.legacy_timing,
+            schip: chip8.is_schip(),
+            xochip: chip8.is_xochip(),
+            cdp1802: chip8.is_cdp1802(),
+            hires_detection: chip8.detects_hires(),
+            quirks: chip8.quirks(),
+            state: self.state,
+            inputs: self.inputs,
+        }
+    }
+}
+
+pub struct Replay {
+    rom_hash: u32,
+    pub seed: u64,
+    pub ips: u32,
+    // A timer tick with every instruction, see the main loop
+    pub legacy_timing: bool,
+    pub schip: bool,
+    pub xochip: bool,
+    pub cdp1802: bool,
+    pub hires_detection: bool,
+    pub quirks: Quirks,
+    pub state: Option<Vec<u8>>,
+    inputs: Vec<u16>,
+}
+
+// Reads the file a field at a time
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], String> {
+        if self.data.len() < count {
+            return Err("file is truncated".to_string());
+        }
+        let (bytes, rest) = self.data.split_at(count);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn block(&mut self) -> Result<&'a [u8], String> {
+        let len = self.u32()? as usize;
+        self.bytes(len)
+    }
+}
+
+fn push_block(out: &mut Vec<u8>, block: &[u8]) {
+    out.extend_from_slice(&(block.len() as u32).to_le_bytes());
+    out.extend_from_slice(block);
+}
+
+impl Replay {
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let mut out = MAGIC.to_vec();
+        out.push(VERSION);
+        out.extend_from_slice(&self.rom_hash.to_le_bytes());
+        out.extend_from_slice(&self.seed.to_le_bytes());
+        out.extend_from_slice(&self.ips.to_le_bytes());
+        out.push(
+            self.schip as u8 | (self.xochip as u8) << 1 | (self.cdp1802 as u8) << 2
+                | (!self.hires_detection as u8) << 3 | (self.legacy_timing as u8) << 4,
+        );
+        push_block(&mut out, self.quirks.enabled().join(",").as_bytes());
+        push_block(&mut out, self.state.as_deref().unwrap_or_default());
+        out.extend_from_slice(&(self.inputs.len() as u32).to_le_bytes());
+        for mask in &self.inputs {
+            out.extend_from_slice(&mask.to_le_bytes());
+        }
+        fs::write(path, out).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn load(path: &str) -> Result<Replay, String> {
+        let data = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        Replay::parse(&data).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    fn parse(data: &[u8]) -> Result<Replay, String> {
+        let mut reader = Reader { data };
+        if reader.bytes(4)? != MAGIC {
+            return Err("not a recording".to_string());
+        }
+        let version = reader.u8()?;
+        if version != VERSION {
+            return Err(format!("unsupported recording version {}", version));
+        }
+
+        let rom_hash = reader.u32()?;
+        let seed = reader.u64()?;
+        let ips = reader.u32()?;
+        let mode = reader.u8()?;
+        let quirks = std::str::from_utf8(reader.block()?).map_err(|_| "quirks aren't text".to_string())?;
+        let quirks = Quirks::parse(quirks)?;
+        let state = Some(reader.block()?.to_vec()).filter(|state| !state.is_empty());
+        let frames = reader.u32()?;
+        let inputs = (0..frames).map(|_| reader.u16()).collect::<Result<_, _>>()?;
+
+        Ok(Replay {
+            rom_hash,
+            seed,
+            // Older recordings without --ips ran an instruction a frame
+            ips: if ips == 0 { FRAME_RATE } else { ips },
+            legacy_timing: ips == 0 || mode & 16 != 0,
+            schip: mode & 1 != 0,
+            xochip: mode & 2 != 0,
+            cdp1802: mode & 4 != 0,
+            hires_detection: mode & 8 == 0,
+            quirks,
+            state,
+            inputs,
+        })
+    }
+
+    // Whether it was recorded with `rom`
+    pub fn matches(&self, rom: &[u8]) -> bool {
+        rom_hash(rom) == self.rom_hash
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.inputs.len() as u64
+    }
+
+    // Sets the keypad to how it was on `frame`, false once the recording has run out
+    pub fn play(&self, frame: u64, keypad: &mut [u8; 16]) -> bool {
+        match self.inputs.get(frame as usize) {
+            Some(&mask) => {
+                set_keypad(keypad, mask);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROM: [u8; 2] = [0x12, 0x00];
+
+    fn round_trip(replay: Replay, name: &str) -> Replay {
+        let path = std::env::temp_dir().join(format!("chipeight-{}-{}.c8rp", name, std::process::id()));
+        let path = path.to_str().unwrap();
+        replay.save(path).unwrap();
+        let loaded = Replay::load(path);
+        fs::remove_file(path).unwrap();
+        loaded.unwrap()
+    }
+
+    #[test]
+    fn round_trips_settings_and_inputs() {
+        let mut chip8 = Chip8::new();
+        chip8.set_schip(true);
+        chip8.set_cdp1802(true);
+        chip8.set_hires_detection(false);
+        chip8.set_quirks(Quirks::for_mode(true, false));
+        let mut recording = Recording::from_seed(1234, 700, false);
+        let mut keypad = [0; 16];
+        recording.record(&keypad);
+        keypad[0xF] = 1;
+        recording.record(&keypad);
+        recording.record(&keypad);
+        recording.truncate(2);
+
+        let replay = round_trip(recording.finish(&ROM, &chip8), "settings");
+        assert!(replay.matches(&ROM) && !replay.matches(&[0x12, 0x02]));
+        assert_eq!((replay.seed, replay.ips, replay.legacy_timing), (1234, 700, false));
+        assert_eq!((replay.schip, replay.xochip, replay.cdp1802, replay.hires_detection), (true, false, true, false));
+        assert_eq!(replay.quirks, chip8.quirks());
+        assert_eq!(replay.state, None);
+        assert_eq!(replay.frames(), 2);
+
+        let mut played = [1; 16];
+        assert!(replay.play(0, &mut played));
+        assert_eq!(played, [0; 16]);
+        assert!(replay.play(1, &mut played));
+        assert_eq!(played, keypad);
+        assert!(!replay.play(2, &mut played));
+    }
+
+    #[test]
+    fn round_trips_a_starting_state() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&ROM).unwrap();
+        chip8.registers[3] = 9;
+        let recording = Recording::from_state(&chip8, 540, true);
+        let replay = round_trip(recording.finish(&ROM, &chip8), "state");
+        assert_eq!((replay.ips, replay.legacy_timing), (540, true));
+        assert_eq!(replay.state, Some(chip8.save_state()));
+    }
+
+    #[test]
+    fn runs_old_recordings_an_instruction_a_frame() {
+        let mut data = MAGIC.to_vec();
+        data.push(VERSION);
+        data.extend_from_slice(&rom_hash(&ROM).to_le_bytes());
+        data.extend_from_slice(&7u64.to_le_bytes());
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.push(0);
+        push_block(&mut data, b"");
+        push_block(&mut data, b"");
+        data.extend_from_slice(&0u32.to_le_bytes());
+
+        let replay = Replay::parse(&data).unwrap();
+        assert_eq!((replay.ips, replay.legacy_timing, replay.hires_detection), (FRAME_RATE, true, true));
+        assert_eq!(Replay::parse(&data[..data.len() - 1]).err().unwrap(), "file is truncated");
+        assert_eq!(Replay::parse(b"C8SS\x01").err().unwrap(), "not a recording");
+    }
+}
@@ -0,0 +1,67 @@
+// Rewind
+//
+// Every INTERVAL of play the machine is snapshotted, keeping the last
+// REWIND_SECONDS of them. Holding the rewind hotkey (Backspace) steps back
+// through the snapshots at the same pace, so the game runs backwards at about
+// its own speed, and letting go carries on from there. Reset and loading a
+// state start the history over.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use chip8_core::timing::Timing;
+use chip8_core::Chip8;
+
+const INTERVAL: Duration = Duration::from_millis(50);
+const REWIND_SECONDS: u64 = 10;
+const CAPACITY: usize = (REWIND_SECONDS * 1000 / INTERVAL.as_millis() as u64) as usize;
+
+pub struct Snapshot {
+    // Frames run since the run (or the recording of it) started
+    pub frame: u64,
+    pub state: Vec<u8>,
+    // Where the pacing was in its frame
+    pub timing: Timing,
+}
+
+pub struct Rewind {
+    snapshots: VecDeque<Snapshot>,
+    last: Instant,
+}
+
+impl Default for Rewind {
+    fn default() -> Rewind {
+        Rewind { snapshots: VecDeque::with_capacity(CAPACITY), last: Instant::now() }
+    }
+}
+
+impl Rewind {
+    // Call between frames, takes a snapshot when one is due
+    pub fn record(&mut self, frame: u64, chip8: &Chip8, timing: &Timing) {
+        if self.last.elapsed() < INTERVAL {
+            return;
+        }
+        self.last = Instant::now();
+        if self.snapshots.len() == CAPACITY {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(Snapshot { frame, state: chip8.save_state(), timing: timing.clone() });
+    }
+
+    // The next snapshot back once it's due, while the hotkey is held
+    pub fn step_back(&mut self) -> Option<Snapshot> {
+        if self.last.elapsed() < INTERVAL {
+            return None;
+        }
+        self.last = Instant::now();
+        self.snapshots.pop_back()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+    }
+}
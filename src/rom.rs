@@ -0,0 +1,41 @@
+// Reading ROM files
+//
+// Archived collections often gzip each ROM on its own, so `pong.ch8.gz` loads
+// just like `pong.ch8`. Files sitting next to a ROM (`.hash`, `.audio`) and
+// files named after it are named as if it weren't compressed.
+
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use flate2::read::GzDecoder;
+
+fn is_gzip(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("gz"))
+}
+
+// The ROM image in `path`, decompressed if it's gzipped
+pub fn read(path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+    let path = path.as_ref();
+    if !is_gzip(path) {
+        return fs::read(path);
+    }
+    let mut rom = Vec::new();
+    GzDecoder::new(File::open(path)?).read_to_end(&mut rom)?;
+    Ok(rom)
+}
+
+// `pong.ch8.gz` -> `pong.ch8`, other paths unchanged
+pub fn uncompressed_path(path: &Path) -> PathBuf {
+    if is_gzip(path) { path.with_extension("") } else { path.to_path_buf() }
+}
+
+// `pong` for both `pong.ch8` and `pong.ch8.gz`
+pub fn name(path: &Path) -> String {
+    uncompressed_path(path).file_stem().unwrap_or_default().to_string_lossy().into_owned()
+}
+
+// Whether `path` looks like a (possibly gzipped) ROM with extension `ext`
+pub fn has_extension(path: &Path, ext: &str) -> bool {
+    uncompressed_path(path).extension().is_some_and(|e| e.eq_ignore_ascii_case(ext))
+}
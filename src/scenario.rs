@@ -0,0 +1,249 @@
+// Scenario files: unit tests for ROM subroutines
+//
+// A scenario loads a ROM, pre-loads registers, I and memory, calls a routine and
+// runs it until it returns (or a cycle limit is hit), then checks the results.
+// The ROM runs in the mode and quirks the window would pick for it. Timers
+// don't tick, only instructions execute.
+//
+//   {
+//     "rom": "game.ch8",
+//     "cases": [
+//       {
+//         "name": "adds score",
+//         "call": "0x2A4",
+//         "setup": { "registers": { "V0": 5 }, "index": "0x300",
+//                    "memory": [{ "address": "0x300", "bytes": [1, 2] }] },
+//         "max_cycles": 1000,
+//         "expect": { "registers": { "V0": 8 }, "memory": [{ "address": "0x300", "bytes": [3] }] }
+//       }
+//     ]
+//   }
+//
+// Numbers may be JSON numbers or strings, with a 0x prefix for hex. The ROM path
+// is relative to the scenario file.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::analysis::Analysis;
+use crate::rom;
+use chip8_core::error::Chip8Error;
+use chip8_core::Chip8;
+
+const DEFAULT_MAX_CYCLES: u64 = 100_000;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Number {
+    Int(u64),
+    Text(String),
+}
+
+impl Number {
+    fn value(&self) -> Result<u64, String> {
+        match self {
+            Number::Int(n) => Ok(*n),
+            Number::Text(text) => match text.strip_prefix("0x") {
+                Some(hex) => u64::from_str_radix(hex, 16),
+                None => text.parse(),
+            }
+            .map_err(|_| format!("`{}` is not a number", text)),
+        }
+    }
+
+    fn to_u8(&self) -> Result<u8, String> {
+        let n = self.value()?;
+        u8::try_from(n).map_err(|_| format!("{} doesn't fit in a byte", n))
+    }
+
+    fn to_u16(&self) -> Result<u16, String> {
+        let n = self.value()?;
+        u16::try_from(n).map_err(|_| format!("{} doesn't fit in 16 bits", n))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MemoryRange {
+    address: Number,
+    bytes: Vec<Number>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct MachineState {
+    registers: BTreeMap<String, Number>,
+    index: Option<Number>,
+    memory: Vec<MemoryRange>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Case {
+    name: String,
+    call: Number,
+    #[serde(default)]
+    setup: MachineState,
+    max_cycles: Option<u64>,
+    #[serde(default)]
+    expect: MachineState,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Scenario {
+    rom: String,
+    cases: Vec<Case>,
+}
+
+// "V0".."VF" to a register number
+fn register(name: &str) -> Result<usize, String> {
+    name.strip_prefix(['V', 'v'])
+        .and_then(|n| usize::from_str_radix(n, 16).ok())
+        .filter(|&n| n < 16)
+        .ok_or_else(|| format!("unknown register `{}`", name))
+}
+
+impl MachineState {
+    fn apply(&self, chip8: &mut Chip8) -> Result<(), String> {
+        for (name, value) in &self.registers {
+            chip8.registers[register(name)?] = value.to_u8()?;
+        }
+        if let Some(index) = &self.index {
+            chip8.index = index.to_u16()?;
+        }
+        for range in &self.memory {
+            let start = range.address.to_u16()? as usize;
+            for (offset, byte) in range.bytes.iter().enumerate() {
+                let addr = start + offset;
+                if addr >= chip8.memory.len() {
+                    return Err(format!("memory range at {:#05X} runs past the end of memory", start));
+                }
+                chip8.memory[addr] = byte.to_u8()?;
+            }
+        }
+        Ok(())
+    }
+
+    // Every mismatch between the expectation and the machine
+    fn check(&self, chip8: &Chip8) -> Result<Vec<String>, String> {
+        let mut mismatches = Vec::new();
+        for (name, value) in &self.registers {
+            let (expected, got) = (value.to_u8()?, chip8.registers[register(name)?]);
+            if expected != got {
+                mismatches.push(format!("{}: expected {:#04X}, got {:#04X}", name, expected, got));
+            }
+        }
+        if let Some(index) = &self.index {
+            let expected = index.to_u16()?;
+            if expected != chip8.index {
+                mismatches.push(format!("I: expected {:#05X}, got {:#05X}", expected, chip8.index));
+            }
+        }
+        for range in &self.memory {
+            let start = range.address.to_u16()? as usize;
+            for (offset, byte) in range.bytes.iter().enumerate() {
+                let addr = start + offset;
+                let expected = byte.to_u8()?;
+                match chip8.memory.get(addr) {
+                    Some(&got) if got == expected => {}
+                    Some(&got) => mismatches.push(format!("[{:#05X}]: expected {:#04X}, got {:#04X}", addr, expected, got)),
+                    None => return Err(format!("memory range at {:#05X} runs past the end of memory", start)),
+                }
+            }
+        }
+        Ok(mismatches)
+    }
+}
+
+// Runs the routine until it returns to its (empty) caller
+fn run_case(rom: &[u8], case: &Case) -> Result<Vec<String>, String> {
+    chip8_core::check_rom_size(rom).map_err(|e| e.to_string())?;
+    let (schip, xochip, quirks) = Analysis::new(rom).mode(None, None, None);
+    let mut chip8 = Chip8::new();
+    chip8.set_schip(schip);
+    chip8.set_xochip(xochip);
+    chip8.set_quirks(quirks);
+    chip8.load_rom(rom).map_err(|e| e.to_string())?;
+    case.setup.apply(&mut chip8)?;
+    chip8.pc = case.call.to_u16()?;
+    chip8.sp = 0;
+
+    let max_cycles = case.max_cycles.unwrap_or(DEFAULT_MAX_CYCLES);
+    let finished = panic::catch_unwind(AssertUnwindSafe(|| -> Result<bool, Chip8Error> {
+        for _ in 0..max_cycles {
+            let pc = chip8.pc as usize;
+            if chip8.sp == 0 && chip8.memory.get(pc..pc + 2) == Some(&[0x00, 0xEE]) {
+                return Ok(true);
+            }
+            chip8.tick()?;
+        }
+        Ok(false)
+    }));
+
+    match finished {
+        Ok(Ok(true)) => case.expect.check(&chip8),
+        Ok(Ok(false)) => Ok(vec![format!("didn't return within {} cycles", max_cycles)]),
+        Ok(Err(e)) => Ok(vec![e.to_string()]),
+        Err(_) => Ok(vec![format!("crashed at {:#05X}", chip8.pc)]),
+    }
+}
+
+fn run_file(path: &Path) -> Result<(usize, usize), String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let scenario: Scenario = serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    let rom_path = path.parent().unwrap_or(Path::new(".")).join(&scenario.rom);
+    let rom = rom::read(&rom_path).map_err(|e| format!("{}: {}", rom_path.display(), e))?;
+
+    let mut failed = 0;
+    for case in &scenario.cases {
+        let mismatches = run_case(&rom, case).map_err(|e| format!("{}: {}: {}", path.display(), case.name, e))?;
+        if mismatches.is_empty() {
+            println!("PASS  {}", case.name);
+        } else {
+            failed += 1;
+            println!("FAIL  {}", case.name);
+            for mismatch in mismatches {
+                println!("        {}", mismatch);
+            }
+        }
+    }
+    Ok((scenario.cases.len(), failed))
+}
+
+// `scenario <FILE>...`
+pub fn run(program: &str, args: &[String]) -> i32 {
+    if args.is_empty() {
+        eprintln!("Usage: {} scenario <FILE>...\n", program);
+        return 1;
+    }
+
+    // Panics are reported per case
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+
+    let (mut total, mut failed) = (0, 0);
+    let mut status = 0;
+    for file in args {
+        match run_file(Path::new(file)) {
+            Ok((cases, failures)) => {
+                total += cases;
+                failed += failures;
+            }
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                status = 1;
+            }
+        }
+    }
+
+    panic::set_hook(default_hook);
+
+    println!("\n{} cases, {} failed", total, failed);
+    if failed > 0 { 1 } else { status }
+}
@@ -0,0 +1,124 @@
+// SUPER-CHIP (SCHIP 1.1) extensions
+//
+// Only decoded when the machine is in SCHIP mode (--schip, or detected from
+// the ROM), since plain CHIP-8 treats these opcodes as 0NNN routines:
+//
+//   00CN  scroll down N lines       00FB  scroll right 4 pixels
+//   00FC  scroll left 4 pixels      00FD  exit the interpreter
+//   00FE  64x32 display             00FF  128x64 display
+//   DXY0  draw a 16x16 sprite       FX30  point I at a large digit
+//   FX75  save V0-VX to RPL flags   FX85  load V0-VX from RPL flags
+//
+// Switching the display mode clears it. Scrolling works in pixels of the
+// current mode, as most modern interpreters do rather than the half-pixel
+// scroll of the original in low resolution.
+
+use crate::Chip8;
+
+const LARGE_FONTSET_START_ADDRESS: u16 = 0xA0;
+
+// 8x10 digits 0-9, SCHIP has no large A-F
+const LARGE_FONTSET: [u8; 100] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+];
+
+impl Chip8 {
+    // Turns the SCHIP instructions on or off, loading the large font
+    pub fn set_schip(&mut self, enabled: bool) {
+        self.schip = enabled;
+        if enabled {
+            let addr = LARGE_FONTSET_START_ADDRESS as usize;
+            self.memory[addr..addr + LARGE_FONTSET.len()].copy_from_slice(&LARGE_FONTSET);
+        }
+    }
+
+    pub fn is_schip(&self) -> bool {
+        self.schip
+    }
+
+    // Moves the selected planes by (dx, dy), filling in with unlit pixels
+    pub(crate) fn scroll(&mut self, dx: i32, dy: i32) {
+        let (width, height) = (self.video_width() as i32, self.video_height() as i32);
+        let planes = self.planes as u32;
+        let old = self.video;
+        for y in 0..height {
+            for x in 0..width {
+                let (from_x, from_y) = (x - dx, y - dy);
+                let inside = (0..width).contains(&from_x) && (0..height).contains(&from_y);
+                let moved = if inside { old[(from_y * width + from_x) as usize] & planes } else { 0 };
+                let pixel = &mut self.video[(y * width + x) as usize];
+                *pixel = (*pixel & !planes) | moved;
+            }
+        }
+    }
+
+    // 00CN - SCD nibble: Scroll display down N lines
+    pub(crate) fn op_00cn(&mut self) {
+        let lines = (self.opcode & 0x000F) as i32;
+
+        self.scroll(0, lines);
+    }
+
+    // 00FB - SCR: Scroll display right 4 pixels
+    pub(crate) fn op_00fb(&mut self) {
+        self.scroll(4, 0);
+    }
+
+    // 00FC - SCL: Scroll display left 4 pixels
+    pub(crate) fn op_00fc(&mut self) {
+        self.scroll(-4, 0);
+    }
+
+    // 00FD - EXIT: Exit the interpreter, here by staying on this instruction
+    pub(crate) fn op_00fd(&mut self) {
+        self.pc = self.pc.wrapping_sub(2);
+    }
+
+    // 00FE - LOW: Disable extended screen mode
+    pub(crate) fn op_00fe(&mut self) {
+        self.extended = false;
+        self.video.fill(0);
+    }
+
+    // 00FF - HIGH: Enable extended screen mode for full-screen graphics
+    pub(crate) fn op_00ff(&mut self) {
+        self.extended = true;
+        self.video.fill(0);
+    }
+
+    // Dxy0 - DRW Vx, Vy, 0: Display a 16x16 sprite at memory location I at (Vx, Vy), set VF = collision
+    pub(crate) fn op_dxy0(&mut self) {
+        self.draw_sprite(16, true);
+    }
+
+    // Fx30 - LD HF, Vx: Set I = location of the large sprite for digit Vx
+    pub(crate) fn op_fx30(&mut self) {
+        let vx = ((self.opcode & 0x0F00) >> 8) as usize;
+        let digit = (self.registers[vx] & 0xF) as u16;
+
+        self.index = LARGE_FONTSET_START_ADDRESS + 10 * digit;
+    }
+
+    // Fx75 - LD R, Vx: Store V0 through Vx in the RPL user flags
+    pub(crate) fn op_fx75(&mut self) {
+        let vx = ((self.opcode & 0x0F00) >> 8) as usize;
+
+        self.rpl[..=vx].copy_from_slice(&self.registers[..=vx]);
+    }
+
+    // Fx85 - LD Vx, R: Read V0 through Vx from the RPL user flags
+    pub(crate) fn op_fx85(&mut self) {
+        let vx = ((self.opcode & 0x0F00) >> 8) as usize;
+
+        self.registers[..=vx].copy_from_slice(&self.rpl[..=vx]);
+    }
+}
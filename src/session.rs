@@ -0,0 +1,167 @@
+// Session bundles
+//
+// A `.c8session` file is a TOML document holding everything needed to reopen a
+// game exactly as it was shared: the ROM (by path, relative to the session file,
+// or embedded), a save state, display settings and input bindings. Sessions
+// saved from the emulator embed the ROM so they stand on their own.
+//
+//   rom = "games/pong.ch8"     # or rom_data = "<hex>"
+//   state = "<hex>"
+//   scale = 10
+//   delay = 2
+//   ips = 700                  # as --ips
+//   legacy_timing = true       # as --legacy-timing, running at delay instead
+//   keypad = "cosmac"
+//   schip = true               # the mode, as --schip or --chip8
+//   xochip = false             # as --xochip
+//   quirks = "schip,wrap"      # as --quirks takes them
+//   turbo_rate = 10
+//
+//   [keys]                     # on top of the keypad layout, as --key
+//   Space = "5"
+//
+//   [turbo]                    # as --turbo
+//   Return = "6"
+//
+//   [controller]               # this game's buttons, as [games.<ROM name>.controller]
+//   a = "none"
+//
+//   [hotkeys]
+//   pause = "Space"
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::rom;
+
+pub const EXTENSION: &str = "c8session";
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Session {
+    pub rom: Option<String>,
+    pub rom_data: Option<String>,
+    // Chip8::save_state, hex encoded
+    pub state: Option<String>,
+    pub scale: Option<u32>,
+    pub delay: Option<u32>,
+    pub ips: Option<u32>,
+    pub legacy_timing: Option<bool>,
+    pub keypad: Option<String>,
+    pub schip: Option<bool>,
+    pub xochip: Option<bool>,
+    pub quirks: Option<String>,
+    pub turbo_rate: Option<u32>,
+    // Same form as the tables of the config file, applied on top of them
+    pub keys: HashMap<String, String>,
+    pub turbo: HashMap<String, String>,
+    pub controller: HashMap<String, String>,
+    pub hotkeys: HashMap<String, String>,
+}
+
+pub fn is_session(path: &str) -> bool {
+    Path::new(path).extension().is_some_and(|ext| ext.eq_ignore_ascii_case(EXTENSION))
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn from_hex(text: &str) -> Result<Vec<u8>, String> {
+    let text: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    if !text.is_ascii() || !text.len().is_multiple_of(2) {
+        return Err("expected pairs of hex digits".to_string());
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).map_err(|_| format!("`{}` is not hex", &text[i..i + 2])))
+        .collect()
+}
+
+impl Session {
+    pub fn load(path: &Path) -> Result<Session, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = toml::to_string(self).map_err(|e| e.to_string())?;
+        fs::write(path, text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    // The ROM image, embedded or read from next to the session file
+    pub fn rom(&self, session_path: &Path) -> Result<Vec<u8>, String> {
+        match (&self.rom_data, &self.rom) {
+            (Some(data), _) => from_hex(data).map_err(|e| format!("rom_data: {}", e)),
+            (None, Some(rom)) => {
+                let path = session_path.parent().unwrap_or(Path::new(".")).join(rom);
+                rom::read(&path).map_err(|e| format!("{}: {}", path.display(), e))
+            }
+            (None, None) => Err("session has neither `rom` nor `rom_data`".to_string()),
+        }
+    }
+
+    pub fn embed_rom(&mut self, rom: &[u8]) {
+        self.rom = None;
+        self.rom_data = Some(to_hex(rom));
+    }
+
+    pub fn state(&self) -> Result<Option<Vec<u8>>, String> {
+        self.state.as_deref().map(from_hex).transpose().map_err(|e| format!("state: {}", e))
+    }
+
+    pub fn set_state(&mut self, state: &[u8]) {
+        self.state = Some(to_hex(state));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keymap::Keymap;
+
+    #[test]
+    fn round_trips_through_toml() {
+        let mut keymap = Keymap::layout("cosmac").unwrap();
+        keymap.bind("Space", "5").unwrap();
+        let mut session = Session {
+            ips: Some(700),
+            keypad: Some(keymap.name.to_string()),
+            schip: Some(true),
+            xochip: Some(false),
+            quirks: Some("schip,wrap".to_string()),
+            turbo_rate: Some(15),
+            keys: keymap.to_config(),
+            turbo: HashMap::from([("Return".to_string(), "6".to_string())]),
+            controller: HashMap::from([("a".to_string(), "none".to_string())]),
+            ..Session::default()
+        };
+        session.embed_rom(&[0x12, 0x00]);
+        session.set_state(&[0, 1, 0xfe, 0xff]);
+
+        let loaded: Session = toml::from_str(&toml::to_string(&session).unwrap()).unwrap();
+        assert_eq!(loaded.rom(Path::new("x.c8session")).unwrap(), [0x12, 0x00]);
+        assert_eq!(loaded.state().unwrap().unwrap(), [0, 1, 0xfe, 0xff]);
+        assert_eq!((loaded.ips, loaded.schip, loaded.xochip, loaded.turbo_rate), (Some(700), Some(true), Some(false), Some(15)));
+        assert_eq!(loaded.quirks.as_deref(), Some("schip,wrap"));
+        assert_eq!(loaded.keys, HashMap::from([("Space".to_string(), "5".to_string())]));
+        assert_eq!(loaded.turbo, session.turbo);
+        assert_eq!(loaded.controller, session.controller);
+
+        let mut rebuilt = Keymap::layout(loaded.keypad.as_deref().unwrap()).unwrap();
+        for (name, key) in &loaded.keys {
+            rebuilt.bind(name, key).unwrap();
+        }
+        assert_eq!(rebuilt.to_config(), keymap.to_config());
+    }
+
+    #[test]
+    fn rejects_bad_hex() {
+        assert_eq!(from_hex("0a ff").unwrap(), [0x0a, 0xff]);
+        assert!(from_hex("abc").is_err());
+        assert!(from_hex("zz").is_err());
+    }
+}
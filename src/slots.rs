@@ -0,0 +1,188 @@
+// Save-state slots and their browser
+//
+// The save and load state hotkeys use the selected slot. The slot browser (F7)
+// shows every slot with its number, how long ago it was saved and a thumbnail
+// of the screen at the time, while the game waits:
+//
+//   Left, Right   select a slot
+//   Enter         load it and close the browser
+//   F5            save over it
+//   F7, Escape    close the browser
+//
+// Slots are kept on disk as well, one `slotN.c8st` file of Chip8::save_state
+// per slot under the ROM's directory in the config directory's `states`
+// (see config::states_dir), so they're still there the next time it's run.
+
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas};
+use sdl2::video::Window;
+
+use crate::osd;
+use chip8_core::Chip8;
+
+pub const SLOT_COUNT: usize = 8;
+const COLUMNS: usize = 4;
+
+// The display at its own resolution, one bit a pixel, rather than a screenshot
+// of the scaled window
+struct Thumbnail {
+    width: usize,
+    height: usize,
+    pixels: Vec<bool>,
+}
+
+impl Thumbnail {
+    fn of(chip8: &Chip8) -> Thumbnail {
+        Thumbnail {
+            width: chip8.video_width() as usize,
+            height: chip8.video_height() as usize,
+            pixels: chip8.active_video().iter().map(|&p| p != 0).collect(),
+        }
+    }
+}
+
+struct Slot {
+    machine: Chip8,
+    saved_at: SystemTime,
+    thumbnail: Thumbnail,
+}
+
+// `just now`, `40s ago`, `12m ago`, `3h ago`, `2d ago`
+fn age(saved_at: SystemTime) -> String {
+    let seconds = saved_at.elapsed().map(|d| d.as_secs()).unwrap_or(0);
+    match seconds {
+        0..=4 => "just now".to_string(),
+        5..=59 => format!("{}s ago", seconds),
+        60..=3599 => format!("{}m ago", seconds / 60),
+        3600..=86399 => format!("{}h ago", seconds / 3600),
+        _ => format!("{}d ago", seconds / 86400),
+    }
+}
+
+pub struct Slots {
+    slots: Vec<Option<Slot>>,
+    pub selected: usize,
+    // Where the slot files go, none to keep them in memory only
+    dir: Option<PathBuf>,
+}
+
+impl Default for Slots {
+    fn default() -> Slots {
+        Slots { slots: (0..SLOT_COUNT).map(|_| None).collect(), selected: 0, dir: None }
+    }
+}
+
+impl Slots {
+    // The slots saved in `dir` by earlier runs, restored on top of `chip8` so
+    // they keep its settings. Unreadable files are reported and left empty.
+    pub fn open(dir: Option<PathBuf>, chip8: &Chip8) -> Slots {
+        let mut slots = Slots { dir, ..Slots::default() };
+        for i in 0..SLOT_COUNT {
+            let Some(path) = slots.path(i) else { break };
+            let Ok(data) = fs::read(&path) else { continue };
+            let mut machine = chip8.clone();
+            if let Err(e) = machine.load_state(&data) {
+                eprintln!("Ignoring {}: {}", path.display(), e);
+                continue;
+            }
+            let saved_at = fs::metadata(&path).and_then(|m| m.modified()).unwrap_or_else(|_| SystemTime::now());
+            let thumbnail = Thumbnail::of(&machine);
+            slots.slots[i] = Some(Slot { machine, saved_at, thumbnail });
+        }
+        slots
+    }
+
+    fn path(&self, slot: usize) -> Option<PathBuf> {
+        Some(self.dir.as_ref()?.join(format!("slot{}.c8st", slot + 1)))
+    }
+
+    // Saves into the selected slot, an error if its file couldn't be written
+    pub fn save(&mut self, chip8: &Chip8) -> Result<(), String> {
+        self.slots[self.selected] = Some(Slot {
+            machine: chip8.clone(),
+            saved_at: SystemTime::now(),
+            thumbnail: Thumbnail::of(chip8),
+        });
+        let (Some(dir), Some(path)) = (&self.dir, self.path(self.selected)) else {
+            return Ok(());
+        };
+        fs::create_dir_all(dir)
+            .and_then(|()| fs::write(&path, chip8.save_state()))
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    // The machine saved in the selected slot
+    pub fn load(&self) -> Option<&Chip8> {
+        self.slots[self.selected].as_ref().map(|slot| &slot.machine)
+    }
+
+    // Moves the selection, wrapping around
+    pub fn select(&mut self, step: isize) {
+        self.selected = (self.selected as isize + step).rem_euclid(SLOT_COUNT as isize) as usize;
+    }
+
+    // Draws the browser over the whole window
+    pub fn draw(&self, canvas: &mut Canvas<Window>) -> Result<(), String> {
+        let (width, height) = canvas.output_size()?;
+        let (width, height) = (width as i32, height as i32);
+        let dot = osd::dot_size(canvas)?;
+        let margin = 3 * dot;
+
+        canvas.set_blend_mode(BlendMode::Blend);
+        canvas.set_draw_color(Color::RGBA(0, 0, 0, 200));
+        canvas.fill_rect(None)?;
+
+        canvas.set_draw_color(Color::RGB(255, 255, 255));
+        osd::draw_text(canvas, "Save slots - Enter loads, F5 saves", margin, margin, dot)?;
+
+        // Cards below the title, a thumbnail over a label
+        let top = 2 * margin + osd::text_height(dot);
+        let rows = SLOT_COUNT.div_ceil(COLUMNS) as i32;
+        let card_width = (width - margin * (COLUMNS as i32 + 1)) / COLUMNS as i32;
+        let card_height = (height - top - margin * (rows + 1)) / rows;
+        let label_height = osd::text_height(dot) + 2 * dot;
+        let thumb_area = (card_width - 2 * dot, card_height - label_height - 2 * dot);
+
+        for (i, slot) in self.slots.iter().enumerate() {
+            let x = margin + (i % COLUMNS) as i32 * (card_width + margin);
+            let y = top + margin + (i / COLUMNS) as i32 * (card_height + margin);
+
+            let border = if i == self.selected { Color::RGB(255, 255, 255) } else { Color::RGB(96, 96, 96) };
+            canvas.set_draw_color(border);
+            canvas.draw_rect(Rect::new(x, y, card_width.max(1) as u32, card_height.max(1) as u32))?;
+
+            let label = match slot {
+                Some(slot) => format!("{}  {}", i + 1, age(slot.saved_at)),
+                None => format!("{}  empty", i + 1),
+            };
+            canvas.set_draw_color(Color::RGB(255, 255, 255));
+            osd::draw_text(canvas, &label, x + 2 * dot, y + card_height - label_height, dot)?;
+
+            if let Some(slot) = slot {
+                let thumb = &slot.thumbnail;
+                let pixel = (thumb_area.0 / thumb.width as i32).min(thumb_area.1 / thumb.height as i32).max(1);
+                let (origin_x, origin_y) = (x + dot, y + dot);
+                canvas.set_draw_color(Color::RGB(0, 0, 0));
+                canvas.fill_rect(Rect::new(origin_x, origin_y, (thumb.width as i32 * pixel) as u32, (thumb.height as i32 * pixel) as u32))?;
+                canvas.set_draw_color(Color::RGB(255, 255, 255));
+                let lit: Vec<Rect> = thumb.pixels.iter().enumerate()
+                    .filter(|&(_, &on)| on)
+                    .map(|(p, _)| {
+                        let (px, py) = ((p % thumb.width) as i32, (p / thumb.width) as i32);
+                        Rect::new(origin_x + px * pixel, origin_y + py * pixel, pixel as u32, pixel as u32)
+                    })
+                    .collect();
+                canvas.fill_rects(&lit)?;
+            }
+        }
+
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        canvas.set_blend_mode(BlendMode::None);
+        Ok(())
+    }
+}
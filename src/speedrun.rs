@@ -0,0 +1,73 @@
+// Speedrun timer
+//
+// `--speedrun FILE` shows a timer in the top right corner that starts with the
+// ROM and starts over on every reset. The split hotkey marks a split, and the
+// splits of the current run are written to FILE each time:
+//
+//   # pong
+//   1  0:12.35  0:12.35
+//   2  0:20.00  0:07.65
+//
+// giving the split number, the time since the start and the time since the
+// previous split. Time is counted in emulated frames (60 a second), not wall
+// clock, so it doesn't depend on the host or on fast-forwarding.
+
+use std::fmt::Write;
+use std::fs;
+use std::path::PathBuf;
+
+const FRAMES_PER_SECOND: u64 = 60;
+
+// m:ss.cc
+pub fn format_time(frames: u64) -> String {
+    let hundredths = frames * 100 / FRAMES_PER_SECOND;
+    format!("{}:{:02}.{:02}", hundredths / 6000, hundredths / 100 % 60, hundredths % 100)
+}
+
+#[derive(Clone)]
+pub struct Speedrun {
+    path: PathBuf,
+    title: String,
+    frames: u64,
+    // Frame count at each split
+    splits: Vec<u64>,
+}
+
+impl Speedrun {
+    pub fn new(path: impl Into<PathBuf>, title: &str) -> Speedrun {
+        Speedrun { path: path.into(), title: title.to_string(), frames: 0, splits: Vec::new() }
+    }
+
+    // Starts a new run, keeping the file of the last one until its first split
+    pub fn restart(&mut self) {
+        self.frames = 0;
+        self.splits.clear();
+    }
+
+    // Call once per emulated frame
+    pub fn tick(&mut self) {
+        self.frames += 1;
+    }
+
+    // Marks a split and rewrites the splits file, returning the split's line
+    pub fn split(&mut self) -> Result<String, String> {
+        self.splits.push(self.frames);
+        fs::write(&self.path, self.export()).map_err(|e| format!("{}: {}", self.path.display(), e))?;
+        Ok(format!("Split {}  {}", self.splits.len(), format_time(self.frames)))
+    }
+
+    pub fn export(&self) -> String {
+        let mut text = format!("# {}\n", self.title);
+        let mut previous = 0;
+        for (number, &frames) in self.splits.iter().enumerate() {
+            writeln!(text, "{}  {}  {}", number + 1, format_time(frames), format_time(frames - previous)).unwrap();
+            previous = frames;
+        }
+        text
+    }
+
+    // The timer as shown on screen
+    pub fn status(&self) -> String {
+        format_time(self.frames)
+    }
+}
@@ -0,0 +1,227 @@
+// `sprite-edit` subcommand: draw sprites on a grid and get their bytes
+//
+// Opens a window with an 8xN (N up to 15) or 16x16 grid. Clicking toggles a
+// pixel and dragging paints with the same value, the right button erases.
+//
+//   Up, Down   fewer, more rows (8xN only)
+//   I, X       invert, clear
+//   C          copy the bytes to the clipboard
+//   W          append the sprite to --out as an Octo label, `: sprite` then the bytes
+//   Escape     quit, printing the bytes
+//
+// Bytes are written Octo style (`0xF0 0x90 ...`). 16x16 sprites are 32 bytes,
+// two per row, as SCHIP draws them.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::thread;
+use std::time::Duration;
+
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+
+const CELL: u32 = 24;
+const MAX_ROWS: usize = 16;
+// Dxyn draws at most 15 rows of 8 pixels
+const MAX_NARROW_ROWS: usize = 15;
+const DEFAULT_ROWS: usize = 8;
+// Nothing animates, so the window only needs to keep up with the mouse
+const FRAME_TIME: Duration = Duration::from_millis(16);
+
+struct Sprite {
+    // 8 or 16
+    width: usize,
+    rows: usize,
+    pixels: [[bool; 16]; MAX_ROWS],
+}
+
+impl Sprite {
+    fn bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for row in &self.pixels[..self.rows] {
+            for byte in row[..self.width].chunks(8) {
+                bytes.push(byte.iter().enumerate().fold(0, |b, (bit, &on)| b | ((on as u8) << (7 - bit))));
+            }
+        }
+        bytes
+    }
+
+    fn load(&mut self, bytes: &[u8]) {
+        let per_row = self.width / 8;
+        for (i, &byte) in bytes.iter().enumerate().take(self.rows * per_row) {
+            for bit in 0..8 {
+                self.pixels[i / per_row][(i % per_row) * 8 + bit] = byte & (0x80 >> bit) != 0;
+            }
+        }
+    }
+
+    fn hex(&self) -> String {
+        self.bytes().iter().map(|b| format!("0x{:02X}", b)).collect::<Vec<_>>().join(" ")
+    }
+
+    fn cell(&mut self, x: i32, y: i32) -> Option<&mut bool> {
+        let (col, row) = (x / CELL as i32, y / CELL as i32);
+        if x < 0 || y < 0 || col as usize >= self.width || row as usize >= self.rows {
+            return None;
+        }
+        Some(&mut self.pixels[row as usize][col as usize])
+    }
+}
+
+// Accepts `F0 90`, `0xF0 0x90` and `F090`
+fn parse_bytes(text: &str) -> Option<Vec<u8>> {
+    let digits: String = text.split_whitespace().map(|w| w.trim_start_matches("0x")).collect();
+    if !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..digits.len()).step_by(2).map(|i| u8::from_str_radix(digits.get(i..i + 2)?, 16).ok()).collect()
+}
+
+// `8xN` or `16x16`
+fn parse_size(text: &str) -> Option<(usize, usize)> {
+    match text.split_once('x')? {
+        ("16", "16") => Some((16, 16)),
+        ("8", rows) => rows.parse().ok().filter(|r| (1..=MAX_NARROW_ROWS).contains(r)).map(|r| (8, r)),
+        _ => None,
+    }
+}
+
+fn append_label(path: &str, label: &str, sprite: &Sprite) -> std::io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, ": {}\n  {}", label, sprite.hex())
+}
+
+// `sprite-edit [--size 8xN|16x16] [--hex BYTES] [--out FILE] [--label NAME]`
+pub fn run(program: &str, args: &[String]) -> i32 {
+    let mut size = None;
+    let mut initial = Vec::new();
+    let mut out_path = None;
+    let mut label = "sprite".to_string();
+
+    let mut iter = args.iter();
+    let parsed = (|| {
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--size" => size = Some(parse_size(iter.next()?)?),
+                "--hex" => initial = parse_bytes(iter.next()?)?,
+                "--out" => out_path = Some(iter.next()?),
+                "--label" => label = iter.next()?.clone(),
+                _ => return None,
+            }
+        }
+        Some(())
+    })();
+
+    if parsed.is_none() {
+        eprintln!("Usage: {} sprite-edit [--size 8xN|16x16] [--hex BYTES] [--out FILE] [--label NAME]\n", program);
+        return 1;
+    }
+
+    // Without --size, the bytes given decide the height
+    let (width, rows) = size.unwrap_or(match initial.len() {
+        0 => (8, DEFAULT_ROWS),
+        len => (8, len.min(MAX_NARROW_ROWS)),
+    });
+    let mut sprite = Sprite { width, rows, pixels: [[false; 16]; MAX_ROWS] };
+    sprite.load(&initial);
+
+    match edit(&mut sprite, out_path.map(String::as_str), &label) {
+        Ok(()) => {
+            println!("{}", sprite.hex());
+            0
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            1
+        }
+    }
+}
+
+fn edit(sprite: &mut Sprite, out_path: Option<&str>, label: &str) -> Result<(), String> {
+    let sdl_context = sdl2::init()?;
+    let video_subsystem = sdl_context.video()?;
+    let window = video_subsystem.window("Sprite editor", sprite.width as u32 * CELL, MAX_ROWS as u32 * CELL)
+        .position_centered()
+        .build()
+        .map_err(|e| e.to_string())?;
+    let mut canvas = window.into_canvas()
+        .accelerated()
+        .build()
+        .map_err(|e| e.to_string())?;
+    let mut event_pump = sdl_context.event_pump()?;
+
+    // Value being painted while a mouse button is held
+    let mut painting: Option<bool> = None;
+
+    loop {
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. } | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => return Ok(()),
+                Event::KeyDown { keycode: Some(key), .. } => match key {
+                    Keycode::Up if sprite.width == 8 => sprite.rows = (sprite.rows - 1).max(1),
+                    Keycode::Down if sprite.width == 8 => sprite.rows = (sprite.rows + 1).min(MAX_NARROW_ROWS),
+                    Keycode::I => {
+                        let (width, rows) = (sprite.width, sprite.rows);
+                        for row in sprite.pixels[..rows].iter_mut() {
+                            row[..width].iter_mut().for_each(|p| *p = !*p);
+                        }
+                    }
+                    Keycode::X => sprite.pixels = [[false; 16]; MAX_ROWS],
+                    Keycode::C => {
+                        video_subsystem.clipboard().set_clipboard_text(&sprite.hex())?;
+                        println!("Copied {}", sprite.hex());
+                    }
+                    Keycode::W => match out_path {
+                        Some(path) => match append_label(path, label, sprite) {
+                            Ok(()) => println!("Appended `{}` to {}", label, path),
+                            Err(e) => eprintln!("Error writing {}: {}", path, e),
+                        },
+                        None => println!("No --out file to write to"),
+                    },
+                    _ => {}
+                },
+                Event::MouseButtonDown { mouse_btn, x, y, .. } => {
+                    if let Some(cell) = sprite.cell(x, y) {
+                        let value = mouse_btn == MouseButton::Left && !*cell;
+                        *cell = value;
+                        painting = Some(value);
+                    }
+                }
+                Event::MouseMotion { x, y, .. } => {
+                    if let (Some(value), Some(cell)) = (painting, sprite.cell(x, y)) {
+                        *cell = value;
+                    }
+                }
+                Event::MouseButtonUp { .. } => painting = None,
+                _ => {}
+            }
+        }
+
+        let title = format!("Sprite {}x{} - {}", sprite.width, sprite.rows, sprite.hex());
+        canvas.window_mut().set_title(&title).map_err(|e| e.to_string())?;
+        draw(&mut canvas, sprite)?;
+        thread::sleep(FRAME_TIME);
+    }
+}
+
+fn draw(canvas: &mut Canvas<Window>, sprite: &Sprite) -> Result<(), String> {
+    // Rows past the sprite's height are shaded
+    canvas.set_draw_color(Color::RGB(48, 48, 48));
+    canvas.clear();
+
+    for (row, pixels) in sprite.pixels[..sprite.rows].iter().enumerate() {
+        for (col, &on) in pixels[..sprite.width].iter().enumerate() {
+            canvas.set_draw_color(if on { Color::RGB(255, 255, 255) } else { Color::RGB(0, 0, 0) });
+            let cell = Rect::new(col as i32 * CELL as i32, row as i32 * CELL as i32, CELL - 1, CELL - 1);
+            canvas.fill_rect(cell)?;
+        }
+    }
+
+    canvas.present();
+    Ok(())
+}
@@ -0,0 +1,212 @@
+// `sprites` subcommand: find sprite data in a ROM and export a sprite sheet
+//
+// Sprites are found from how the code uses them: the target of every LD I that
+// reaches a DRW (straight on, or through one call) is taken to be a sprite as
+// tall as that DRW draws, 16x16 for DRW n=0 as on SCHIP. If I is advanced with
+// ADD I, Vx on the way, the target is treated as a table of such sprites,
+// running up to the next referenced address.
+//
+// Every candidate is listed on stdout and drawn into a PNG, one per cell, in
+// order of address.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufWriter;
+
+use crate::analysis::{Analysis, RefKind};
+use chip8_core::decode::Instruction;
+use crate::rom;
+
+// How far past an LD I to look for the DRW that uses it
+const MAX_LOOKAHEAD: usize = 24;
+// Sprites taken from a single table at most
+const MAX_TABLE_SPRITES: usize = 64;
+const SHEET_COLUMNS: usize = 8;
+const DEFAULT_SCALE: u32 = 4;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Candidate {
+    // Rows of 8 pixels, or 16 rows of 16 pixels when `wide`
+    rows: u8,
+    wide: bool,
+    // Reached with ADD I in between, so more sprites follow
+    table: bool,
+    // The LD I it was found through
+    from: u16,
+}
+
+impl Candidate {
+    fn len(&self) -> usize {
+        if self.wide { 32 } else { self.rows as usize }
+    }
+}
+
+// The first DRW reached from `start` before I is loaded again, and whether I was
+// advanced before it
+fn find_draw(analysis: &Analysis, start: u16, follow_calls: bool) -> Option<(u8, bool)> {
+    let mut addr = start;
+    let mut advanced = false;
+    for _ in 0..MAX_LOOKAHEAD {
+        match analysis.instruction(addr)? {
+            Instruction::Drw { n, .. } => return Some((n, advanced)),
+            Instruction::AddI(_) => advanced = true,
+            Instruction::LdI(_) | Instruction::Ret | Instruction::Exit | Instruction::JpV0(_) | Instruction::LdF(_) | Instruction::LdHf(_) => return None,
+            Instruction::Jp(target) => {
+                addr = target;
+                continue;
+            }
+            Instruction::Call(target) if follow_calls => {
+                if let Some((n, in_call)) = find_draw(analysis, target, false) {
+                    return Some((n, advanced || in_call));
+                }
+            }
+            _ => {}
+        }
+        addr = addr.wrapping_add(2);
+    }
+    None
+}
+
+fn find_candidates(analysis: &Analysis) -> BTreeMap<u16, Candidate> {
+    let mut candidates: BTreeMap<u16, Candidate> = BTreeMap::new();
+    for (&target, refs) in &analysis.refs {
+        if analysis.code.contains(&target) || analysis.byte(target).is_none() {
+            continue;
+        }
+        for reference in refs.iter().filter(|r| r.kind == RefKind::Index) {
+            let Some((n, table)) = find_draw(analysis, reference.from.wrapping_add(2), true) else { continue };
+            let found = Candidate { rows: if n == 0 { 16 } else { n }, wide: n == 0, table, from: reference.from };
+            // Several draws of the same data: keep the biggest
+            candidates.entry(target)
+                .and_modify(|c| if found.len() > c.len() { *c = found })
+                .or_insert(found);
+        }
+    }
+    candidates
+}
+
+// Splits candidates into single sprites, expanding tables up to the next reference
+fn sprites(analysis: &Analysis, candidates: &BTreeMap<u16, Candidate>) -> Vec<(u16, Candidate)> {
+    let referenced: Vec<u16> = analysis.refs.keys().copied().collect();
+    let mut sprites = Vec::new();
+    for (&addr, &candidate) in candidates {
+        let end = analysis.end();
+        let limit = referenced.iter().map(|&a| a as usize).find(|&a| a > addr as usize).unwrap_or(end).min(end);
+        let count = if candidate.table {
+            (limit.saturating_sub(addr as usize) / candidate.len()).clamp(1, MAX_TABLE_SPRITES)
+        } else {
+            1
+        };
+        for i in 0..count {
+            sprites.push((addr + (i * candidate.len()) as u16, candidate));
+        }
+    }
+    sprites
+}
+
+// 1 for every lit pixel of the sprite at `addr`, 16 wide either way
+fn pixels(analysis: &Analysis, addr: u16, candidate: &Candidate) -> Vec<[bool; 16]> {
+    let byte = |a: u16| analysis.byte(a).unwrap_or(0);
+    (0..candidate.rows as u16)
+        .map(|row| {
+            let bits = if candidate.wide {
+                ((byte(addr + row * 2) as u16) << 8) | byte(addr + row * 2 + 1) as u16
+            } else {
+                (byte(addr + row) as u16) << 8
+            };
+            let mut pixels = [false; 16];
+            for (col, pixel) in pixels.iter_mut().enumerate() {
+                *pixel = bits & (0x8000 >> col) != 0;
+            }
+            pixels
+        })
+        .collect()
+}
+
+fn write_sheet(path: &str, analysis: &Analysis, sprites: &[(u16, Candidate)], scale: u32) -> Result<(), String> {
+    // Cells fit the largest sprite, with a one pixel gutter
+    let cell = 17;
+    let columns = sprites.len().clamp(1, SHEET_COLUMNS);
+    let rows = sprites.len().div_ceil(SHEET_COLUMNS).max(1);
+    let (width, height) = (columns * cell + 1, rows * cell + 1);
+
+    // Gutters grey, unlit pixels black
+    let mut image = vec![0x40u8; width * height];
+    for (i, (addr, candidate)) in sprites.iter().enumerate() {
+        let (x0, y0) = (1 + (i % SHEET_COLUMNS) * cell, 1 + (i / SHEET_COLUMNS) * cell);
+        for y in 0..16 {
+            image[(y0 + y) * width + x0..(y0 + y) * width + x0 + 16].fill(0);
+        }
+        for (y, row) in pixels(analysis, *addr, candidate).iter().enumerate() {
+            for (x, &lit) in row.iter().enumerate() {
+                if lit {
+                    image[(y0 + y) * width + x0 + x] = 0xFF;
+                }
+            }
+        }
+    }
+
+    let scale = scale as usize;
+    let mut scaled = Vec::with_capacity(image.len() * scale * scale);
+    for row in image.chunks(width) {
+        let line: Vec<u8> = row.iter().flat_map(|&p| std::iter::repeat_n(p, scale)).collect();
+        for _ in 0..scale {
+            scaled.extend_from_slice(&line);
+        }
+    }
+
+    let file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), (width * scale) as u32, (height * scale) as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| format!("{}: {}", path, e))?;
+    writer.write_image_data(&scaled).map_err(|e| format!("{}: {}", path, e))
+}
+
+// `sprites <ROM> <OUT.png> [--scale N]`
+pub fn run(program: &str, args: &[String]) -> i32 {
+    let mut positional = Vec::new();
+    let mut scale = DEFAULT_SCALE;
+
+    let mut iter = args.iter();
+    let parsed = (|| {
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--scale" => scale = iter.next()?.parse().ok().filter(|&s| s > 0)?,
+                _ => positional.push(arg),
+            }
+        }
+        Some(())
+    })();
+
+    if parsed.is_none() || positional.len() != 2 {
+        eprintln!("Usage: {} sprites <ROM> <OUT.png> [--scale N]\n", program);
+        return 1;
+    }
+
+    let rom = match rom::read(positional[0]) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("Error reading {}: {}", positional[0], e);
+            return 1;
+        }
+    };
+
+    if let Err(e) = chip8_core::check_rom_size(&rom) {
+        eprintln!("Error loading {}: {}", positional[0], e);
+        return 1;
+    }
+    let analysis = Analysis::new(&rom);
+    let sprites = sprites(&analysis, &find_candidates(&analysis));
+    for (i, (addr, candidate)) in sprites.iter().enumerate() {
+        let size = if candidate.wide { "16x16".to_string() } else { format!("8x{}", candidate.rows) };
+        println!("{:3}  0x{:03X}  {:5}  LD I at 0x{:03X}", i, addr, size, candidate.from);
+    }
+
+    if let Err(e) = write_sheet(positional[1], &analysis, &sprites, scale) {
+        eprintln!("Error writing sprite sheet: {}", e);
+        return 1;
+    }
+    println!("{} sprites written to {}", sprites.len(), positional[1]);
+    0
+}
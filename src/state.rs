@@ -0,0 +1,268 @@
+// Machine state snapshots
+//
+// A versioned little-endian binary image of everything the core needs to carry
+// on exactly where it left off. The display is stored one bit per pixel.
+//
+//   "C8ST" version:u8 registers:16 memory:4096 index:u16 pc:u16 stack:16*u16 sp:u8
+//   delay_timer:u8 sound_timer:u8 keypad:16 opcode:u16 hires:u8 video:128*64 bits
+//   rng:u64 extended:u8 rpl:16 plane2:128*64 bits planes:u8 pattern:16 pitch:u8
+//   pattern_loaded:u8 high_memory_len:u32 high_memory
+//
+// `video` is plane 1 and `plane2` the second XO-CHIP plane. High memory is the
+// XO-CHIP memory past the first 4KB, empty for the other machines.
+//
+// Version 1 ends after the video, versions 1 and 2 store only 64*64 bits of it,
+// version 3 ends after the RPL flags.
+
+use crate::Chip8;
+
+const MAGIC: &[u8; 4] = b"C8ST";
+const VERSION: u8 = 4;
+const LOW_MEMORY: usize = 4096;
+
+// Reads fields back in the order they were written
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.data.len() < len {
+            return Err("state is truncated".to_string());
+        }
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+}
+
+// One bit per pixel of whether `plane` is lit
+fn push_plane(out: &mut Vec<u8>, video: &[u32], plane: u32) {
+    for pixels in video.chunks(8) {
+        out.push(pixels.iter().enumerate().fold(0, |byte, (bit, &p)| byte | (((p & plane != 0) as u8) << bit)));
+    }
+}
+
+fn read_plane(video: &mut [u32], bits: &[u8], plane: u32) {
+    for (i, pixel) in video.iter_mut().enumerate().take(bits.len() * 8) {
+        if bits[i / 8] & (1 << (i % 8)) != 0 {
+            *pixel |= plane;
+        }
+    }
+}
+
+impl Chip8 {
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(5 + LOW_MEMORY + self.video.len() / 4 + 128);
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+        out.extend_from_slice(&self.registers);
+        out.extend_from_slice(&self.memory[..LOW_MEMORY]);
+        out.extend_from_slice(&self.index.to_le_bytes());
+        out.extend_from_slice(&self.pc.to_le_bytes());
+        for entry in self.stack {
+            out.extend_from_slice(&entry.to_le_bytes());
+        }
+        out.push(self.sp);
+        out.push(self.delay_timer);
+        out.push(self.sound_timer);
+        out.extend_from_slice(&self.keypad);
+        out.extend_from_slice(&self.opcode.to_le_bytes());
+        out.push(self.hires as u8);
+        push_plane(&mut out, &self.video, 1);
+        out.extend_from_slice(&self.rng.to_le_bytes());
+        out.push(self.extended as u8);
+        out.extend_from_slice(&self.rpl);
+        push_plane(&mut out, &self.video, 2);
+        out.push(self.planes);
+        out.extend_from_slice(&self.pattern);
+        out.push(self.pitch);
+        out.push(self.pattern_loaded as u8);
+        let high_memory = if self.xochip { &self.memory[LOW_MEMORY..] } else { &[][..] };
+        out.extend_from_slice(&(high_memory.len() as u32).to_le_bytes());
+        out.extend_from_slice(high_memory);
+        out
+    }
+
+    // Restores a snapshot from save_state, leaving the machine untouched if it's invalid
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut reader = Reader { data };
+        if reader.bytes(MAGIC.len())? != MAGIC {
+            return Err("not a CHIP-8 state".to_string());
+        }
+        let version = reader.u8()?;
+        if !(1..=VERSION).contains(&version) {
+            return Err(format!("unsupported state version {}", version));
+        }
+
+        let mut chip8 = self.clone();
+        chip8.registers.copy_from_slice(reader.bytes(16)?);
+        chip8.memory[..LOW_MEMORY].copy_from_slice(reader.bytes(LOW_MEMORY)?);
+        chip8.index = reader.u16()?;
+        chip8.pc = reader.u16()?;
+        for entry in chip8.stack.iter_mut() {
+            *entry = reader.u16()?;
+        }
+        chip8.sp = reader.u8()?;
+        chip8.delay_timer = reader.u8()?;
+        chip8.sound_timer = reader.u8()?;
+        chip8.keypad.copy_from_slice(reader.bytes(16)?);
+        chip8.opcode = reader.u16()?;
+        chip8.hires = reader.u8()? != 0;
+        let pixels = if version >= 3 { chip8.video.len() } else { 64 * 64 };
+        chip8.video.fill(0);
+        read_plane(&mut chip8.video, reader.bytes(pixels / 8)?, 1);
+        // Older states keep the running generator
+        if version >= 2 {
+            chip8.rng = reader.u64()?;
+        }
+        if version >= 3 {
+            chip8.extended = reader.u8()? != 0;
+            chip8.rpl.copy_from_slice(reader.bytes(16)?);
+        } else {
+            chip8.extended = false;
+        }
+        chip8.memory[LOW_MEMORY..].fill(0);
+        if version >= 4 {
+            let bits = reader.bytes(chip8.video.len() / 8)?;
+            read_plane(&mut chip8.video, bits, 2);
+            chip8.planes = reader.u8()?;
+            chip8.pattern.copy_from_slice(reader.bytes(16)?);
+            chip8.pitch = reader.u8()?;
+            chip8.pattern_loaded = reader.u8()? != 0;
+            let len = reader.u32()? as usize;
+            if len > chip8.memory.len() - LOW_MEMORY {
+                return Err("high memory is too long".to_string());
+            }
+            chip8.memory[LOW_MEMORY..LOW_MEMORY + len].copy_from_slice(reader.bytes(len)?);
+        } else {
+            chip8.planes = 1;
+            chip8.pattern_loaded = false;
+        }
+        if !reader.data.is_empty() {
+            return Err("trailing data after state".to_string());
+        }
+
+        *self = chip8;
+        Ok(())
+    }
+
+    // FNV-1a over the whole snapshot, for telling two machine states apart
+    pub fn state_hash(&self) -> u64 {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for byte in self.save_state() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A machine part way through drawing a digit, with a call on the stack
+    fn running() -> Chip8 {
+        let mut chip8 = Chip8::new();
+        // LD V0, 5; LD F, V0; DRW V0, V0, 5; CALL 0x208; JP 0x208
+        chip8.load_rom(&[0x60, 0x05, 0xF0, 0x29, 0xD0, 0x05, 0x22, 0x0A, 0x12, 0x08, 0x12, 0x0A]).unwrap();
+        chip8.seed(42);
+        for _ in 0..5 {
+            chip8.tick().unwrap();
+        }
+        chip8.delay_timer = 30;
+        chip8.keypad_mut()[7] = 1;
+        chip8
+    }
+
+    #[test]
+    fn round_trips() {
+        let chip8 = running();
+        let state = chip8.save_state();
+        let mut restored = Chip8::new();
+        restored.load_state(&state).unwrap();
+
+        assert_eq!(restored.save_state(), state);
+        assert_eq!(restored.state_hash(), chip8.state_hash());
+        assert_eq!((restored.pc, restored.sp, restored.stack[0]), (0x20A, 1, 0x208));
+        assert_eq!((restored.registers[0], restored.index, restored.delay_timer), (5, chip8.index, 30));
+        assert_eq!(restored.active_video(), chip8.active_video());
+        assert_eq!(restored.keypad()[7], 1);
+    }
+
+    #[test]
+    fn round_trips_xochip_memory() {
+        let mut chip8 = Chip8::new();
+        chip8.set_xochip(true);
+        chip8.memory[0x1234] = 0xAB;
+        chip8.memory[0xFFFF] = 0xCD;
+        let mut restored = Chip8::new();
+        restored.set_xochip(true);
+        restored.load_state(&chip8.save_state()).unwrap();
+        assert_eq!((restored.memory[0x1234], restored.memory[0xFFFF]), (0xAB, 0xCD));
+    }
+
+    #[test]
+    fn rejects_a_bad_magic() {
+        let mut state = running().save_state();
+        state[..4].copy_from_slice(b"C8SS");
+        let mut chip8 = Chip8::new();
+        let before = chip8.save_state();
+        assert_eq!(chip8.load_state(&state), Err("not a CHIP-8 state".to_string()));
+        assert_eq!(chip8.save_state(), before);
+    }
+
+    #[test]
+    fn rejects_an_unknown_version() {
+        let mut state = running().save_state();
+        for version in [0, VERSION + 1] {
+            state[4] = version;
+            let mut chip8 = Chip8::new();
+            assert_eq!(chip8.load_state(&state), Err(format!("unsupported state version {}", version)));
+            assert_eq!(chip8.pc, 0x200);
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_and_trailing_data() {
+        let state = running().save_state();
+        let mut chip8 = Chip8::new();
+        assert_eq!(chip8.load_state(&state[..state.len() - 1]), Err("state is truncated".to_string()));
+        assert_eq!(chip8.load_state(&[state.as_slice(), &[0]].concat()), Err("trailing data after state".to_string()));
+        assert_eq!(chip8.load_state(b"C8"), Err("state is truncated".to_string()));
+    }
+
+    #[test]
+    fn loads_version_1() {
+        let chip8 = running();
+        let state = chip8.save_state();
+        // Everything up to the video, then its first 64*64 pixels
+        let fields = 5 + 16 + LOW_MEMORY + 2 + 2 + 32 + 3 + 16 + 2 + 1;
+        let mut old = state[..fields + 64 * 64 / 8].to_vec();
+        old[4] = 1;
+
+        let mut restored = Chip8::new();
+        restored.load_state(&old).unwrap();
+        assert_eq!((restored.pc, restored.registers[0]), (chip8.pc, 5));
+        assert_eq!(&restored.video[..64 * 64], &chip8.video[..64 * 64]);
+        assert_eq!(restored.planes, 1);
+    }
+}
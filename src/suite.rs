@@ -0,0 +1,231 @@
+// Headless runner for a directory of test ROMs
+//
+// Every ROM gets its own Chip8 instance; instances are handed out to a pool of
+// worker threads so large collections finish in a fraction of the wall-clock time.
+// Each runs in the mode and with the quirks the window would pick for it, in
+// 60Hz frames of instructions as --headless runs it, for up to `--cycles`
+// instructions or until it settles. The two agree on the hash of a ROM.
+// `--timing` runs the frames by a cycle-cost table instead, see costs.rs.
+//
+// Besides the display hash, a ROM can have an expected audio timeline next to it
+// (`corax.ch8` -> `corax.audio`), written by `--record-audio`.
+
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::timeline::{self, AudioTimeline, Timeline, Tone};
+use chip8_core::beep::Beep;
+use chip8_core::error::Chip8Error;
+use chip8_core::timing::Timing;
+use crate::analysis::Analysis;
+use crate::costs;
+use crate::headless::{run_until_settled, Stop};
+use crate::rom;
+use chip8_core::palette;
+use chip8_core::Chip8;
+
+const DEFAULT_CYCLES: u64 = 1000;
+
+// Outcome of running a single ROM
+enum Outcome {
+    // No expected hash next to the ROM, only report what we got
+    Unchecked(u64),
+    Pass(u64),
+    Fail { got: u64, expected: u64 },
+    // The display matched (or isn't checked) but the sound didn't
+    AudioFail { hash: u64, detail: String },
+    // The ROM failed with a Chip8Error
+    Error(String),
+    Crashed,
+}
+
+struct Options {
+    dir: PathBuf,
+    jobs: usize,
+    cycles: u64,
+    record_audio: bool,
+    beep: Beep,
+    timing: Timing,
+}
+
+fn usage(program: &str) -> i32 {
+    eprintln!("Usage: {} suite <DIR> [--jobs N] [--cycles N] [--timing FILE] [--record-audio] [--min-audible N] [--min-beep N]\n", program);
+    1
+}
+
+fn parse_args(args: &[String]) -> Option<Options> {
+    let mut dir = None;
+    let mut jobs = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let mut cycles = DEFAULT_CYCLES;
+    let mut record_audio = false;
+    let mut beep = Beep::default();
+    let mut timing = Timing::default();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--jobs" => jobs = iter.next()?.parse().ok().filter(|&n| n > 0)?,
+            "--cycles" => cycles = iter.next()?.parse().ok()?,
+            "--timing" => {
+                let path = iter.next()?;
+                timing = costs::load(path).map_err(|e| eprintln!("Error loading timing table: {}", e)).ok()?;
+            }
+            "--record-audio" => record_audio = true,
+            "--min-audible" => beep.min_audible = iter.next()?.parse().ok()?,
+            "--min-beep" => beep.min_frames = iter.next()?.parse().ok()?,
+            _ if dir.is_none() => dir = Some(PathBuf::from(arg)),
+            _ => return None,
+        }
+    }
+
+    Some(Options { dir: dir?, jobs, cycles, record_audio, beep, timing })
+}
+
+// FNV-1a over the coloured framebuffer, stable across platforms and runs
+pub fn hash_video(video: &[u32]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for pixel in video {
+        for byte in pixel.to_le_bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+// Expected hashes live next to the ROM, e.g. `corax.ch8` -> `corax.hash`
+fn expected_hash(rom: &Path) -> Option<u64> {
+    let text = fs::read_to_string(rom::uncompressed_path(rom).with_extension("hash")).ok()?;
+    u64::from_str_radix(text.trim().trim_start_matches("0x"), 16).ok()
+}
+
+fn run_rom(rom: &Path, opts: &Options) -> Outcome {
+    let result = panic::catch_unwind(AssertUnwindSafe(|| -> Result<_, Chip8Error> {
+        let image = rom::read(rom).map_err(Chip8Error::from)?;
+        chip8_core::check_rom_size(&image)?;
+        let (schip, xochip, quirks) = Analysis::new(&image).mode(None, None, None);
+        let mut chip8 = Chip8::new();
+        chip8.set_beep(opts.beep);
+        chip8.set_schip(schip);
+        chip8.set_xochip(xochip);
+        chip8.set_quirks(quirks);
+        let mut audio = AudioTimeline::default();
+        chip8.load_rom(&image)?;
+        if let (Stop::Failed(e), _) = run_until_settled(&mut chip8, &mut opts.timing.clone(), opts.cycles, &mut audio) {
+            return Err(e);
+        }
+        Ok((hash_video(&palette::colorize(chip8.active_video(), &palette::DEFAULT)), audio.tones()))
+    }));
+
+    let (got, tones) = match result {
+        Ok(Ok(result)) => result,
+        Ok(Err(e)) => return Outcome::Error(e.to_string()),
+        Err(_) => return Outcome::Crashed,
+    };
+
+    if let Some(detail) = check_audio(rom, &tones, opts.record_audio) {
+        return Outcome::AudioFail { hash: got, detail };
+    }
+
+    match expected_hash(rom) {
+        None => Outcome::Unchecked(got),
+        Some(expected) if got == expected => Outcome::Pass(got),
+        Some(expected) => Outcome::Fail { got, expected },
+    }
+}
+
+// Compares against the expected timeline, or records one if asked to and there's none yet
+fn check_audio(rom: &Path, tones: &[Tone], record: bool) -> Option<String> {
+    let path = rom::uncompressed_path(rom).with_extension("audio");
+    if !path.exists() {
+        if record {
+            if let Err(e) = fs::write(&path, Timeline(tones).to_string()) {
+                return Some(format!("can't write {}: {}", path.display(), e));
+            }
+        }
+        return None;
+    }
+    match timeline::load(&path) {
+        Ok(expected) => timeline::diff(&expected, tones),
+        Err(e) => Some(format!("can't read {}: {}", path.display(), e)),
+    }
+}
+
+fn collect_roms(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut roms: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| rom::has_extension(path, "ch8"))
+        .collect();
+    roms.sort();
+    Ok(roms)
+}
+
+// Runs every ROM in `roms` across `jobs` threads, keeping results in input order
+fn run_parallel(roms: &[PathBuf], opts: &Options) -> Vec<Outcome> {
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<Outcome>>> = Mutex::new((0..roms.len()).map(|_| None).collect());
+
+    thread::scope(|scope| {
+        for _ in 0..opts.jobs.min(roms.len()) {
+            scope.spawn(|| loop {
+                let idx = next.fetch_add(1, Ordering::Relaxed);
+                if idx >= roms.len() {
+                    break;
+                }
+                let outcome = run_rom(&roms[idx], opts);
+                results.lock().unwrap()[idx] = Some(outcome);
+            });
+        }
+    });
+
+    results.into_inner().unwrap().into_iter().map(|o| o.unwrap()).collect()
+}
+
+pub fn run(program: &str, args: &[String]) -> i32 {
+    let opts = match parse_args(args) {
+        Some(opts) => opts,
+        None => return usage(program),
+    };
+
+    let roms = match collect_roms(&opts.dir) {
+        Ok(roms) => roms,
+        Err(e) => {
+            eprintln!("Error reading {}: {}", opts.dir.display(), e);
+            return 1;
+        }
+    };
+
+    let outcomes = run_parallel(&roms, &opts);
+
+    let mut failures = 0;
+    for (rom, outcome) in roms.iter().zip(&outcomes) {
+        let name = rom.file_name().unwrap_or_default().to_string_lossy();
+        match outcome {
+            Outcome::Unchecked(hash) => println!("----  {:016x}  {}", hash, name),
+            Outcome::Pass(hash) => println!("PASS  {:016x}  {}", hash, name),
+            Outcome::Fail { got, expected } => {
+                failures += 1;
+                println!("FAIL  {:016x}  {} (expected {:016x})", got, name, expected);
+            }
+            Outcome::AudioFail { hash, detail } => {
+                failures += 1;
+                println!("FAIL  {:016x}  {} (audio {})", hash, name, detail);
+            }
+            Outcome::Error(e) => {
+                failures += 1;
+                println!("FAIL  {:>16}  {} ({})", "error", name, e);
+            }
+            Outcome::Crashed => {
+                failures += 1;
+                println!("FAIL  {:>16}  {}", "crashed", name);
+            }
+        }
+    }
+
+    println!("\n{} ROMs, {} failed", roms.len(), failures);
+    if failures > 0 { 1 } else { 0 }
+}
@@ -0,0 +1,95 @@
+// Audio event timeline
+//
+// Records when the buzzer is on during a run as a list of tones, each with the
+// frame (timer tick) it started on and how many frames it lasted. Headless runs
+// compare it against an expected timeline so sound is regression tested too.
+//
+// Timeline files have one tone per line, `<start> <duration>`, with `#` comments.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Tone {
+    pub start: u64,
+    pub duration: u64,
+}
+
+#[derive(Default)]
+pub struct AudioTimeline {
+    frame: u64,
+    // Start of the tone that's still sounding
+    sounding_since: Option<u64>,
+    tones: Vec<Tone>,
+}
+
+impl AudioTimeline {
+    // Called once per timer tick, before the sound timer is decremented
+    pub fn record(&mut self, sounding: bool) {
+        match (sounding, self.sounding_since) {
+            (true, None) => self.sounding_since = Some(self.frame),
+            (false, Some(start)) => {
+                self.tones.push(Tone { start, duration: self.frame - start });
+                self.sounding_since = None;
+            }
+            _ => {}
+        }
+        self.frame += 1;
+    }
+
+    // Every tone so far, a tone still sounding is cut off at the current frame
+    pub fn tones(&self) -> Vec<Tone> {
+        let mut tones = self.tones.clone();
+        if let Some(start) = self.sounding_since {
+            tones.push(Tone { start, duration: self.frame - start });
+        }
+        tones
+    }
+}
+
+pub struct Timeline<'a>(pub &'a [Tone]);
+
+impl fmt::Display for Timeline<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "# start duration (frames)")?;
+        for tone in self.0 {
+            writeln!(f, "{} {}", tone.start, tone.duration)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn parse(text: &str) -> Result<Vec<Tone>, String> {
+    let mut tones = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let fields: Vec<u64> = line.split_whitespace().map(|f| f.parse()).collect::<Result<_, _>>()
+            .map_err(|_| format!("line {}: expected `<start> <duration>`", number + 1))?;
+        match fields.as_slice() {
+            &[start, duration] => tones.push(Tone { start, duration }),
+            _ => return Err(format!("line {}: expected `<start> <duration>`", number + 1)),
+        }
+    }
+    Ok(tones)
+}
+
+pub fn load(path: &Path) -> io::Result<Vec<Tone>> {
+    let text = fs::read_to_string(path)?;
+    parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// Describes the first difference between two timelines
+pub fn diff(expected: &[Tone], got: &[Tone]) -> Option<String> {
+    let show = |tone: Option<&Tone>| match tone {
+        Some(tone) => format!("{} frames at frame {}", tone.duration, tone.start),
+        None => "nothing".to_string(),
+    };
+    (0..expected.len().max(got.len()))
+        .find(|&i| expected.get(i) != got.get(i))
+        .map(|i| format!("tone {}: expected {}, got {}", i + 1, show(expected.get(i)), show(got.get(i))))
+}
@@ -0,0 +1,171 @@
+// Accurate timing from a cycle-cost table
+//
+// Instead of a fixed number of instructions per 60Hz frame, every frame gets a
+// budget of machine cycles and each instruction is charged the cost of its
+// opcode class. Overrunning the budget is paid back out of the next frame, as on
+// the real interpreters. Tables give a cost per class name, the opcode patterns
+// in decode::CLASSES, so timing models of different historical interpreters can
+// be tried; the chipeight binary reads them from TOML files. Without a table,
+// every instruction costs 1 and a frame is CYCLES_PER_FRAME instructions.
+//
+// `Timing::from_ips(n)` is the same model with every instruction costing 60 and a
+// frame n, so n instructions run a second however they fall on frames.
+
+use std::collections::HashMap;
+
+use crate::decode::{decode, CLASSES};
+use crate::error::Chip8Error;
+use crate::Chip8;
+
+// Instructions executed per 60Hz frame, between two timer ticks
+pub const CYCLES_PER_FRAME: u32 = 10;
+
+pub const FRAME_RATE: u32 = 60;
+
+#[derive(Clone)]
+pub struct Timing {
+    cycles_per_frame: i64,
+    default: i64,
+    costs: HashMap<&'static str, i64>,
+    // Budget left in the current frame, zero or less (an overrun) once it's done
+    budget: i64,
+}
+
+impl Default for Timing {
+    fn default() -> Timing {
+        Timing { cycles_per_frame: CYCLES_PER_FRAME as i64, default: 1, costs: HashMap::new(), budget: 0 }
+    }
+}
+
+impl Timing {
+    // A frame of `cycles_per_frame`, with `costs` by class name and `default`
+    // for the classes missing from it. A free instruction could run forever
+    // without using up a frame, so every cost has to be positive.
+    pub fn new(cycles_per_frame: u64, default: u64, costs: &HashMap<String, u64>) -> Result<Timing, String> {
+        if cycles_per_frame == 0 {
+            return Err("cycles_per_frame must be positive".to_string());
+        }
+        if default == 0 {
+            return Err("the default cost must be positive".to_string());
+        }
+        let mut table = HashMap::new();
+        for (class, &cost) in costs {
+            let class = CLASSES.iter()
+                .find(|c| c.eq_ignore_ascii_case(class))
+                .ok_or_else(|| format!("unknown opcode class `{}`", class))?;
+            if cost == 0 {
+                return Err(format!("the cost of `{}` must be positive", class));
+            }
+            table.insert(*class, cost as i64);
+        }
+
+        Ok(Timing { cycles_per_frame: cycles_per_frame as i64, default: default as i64, costs: table, budget: 0 })
+    }
+
+    // `ips` instructions a second, whatever they are
+    pub fn from_ips(ips: u32) -> Timing {
+        Timing { cycles_per_frame: ips as i64, default: FRAME_RATE as i64, costs: HashMap::new(), budget: 0 }
+    }
+
+    // Cost of the instruction about to execute
+    fn next_cost(&self, chip8: &Chip8) -> i64 {
+        let mask = chip8.address_mask();
+        let pc = chip8.pc & mask;
+        let opcode = ((chip8.memory[pc as usize] as u16) << 8) | chip8.memory[(pc.wrapping_add(1) & mask) as usize] as u16;
+        decode(opcode, chip8.is_hires()).class()
+            .and_then(|class| self.costs.get(class).copied())
+            .unwrap_or(self.default)
+    }
+
+    // Gives the next frame its budget, less any overrun of the last one
+    pub fn start_frame(&mut self) {
+        self.budget += self.cycles_per_frame;
+    }
+
+    // Whether the current frame has budget for another instruction
+    pub fn due(&self) -> bool {
+        self.budget > 0
+    }
+
+    // Executes the next instruction, charging it to the frame
+    pub fn tick(&mut self, chip8: &mut Chip8) -> Result<(), Chip8Error> {
+        self.budget -= self.next_cost(chip8);
+        chip8.tick()
+    }
+
+    // Executes one frame's worth of instructions, without ticking the timers
+    pub fn run_frame(&mut self, chip8: &mut Chip8) -> Result<(), Chip8Error> {
+        self.start_frame();
+        while self.due() {
+            self.tick(chip8)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_free_instructions() {
+        let costs = |class: &str, cost| HashMap::from([(class.to_string(), cost)]);
+        assert!(Timing::new(100, 1, &HashMap::new()).is_ok());
+        assert_eq!(Timing::new(0, 1, &HashMap::new()).err().unwrap(), "cycles_per_frame must be positive");
+        assert_eq!(Timing::new(100, 0, &HashMap::new()).err().unwrap(), "the default cost must be positive");
+        assert_eq!(Timing::new(100, 1, &costs("dxyn", 0)).err().unwrap(), "the cost of `DXYN` must be positive");
+        assert_eq!(Timing::new(100, 1, &costs("DXYZ", 5)).err().unwrap(), "unknown opcode class `DXYZ`");
+    }
+
+    // ADD V0, 1; JP 0x200
+    fn counter() -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        chip8
+    }
+
+    // Instructions run in each of `frames` frames
+    fn per_frame(timing: &mut Timing, chip8: &mut Chip8, frames: usize) -> Vec<u32> {
+        (0..frames).map(|_| {
+            let mut count = 0;
+            timing.start_frame();
+            while timing.due() {
+                timing.tick(chip8).unwrap();
+                count += 1;
+            }
+            count
+        }).collect()
+    }
+
+    #[test]
+    fn runs_a_fixed_number_of_instructions_by_default() {
+        let mut chip8 = counter();
+        assert_eq!(per_frame(&mut Timing::default(), &mut chip8, 3), [CYCLES_PER_FRAME; 3]);
+        assert_eq!(chip8.registers[0], 15);
+    }
+
+    #[test]
+    fn spreads_instructions_a_second_over_frames() {
+        let mut chip8 = counter();
+        let mut timing = Timing::from_ips(90);
+        assert_eq!(per_frame(&mut timing, &mut chip8, 4), [2, 1, 2, 1]);
+        let second: u32 = per_frame(&mut timing, &mut chip8, FRAME_RATE as usize).iter().sum();
+        assert_eq!(second, 90);
+    }
+
+    #[test]
+    fn charges_instructions_by_class_and_carries_overruns() {
+        let mut chip8 = counter();
+        let costs = HashMap::from([("7xkk".to_string(), 3)]);
+        // 3 + 1 + 3 overruns a frame of 5 by 2, leaving the next one 3
+        let mut timing = Timing::new(5, 1, &costs).unwrap();
+        assert_eq!(per_frame(&mut timing, &mut chip8, 1), [3]);
+        assert_eq!(chip8.registers[0], 2);
+        assert_eq!(per_frame(&mut timing, &mut chip8, 1), [2]);
+        assert_eq!(chip8.registers[0], 3);
+
+        let mut chip8 = counter();
+        Timing::new(8, 1, &costs).unwrap().run_frame(&mut chip8).unwrap();
+        assert_eq!(chip8.registers[0], 2);
+    }
+}
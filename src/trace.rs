@@ -0,0 +1,84 @@
+// Instruction tracing
+//
+// A tracer set with Chip8::set_tracer sees every instruction `tick` runs: where
+// it was, its opcode and what that decodes to, and the registers before and
+// after it. An instruction that fails is traced too, with nothing changed.
+// Clones of a machine share its tracer, as they do peripherals.
+//
+// An entry prints as the address, opcode, mnemonic and the registers it
+// changed:
+//
+//   0x21A  7A01  ADD VA, 0x01         VA 05->06
+//   0x21C  2300  CALL 0x300           SP 0->1
+
+use std::fmt;
+use std::sync::Arc;
+
+use crate::decode::Instruction;
+use crate::Chip8;
+
+// The registers an instruction can change, other than the PC
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Registers {
+    pub v: [u8; 16],
+    pub i: u16,
+    pub sp: u8,
+    pub dt: u8,
+    pub st: u8,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TraceEntry {
+    pub pc: u16,
+    pub opcode: u16,
+    pub instruction: Instruction,
+    pub before: Registers,
+    pub after: Registers,
+}
+
+pub trait Tracer: Send + Sync {
+    fn trace(&self, entry: &TraceEntry);
+}
+
+pub type SharedTracer = Arc<dyn Tracer>;
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (before, after) = (&self.before, &self.after);
+        let mut changes = String::new();
+        for (n, (old, new)) in before.v.iter().zip(&after.v).enumerate() {
+            if old != new {
+                changes += &format!(" V{:X} {:02X}->{:02X}", n, old, new);
+            }
+        }
+        if before.i != after.i {
+            changes += &format!(" I {:04X}->{:04X}", before.i, after.i);
+        }
+        if before.sp != after.sp {
+            changes += &format!(" SP {}->{}", before.sp, after.sp);
+        }
+        if before.dt != after.dt {
+            changes += &format!(" DT {:02X}->{:02X}", before.dt, after.dt);
+        }
+        if before.st != after.st {
+            changes += &format!(" ST {:02X}->{:02X}", before.st, after.st);
+        }
+
+        let mnemonic = self.instruction.to_string();
+        if changes.is_empty() {
+            write!(f, "0x{:03X}  {:04X}  {}", self.pc, self.opcode, mnemonic)
+        } else {
+            write!(f, "0x{:03X}  {:04X}  {:<20}{}", self.pc, self.opcode, mnemonic, changes)
+        }
+    }
+}
+
+impl Chip8 {
+    pub fn set_tracer(&mut self, tracer: Option<SharedTracer>) {
+        self.tracer = tracer;
+    }
+
+    pub(crate) fn trace_registers(&self) -> Registers {
+        Registers { v: self.registers, i: self.index, sp: self.sp, dt: self.delay_timer, st: self.sound_timer }
+    }
+}
@@ -0,0 +1,116 @@
+// `--trace`: log the instructions a ROM runs
+//
+// `--trace FILE` (`-` for stdout) writes a line for every instruction, see
+// chip8_core::trace for what's in it. `--trace-range START-END` keeps only
+// those at addresses in the range, in hex as in `--trace-range 300-3FF`.
+// `--trace-last N` keeps just the last N in memory and writes them out when
+// the ROM fails, to stderr unless --trace gives a file; a trace of a whole run
+// is long and usually only its end matters.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::RangeInclusive;
+use std::sync::Mutex;
+
+use chip8_core::trace::{TraceEntry, Tracer};
+
+struct State {
+    out: BufWriter<Box<dyn Write + Send>>,
+    // With --trace-last, the latest entries and how many to keep
+    ring: Option<(VecDeque<TraceEntry>, usize)>,
+}
+
+pub struct TraceLog {
+    range: Option<RangeInclusive<u16>>,
+    state: Mutex<State>,
+}
+
+// `START-END` in hex
+fn parse_range(spec: &str) -> Result<RangeInclusive<u16>, String> {
+    let parse = |addr: &str| u16::from_str_radix(addr.trim().trim_start_matches("0x"), 16);
+    match spec.split_once('-').map(|(start, end)| (parse(start), parse(end))) {
+        Some((Ok(start), Ok(end))) if start <= end => Ok(start..=end),
+        _ => Err(format!("expected START-END in hex, got `{}`", spec)),
+    }
+}
+
+impl TraceLog {
+    // The --trace, --trace-range and --trace-last arguments, any of them left out
+    pub fn open(path: Option<&str>, range: Option<&str>, last: Option<&str>) -> Result<TraceLog, String> {
+        let out: Box<dyn Write + Send> = match path {
+            Some("-") => Box::new(io::stdout()),
+            Some(path) => Box::new(File::create(path).map_err(|e| format!("{}: {}", path, e))?),
+            None => Box::new(io::stderr()),
+        };
+        let range = range.map(parse_range).transpose()?;
+        let ring = match last {
+            Some(n) => match n.parse::<usize>() {
+                Ok(n) if n > 0 => Some((VecDeque::with_capacity(n), n)),
+                _ => return Err(format!("--trace-last needs a positive integer, got `{}`", n)),
+            },
+            None => None,
+        };
+        Ok(TraceLog { range, state: Mutex::new(State { out: BufWriter::new(out), ring }) })
+    }
+
+    // Writes out the entries kept by --trace-last, after the ROM has failed
+    pub fn dump(&self) {
+        let mut state = self.state.lock().unwrap();
+        let State { out, ring } = &mut *state;
+        if let Some((entries, _)) = ring {
+            writeln!(out, "Last {} instructions:", entries.len()).ok();
+            for entry in entries.drain(..) {
+                writeln!(out, "{}", entry).ok();
+            }
+        }
+        out.flush().ok();
+    }
+
+    // Called before exiting, which skips destructors
+    pub fn flush(&self) {
+        self.state.lock().unwrap().out.flush().ok();
+    }
+}
+
+impl Tracer for TraceLog {
+    fn trace(&self, entry: &TraceEntry) {
+        if self.range.as_ref().is_some_and(|range| !range.contains(&entry.pc)) {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        match &mut state.ring {
+            Some((entries, capacity)) => {
+                if entries.len() == *capacity {
+                    entries.pop_front();
+                }
+                entries.push_back(*entry);
+            }
+            None => {
+                writeln!(state.out, "{}", entry).ok();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hex_ranges() {
+        assert_eq!(parse_range("300-3FF"), Ok(0x300..=0x3ff));
+        assert_eq!(parse_range("0x200 - 0x200"), Ok(0x200..=0x200));
+        assert_eq!(parse_range("0-FFFF"), Ok(0..=0xffff));
+        for bad in ["3FF-300", "300", "300-", "300-10000", "x-3FF"] {
+            assert_eq!(parse_range(bad), Err(format!("expected START-END in hex, got `{}`", bad)));
+        }
+    }
+
+    #[test]
+    fn rejects_bad_trace_last() {
+        assert!(TraceLog::open(None, None, Some("8")).is_ok());
+        assert_eq!(TraceLog::open(None, None, Some("0")).err().unwrap(), "--trace-last needs a positive integer, got `0`");
+        assert!(TraceLog::open(None, Some("300"), None).is_err());
+    }
+}
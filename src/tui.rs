@@ -0,0 +1,239 @@
+// Terminal frontend
+//
+// chipeight-tui plays a ROM in a terminal, for SSH sessions and machines without
+// SDL2; it's built with `cargo build --no-default-features --features tui`. Each
+// character cell shows two pixels, one above the other, so the 64x32 display
+// takes 64x16 cells and SCHIP's 128x64 one 128x32.
+//
+// The keypad is on the left of a QWERTY keyboard, as in the window:
+//
+//   1 2 3 C      1 2 3 4
+//   4 5 6 D  ->  Q W E R
+//   7 8 9 E      A S D F
+//   A 0 B F      Z X C V
+//
+// Escape or Ctrl-C quits. Most terminals only report presses, not releases, so
+// a key stays down for HOLD_FRAMES after its last press and the terminal's key
+// repeat keeps it down while held. Terminals that report releases (the kitty
+// keyboard protocol) release it exactly. The buzzer rings the terminal bell.
+
+use std::env;
+use std::fs;
+use std::io::{self, Stdout, Write};
+use std::process;
+use std::time::Duration;
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags};
+use crossterm::event::{PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags};
+use crossterm::style::{Color, Print, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{cursor, execute, queue};
+
+use chip8_core::palette;
+use chip8_core::quirks::Quirks;
+use chip8_core::renderer::{self, Renderer};
+use chip8_core::Chip8;
+
+const DEFAULT_IPS: u32 = 600;
+// Frames a key stays down after a press on terminals without releases, longer
+// than the delay before key repeat starts
+const HOLD_FRAMES: u32 = 30;
+
+const KEYS: [(char, u8); 16] = [
+    ('x', 0x0), ('1', 0x1), ('2', 0x2), ('3', 0x3),
+    ('q', 0x4), ('w', 0x5), ('e', 0x6), ('a', 0x7),
+    ('s', 0x8), ('d', 0x9), ('z', 0xA), ('c', 0xB),
+    ('4', 0xC), ('r', 0xD), ('f', 0xE), ('v', 0xF),
+];
+
+fn color(argb: u32) -> Color {
+    Color::Rgb { r: (argb >> 16) as u8, g: (argb >> 8) as u8, b: argb as u8 }
+}
+
+struct Terminal {
+    out: Stdout,
+    // Whether key releases are reported
+    releases: bool,
+    // Frames each keypad key stays down for, u32::MAX until released
+    held: [u32; 16],
+    // What's on screen, to skip redrawing an unchanged display
+    shown: Vec<u32>,
+    beeping: bool,
+}
+
+impl Terminal {
+    fn open() -> io::Result<Terminal> {
+        let mut out = io::stdout();
+        terminal::enable_raw_mode()?;
+        execute!(out, EnterAlternateScreen, cursor::Hide)?;
+        let releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if releases {
+            execute!(out, PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES))?;
+        }
+        Ok(Terminal { out, releases, held: [0; 16], shown: Vec::new(), beeping: false })
+    }
+
+    fn key(&mut self, key: KeyEvent) -> bool {
+        let quit = key.code == KeyCode::Esc
+            || (key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL));
+        if quit {
+            return false;
+        }
+        if let KeyCode::Char(c) = key.code {
+            if let Some(&(_, k)) = KEYS.iter().find(|&&(ch, _)| ch == c.to_ascii_lowercase()) {
+                self.held[k as usize] = match key.kind {
+                    KeyEventKind::Release => 0,
+                    _ if self.releases => u32::MAX,
+                    _ => HOLD_FRAMES,
+                };
+            }
+        }
+        true
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        if self.releases {
+            execute!(self.out, PopKeyboardEnhancementFlags).ok();
+        }
+        execute!(self.out, SetBackgroundColor(Color::Reset), cursor::Show, LeaveAlternateScreen).ok();
+        terminal::disable_raw_mode().ok();
+    }
+}
+
+impl Renderer for Terminal {
+    fn update(&mut self, video: &[u32], width: u32, height: u32) -> Result<(), String> {
+        if self.shown == video {
+            return Ok(());
+        }
+        self.shown = video.to_vec();
+
+        // The upper half block in the foreground colour over the background
+        // colour draws the pixel above over the one below
+        let width = width as usize;
+        let mut colors = None;
+        let mut draw = || -> io::Result<()> {
+            for row in 0..height as usize / 2 {
+                queue!(self.out, cursor::MoveTo(0, row as u16))?;
+                for x in 0..width {
+                    let top = palette::DEFAULT[video[2 * row * width + x] as usize & 3];
+                    let bottom = palette::DEFAULT[video[(2 * row + 1) * width + x] as usize & 3];
+                    if colors != Some((top, bottom)) {
+                        colors = Some((top, bottom));
+                        queue!(self.out, SetForegroundColor(color(top)), SetBackgroundColor(color(bottom)))?;
+                    }
+                    queue!(self.out, Print('\u{2580}'))?;
+                }
+            }
+            self.out.flush()
+        };
+        draw().map_err(|e| e.to_string())
+    }
+
+    fn poll_input(&mut self, keypad: &mut [u8; 16]) -> Result<bool, String> {
+        while event::poll(Duration::ZERO).map_err(|e| e.to_string())? {
+            match event::read().map_err(|e| e.to_string())? {
+                Event::Key(key) if !self.key(key) => return Ok(false),
+                // Everything gets drawn again at the new size
+                Event::Resize(..) => {
+                    self.shown.clear();
+                    execute!(self.out, terminal::Clear(terminal::ClearType::All)).map_err(|e| e.to_string())?;
+                }
+                _ => {}
+            }
+        }
+        for (down, frames) in keypad.iter_mut().zip(self.held.iter_mut()) {
+            *down = (*frames > 0) as u8;
+            if *frames != u32::MAX {
+                *frames = frames.saturating_sub(1);
+            }
+        }
+        Ok(true)
+    }
+
+    fn beep(&mut self, on: bool) {
+        if on && !self.beeping {
+            execute!(self.out, Print('\x07')).ok();
+        }
+        self.beeping = on;
+    }
+}
+
+fn usage(program: &str) -> ! {
+    eprintln!("Usage: {} [options] <ROM>\n", program);
+    eprintln!("Options:");
+    eprintln!("  --schip, --xochip   run as SUPER-CHIP or XO-CHIP rather than plain CHIP-8");
+    eprintln!("  --quirks SPEC       instruction quirks: chip8, schip, xochip or quirk names, as in `chip8,-vf_reset`");
+    eprintln!("  --ips N             instructions a second (default 600)");
+    eprintln!("  --seed N            seed for RND, so runs can be repeated");
+    process::exit(1);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let mut rom_file: Option<&String> = None;
+    let mut schip = false;
+    let mut xochip = false;
+    let mut quirks: Option<Quirks> = None;
+    let mut ips = DEFAULT_IPS;
+    let mut seed = rand::random::<u32>() as u64;
+
+    let mut iter = args[1..].iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--schip" => schip = true,
+            "--xochip" => xochip = true,
+            "--quirks" => {
+                let spec = iter.next().unwrap_or_else(|| usage(&args[0]));
+                quirks = Some(Quirks::parse(spec).unwrap_or_else(|e| {
+                    eprintln!("Bad --quirks: {}", e);
+                    process::exit(1);
+                }));
+            }
+            "--ips" => {
+                let n = iter.next().unwrap_or_else(|| usage(&args[0]));
+                ips = n.parse().ok().filter(|&n| n > 0).unwrap_or_else(|| {
+                    eprintln!("--ips needs a positive integer");
+                    process::exit(1);
+                });
+            }
+            "--seed" => {
+                let n = iter.next().unwrap_or_else(|| usage(&args[0]));
+                seed = n.parse().unwrap_or_else(|_| {
+                    eprintln!("This argument is not integer!");
+                    process::exit(1);
+                });
+            }
+            flag if flag.starts_with("--") => {
+                eprintln!("Unknown option {}", flag);
+                usage(&args[0]);
+            }
+            _ if rom_file.is_none() => rom_file = Some(arg),
+            _ => usage(&args[0]),
+        }
+    }
+    let rom_file = rom_file.unwrap_or_else(|| usage(&args[0]));
+    let rom = fs::read(rom_file).unwrap_or_else(|e| {
+        eprintln!("Error reading {}: {}", rom_file, e);
+        process::exit(1);
+    });
+
+    let mut chip8 = Chip8::new();
+    chip8.set_schip(schip);
+    chip8.set_xochip(xochip);
+    chip8.set_quirks(quirks.unwrap_or_else(|| Quirks::for_mode(schip, xochip)));
+    chip8.seed(seed);
+    if let Err(e) = chip8.load_rom(&rom) {
+        eprintln!("Error loading {}: {}", rom_file, e);
+        process::exit(1);
+    }
+
+    let result = Terminal::open()
+        .map_err(|e| e.to_string())
+        .and_then(|mut terminal| renderer::run(&mut chip8, &mut terminal, (ips / 60).max(1)));
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
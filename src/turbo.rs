@@ -0,0 +1,93 @@
+// Turbo keys
+//
+// A turbo key presses and releases a keypad key over and over while it's held,
+// for games that want the key mashed. They're bound in the config file or with
+// `--turbo KEY=K`, and repeat `turbo_rate` (`--turbo-rate`) times a second:
+//
+//   turbo_rate = 10
+//
+//   [turbo]
+//   Space = "5"
+
+use std::collections::HashMap;
+use std::time::Instant;
+
+use sdl2::keyboard::Keycode;
+
+pub const DEFAULT_RATE: u32 = 10;
+
+#[derive(Clone)]
+pub struct Turbo {
+    // Host key to keypad key
+    bindings: Vec<(Keycode, u8)>,
+    // Presses a second
+    pub rate: u32,
+    // When each held turbo key went down, so the first press is immediate
+    held_since: HashMap<Keycode, Instant>,
+}
+
+impl Default for Turbo {
+    fn default() -> Turbo {
+        Turbo { bindings: Vec::new(), rate: DEFAULT_RATE, held_since: HashMap::new() }
+    }
+}
+
+impl Turbo {
+    // `KEY = "K"` pairs as the config file's [turbo] table has them
+    pub fn from_config(bindings: &HashMap<String, String>) -> Result<Turbo, String> {
+        let mut turbo = Turbo::default();
+        for (name, key) in bindings {
+            turbo.bind(name, key)?;
+        }
+        Ok(turbo)
+    }
+
+    // Binds the host key called `name` to keypad key `key` (a hex digit)
+    pub fn bind(&mut self, name: &str, key: &str) -> Result<(), String> {
+        let keycode = Keycode::from_name(name).ok_or_else(|| format!("unknown key `{}`", name))?;
+        let key = u8::from_str_radix(key, 16).ok().filter(|&k| k < 16)
+            .ok_or_else(|| format!("`{}` is not a keypad key (0-F)", key))?;
+        self.bindings.retain(|&(k, _)| k != keycode);
+        self.bindings.push((keycode, key));
+        Ok(())
+    }
+
+    // The bindings, in the form from_config reads
+    pub fn to_config(&self) -> HashMap<String, String> {
+        self.bindings.iter().map(|&(keycode, key)| (keycode.name(), format!("{:X}", key))).collect()
+    }
+
+    pub fn bindings(&self) -> &[(Keycode, u8)] {
+        &self.bindings
+    }
+
+    pub fn is_turbo(&self, keycode: Keycode) -> bool {
+        self.bindings.iter().any(|&(k, _)| k == keycode)
+    }
+
+    pub fn press(&mut self, keycode: Keycode) {
+        if self.is_turbo(keycode) {
+            self.held_since.entry(keycode).or_insert_with(Instant::now);
+        }
+    }
+
+    pub fn release(&mut self, keycode: Keycode) {
+        self.held_since.remove(&keycode);
+    }
+
+    pub fn release_all(&mut self) {
+        self.held_since.clear();
+    }
+
+    // Presses the keypad keys of held turbo keys during the first half of each repeat
+    pub fn apply(&self, keys: &mut [u8; 16]) {
+        for (&keycode, since) in &self.held_since {
+            let half_periods = since.elapsed().as_millis() * 2 * self.rate.max(1) as u128 / 1000;
+            if half_periods.is_multiple_of(2) {
+                if let Some(&(_, key)) = self.bindings.iter().find(|&&(k, _)| k == keycode) {
+                    keys[key as usize] = 1;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,321 @@
+// Watch expressions
+//
+// `--watch EXPR` (or `watch = [...]` in the config file) pins an expression to
+// an overlay in the top left corner that's evaluated again every frame, for
+// keeping an eye on a game's state without the debugger. Expressions are made of
+//
+//   V0-VF, I, PC, SP, DT, ST   registers
+//   2A4, 0x2A4, $2A4           hex numbers, as in the debugger
+//   score                      labels from the symbol file
+//   [EXPR]                     the memory byte at an address
+//   + - * / % & | ^ << >> ( )  arithmetic, with the usual precedence
+//
+// and may be given a name to show instead, as in `lives=[2F0]`. The watch
+// hotkey (F8) hides and shows the overlay.
+
+use crate::debugger::Symbols;
+use chip8_core::Chip8;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Register {
+    V(u8),
+    I,
+    Pc,
+    Sp,
+    Dt,
+    St,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    And,
+    Or,
+    Xor,
+    Shl,
+    Shr,
+}
+
+impl Op {
+    // Higher binds tighter, as in C
+    fn precedence(self) -> u8 {
+        match self {
+            Op::Or => 1,
+            Op::Xor => 2,
+            Op::And => 3,
+            Op::Shl | Op::Shr => 4,
+            Op::Add | Op::Sub => 5,
+            Op::Mul | Op::Div | Op::Rem => 6,
+        }
+    }
+
+    fn apply(self, a: i64, b: i64) -> Option<i64> {
+        Some(match self {
+            Op::Add => a.wrapping_add(b),
+            Op::Sub => a.wrapping_sub(b),
+            Op::Mul => a.wrapping_mul(b),
+            Op::Div => a.checked_div(b)?,
+            Op::Rem => a.checked_rem(b)?,
+            Op::And => a & b,
+            Op::Or => a | b,
+            Op::Xor => a ^ b,
+            Op::Shl => a.checked_shl(u32::try_from(b).ok()?)?,
+            Op::Shr => a.checked_shr(u32::try_from(b).ok()?)?,
+        })
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Expr {
+    Number(i64),
+    Register(Register),
+    Memory(Box<Expr>),
+    Negate(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    // None when dividing by zero or shifting too far
+    fn eval(&self, chip8: &Chip8) -> Option<i64> {
+        Some(match self {
+            Expr::Number(n) => *n,
+            Expr::Register(register) => match register {
+                Register::V(x) => chip8.registers[*x as usize] as i64,
+                Register::I => chip8.index as i64,
+                Register::Pc => chip8.pc as i64,
+                Register::Sp => chip8.sp as i64,
+                Register::Dt => chip8.delay_timer as i64,
+                Register::St => chip8.sound_timer as i64,
+            },
+            Expr::Memory(addr) => chip8.memory[(addr.eval(chip8)? as u16 & chip8.address_mask()) as usize] as i64,
+            Expr::Negate(e) => e.eval(chip8)?.wrapping_neg(),
+            Expr::Binary(op, a, b) => op.apply(a.eval(chip8)?, b.eval(chip8)?)?,
+        })
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Token {
+    Word(String),
+    Op(Op),
+    Minus,
+    Open,
+    Close,
+    OpenBracket,
+    CloseBracket,
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        let token = match c {
+            _ if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            _ if c.is_ascii_alphanumeric() || c == '_' || c == '$' => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek().filter(|c| c.is_ascii_alphanumeric() || **c == '_' || **c == '$') {
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+                continue;
+            }
+            '+' => Token::Op(Op::Add),
+            '-' => Token::Minus,
+            '*' => Token::Op(Op::Mul),
+            '/' => Token::Op(Op::Div),
+            '%' => Token::Op(Op::Rem),
+            '&' => Token::Op(Op::And),
+            '|' => Token::Op(Op::Or),
+            '^' => Token::Op(Op::Xor),
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '[' => Token::OpenBracket,
+            ']' => Token::CloseBracket,
+            '<' | '>' => {
+                chars.next();
+                if chars.peek() != Some(&c) {
+                    return Err(format!("expected `{}{}`", c, c));
+                }
+                if c == '<' { Token::Op(Op::Shl) } else { Token::Op(Op::Shr) }
+            }
+            _ => return Err(format!("unexpected `{}`", c)),
+        };
+        chars.next();
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+fn register(word: &str) -> Option<Register> {
+    let word = word.to_ascii_uppercase();
+    match word.as_str() {
+        "I" => Some(Register::I),
+        "PC" => Some(Register::Pc),
+        "SP" => Some(Register::Sp),
+        "DT" => Some(Register::Dt),
+        "ST" => Some(Register::St),
+        _ => {
+            let x = word.strip_prefix('V')?;
+            (x.len() == 1).then(|| u8::from_str_radix(x, 16).ok()).flatten().map(Register::V)
+        }
+    }
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    at: usize,
+    symbols: &'a Symbols,
+}
+
+impl Parser<'_> {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.at).cloned();
+        self.at += 1;
+        token
+    }
+
+    fn peek_op(&self) -> Option<Op> {
+        match self.tokens.get(self.at)? {
+            Token::Op(op) => Some(*op),
+            Token::Minus => Some(Op::Sub),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, token: Token, what: &str) -> Result<(), String> {
+        match self.next() {
+            Some(t) if t == token => Ok(()),
+            _ => Err(format!("expected `{}`", what)),
+        }
+    }
+
+    // Binary operators binding at least as tightly as `min`
+    fn expr(&mut self, min: u8) -> Result<Expr, String> {
+        let mut left = self.operand()?;
+        while let Some(op) = self.peek_op().filter(|op| op.precedence() >= min) {
+            self.at += 1;
+            let right = self.expr(op.precedence() + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn operand(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Minus) => Ok(Expr::Negate(Box::new(self.operand()?))),
+            Some(Token::Open) => {
+                let e = self.expr(0)?;
+                self.expect(Token::Close, ")")?;
+                Ok(e)
+            }
+            Some(Token::OpenBracket) => {
+                let e = self.expr(0)?;
+                self.expect(Token::CloseBracket, "]")?;
+                Ok(Expr::Memory(Box::new(e)))
+            }
+            Some(Token::Word(word)) => match register(&word) {
+                Some(register) => Ok(Expr::Register(register)),
+                None => self.symbols.resolve(&word)
+                    .map(|n| Expr::Number(n as i64))
+                    .ok_or_else(|| format!("`{}` is not a register, label or hex number", word)),
+            },
+            Some(_) => Err("expected a value".to_string()),
+            None => Err("unexpected end".to_string()),
+        }
+    }
+}
+
+pub struct Watch {
+    name: String,
+    expr: Expr,
+}
+
+impl Watch {
+    // `EXPR` or `NAME=EXPR`, labels resolved against `symbols`
+    pub fn parse(text: &str, symbols: &Symbols) -> Result<Watch, String> {
+        let (name, source) = match text.split_once('=') {
+            Some((name, source)) => (name.trim(), source),
+            None => (text.trim(), text),
+        };
+        let mut parser = Parser { tokens: tokenize(source)?, at: 0, symbols };
+        let expr = parser.expr(0)?;
+        if parser.at < parser.tokens.len() {
+            return Err("unexpected input after the expression".to_string());
+        }
+        Ok(Watch { name: name.to_string(), expr })
+    }
+
+    // `NAME = HEX (DECIMAL)` as the overlay shows it
+    pub fn show(&self, chip8: &Chip8) -> String {
+        match self.expr.eval(chip8) {
+            Some(value) if value < 0 => format!("{} = -{:02X} ({})", self.name, value.unsigned_abs(), value),
+            Some(value) => format!("{} = {:02X} ({})", self.name, value, value),
+            None => format!("{} = ?", self.name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_memory_the_mode_addresses() {
+        let watch = Watch::parse("hi=[A234]", &Symbols::default()).unwrap();
+        let mut chip8 = Chip8::new();
+        chip8.set_xochip(true);
+        chip8.memory[0xA234] = 0x42;
+        chip8.memory[0x0234] = 0x17;
+        assert_eq!(watch.show(&chip8), "hi = 42 (66)");
+
+        // Other modes wrap at 4KB
+        chip8.set_xochip(false);
+        assert_eq!(watch.show(&chip8), "hi = 17 (23)");
+    }
+
+    fn show(text: &str, chip8: &Chip8) -> String {
+        Watch::parse(text, &Symbols::default()).unwrap().show(chip8)
+    }
+
+    #[test]
+    fn evaluates_with_c_precedence() {
+        let mut chip8 = Chip8::new();
+        chip8.registers[0xA] = 3;
+        chip8.index = 0x300;
+        chip8.memory[0x302] = 9;
+        assert_eq!(show("1 + 2 * 3", &chip8), "1 + 2 * 3 = 07 (7)");
+        assert_eq!(show("(1 + 2) * 3", &chip8), "(1 + 2) * 3 = 09 (9)");
+        assert_eq!(show("1 | 2 << 4 & F0", &chip8), "1 | 2 << 4 & F0 = 21 (33)");
+        assert_eq!(show("x=[i + 2] - va", &chip8), "x = 06 (6)");
+        assert_eq!(show("pc", &chip8), "pc = 200 (512)");
+        assert_eq!(show("-VA", &chip8), "-VA = -03 (-3)");
+        assert_eq!(show("$10 - 0x1", &chip8), "$10 - 0x1 = 0F (15)");
+    }
+
+    #[test]
+    fn shows_what_cant_be_evaluated() {
+        let chip8 = Chip8::new();
+        assert_eq!(show("n=1 / v0", &chip8), "n = ?");
+        assert_eq!(show("n=1 << 80", &chip8), "n = ?");
+    }
+
+    #[test]
+    fn rejects_malformed_expressions() {
+        let parse = |text| Watch::parse(text, &Symbols::default()).err().unwrap();
+        assert_eq!(parse("(1 + 2"), "expected `)`");
+        assert_eq!(parse("[2A4"), "expected `]`");
+        assert_eq!(parse("1 +"), "unexpected end");
+        assert_eq!(parse("1 2"), "unexpected input after the expression");
+        assert_eq!(parse("1 < 2"), "expected `<<`");
+        assert_eq!(parse("VG"), "`VG` is not a register, label or hex number");
+        assert_eq!(parse("1 # 2"), "unexpected `#`");
+    }
+}
@@ -0,0 +1,109 @@
+// XO-CHIP extensions
+//
+// XO-CHIP builds on SCHIP, so its mode turns the SCHIP instructions on too.
+// These are only decoded in XO-CHIP mode (--xochip, or detected from the ROM):
+//
+//   00DN       scroll up N lines          5XY2  save VX..VY at I
+//   5XY3       load VX..VY from I         FN01  draw on planes N
+//   F000 NNNN  point I at NNNN            F002  load the audio pattern at I
+//   FX3A       set the pattern's pitch to VX
+//
+// Memory grows to 64KB, all of it reachable through I. F000 is four bytes
+// long, so the skip instructions step over it whole. There are two display
+// planes: drawing, clearing and scrolling affect the selected ones, and each
+// pixel of the framebuffer holds the planes lit in it for the frontend to pick
+// one of four colours by. Once F002 has run, the buzzer plays the 128-bit
+// pattern instead of a plain tone.
+
+use crate::Chip8;
+
+impl Chip8 {
+    // Turns the XO-CHIP instructions, and with them SCHIP's, on or off
+    pub fn set_xochip(&mut self, enabled: bool) {
+        self.xochip = enabled;
+        if enabled {
+            self.set_schip(true);
+        }
+    }
+
+    pub fn is_xochip(&self) -> bool {
+        self.xochip
+    }
+
+    // The audio pattern, 128 one-bit samples, and their playback rate in
+    // samples a second, once a ROM has loaded one
+    pub fn audio_pattern(&self) -> Option<(&[u8; 16], f32)> {
+        let rate = 4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0);
+        self.pattern_loaded.then_some((&self.pattern, rate))
+    }
+
+    // Addresses wrap at 64KB in XO-CHIP and at 4KB otherwise
+    pub fn address_mask(&self) -> u16 {
+        if self.xochip { 0xFFFF } else { 0x0FFF }
+    }
+
+    // Steps over the next instruction, all four bytes of an F000
+    pub(crate) fn skip(&mut self) {
+        let mask = self.address_mask();
+        let pc = self.pc & mask;
+        let long = self.xochip && self.memory[pc as usize] == 0xF0 && self.memory[(pc.wrapping_add(1) & mask) as usize] == 0x00;
+        self.pc = pc.wrapping_add(if long { 4 } else { 2 });
+    }
+
+    // The registers from Vx to Vy, in whichever direction they go
+    fn register_range(&self) -> impl Iterator<Item = usize> {
+        let vx = ((self.opcode & 0x0F00) >> 8) as usize;
+        let vy = ((self.opcode & 0x00F0) >> 4) as usize;
+        let count = vx.abs_diff(vy) + 1;
+        (0..count).map(move |i| if vx <= vy { vx + i } else { vx - i })
+    }
+
+    // 00DN - SCU nibble: Scroll display up N lines
+    pub(crate) fn op_00dn(&mut self) {
+        let lines = (self.opcode & 0x000F) as i32;
+
+        self.scroll(0, -lines);
+    }
+
+    // 5xy2 - SAVE Vx - Vy: Store Vx through Vy in memory starting at location I, I unchanged
+    pub(crate) fn op_5xy2(&mut self) {
+        for (i, register) in self.register_range().enumerate() {
+            self.write(self.index.wrapping_add(i as u16), self.registers[register]);
+        }
+    }
+
+    // 5xy3 - LOAD Vx - Vy: Read Vx through Vy from memory starting at location I, I unchanged
+    pub(crate) fn op_5xy3(&mut self) {
+        for (i, register) in self.register_range().enumerate() {
+            self.registers[register] = self.read(self.index.wrapping_add(i as u16));
+        }
+    }
+
+    // F000 nnnn - LD I, long nnnn: Set I = the 16-bit address in the next two bytes
+    pub(crate) fn op_f000(&mut self) {
+        let mask = self.address_mask();
+        let pc = self.pc & mask;
+        self.index = ((self.memory[pc as usize] as u16) << 8) | self.memory[(pc.wrapping_add(1) & mask) as usize] as u16;
+        self.pc = pc.wrapping_add(2);
+    }
+
+    // Fn01 - PLANE n: Select the planes drawing, clearing and scrolling act on
+    pub(crate) fn op_fn01(&mut self) {
+        self.planes = ((self.opcode & 0x0F00) >> 8) as u8 & 0x3;
+    }
+
+    // F002 - AUDIO: Load the 16-byte audio pattern from memory starting at location I
+    pub(crate) fn op_f002(&mut self) {
+        for i in 0..16 {
+            self.pattern[i] = self.read(self.index.wrapping_add(i as u16));
+        }
+        self.pattern_loaded = true;
+    }
+
+    // Fx3A - PITCH Vx: Set the audio pattern's pitch to Vx
+    pub(crate) fn op_fx3a(&mut self) {
+        let vx = ((self.opcode & 0x0F00) >> 8) as usize;
+
+        self.pitch = self.registers[vx];
+    }
+}